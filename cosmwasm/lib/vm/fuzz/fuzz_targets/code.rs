@@ -0,0 +1,10 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use cosmwasm_vm::fuzzing::{check_code, FuzzOutcome};
+
+fuzz_target!(|data: &[u8]| {
+    if let FuzzOutcome::Panicked(msg) = check_code(data) {
+        panic!("{}", msg);
+    }
+});
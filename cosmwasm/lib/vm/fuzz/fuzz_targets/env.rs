@@ -0,0 +1,10 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use cosmwasm_vm::fuzzing::{parse_env, FuzzOutcome};
+
+fuzz_target!(|data: &[u8]| {
+    if let FuzzOutcome::Panicked(msg) = parse_env(data) {
+        panic!("{}", msg);
+    }
+});
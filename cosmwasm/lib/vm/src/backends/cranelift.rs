@@ -1,13 +1,20 @@
 #![cfg(any(feature = "cranelift", feature = "default-cranelift"))]
 use wasmer_clif_backend::CraneliftCompiler;
-use wasmer_runtime_core::{backend::Compiler, compile_with, instance::Instance, module::Module};
+use wasmer_runtime_core::{
+    backend::Compiler, compile_with, error::RuntimeError, instance::Instance, module::Module,
+    vm::Ctx,
+};
 
+#[cfg(feature = "call-depth")]
+use crate::call_depth;
 use crate::errors::{CompileErr, Error};
 use snafu::ResultExt;
 
 static FAKE_GAS_AVAILABLE: u64 = 1_000_000;
 
 pub fn compile(code: &[u8]) -> Result<Module, Error> {
+    #[cfg(feature = "call-depth")]
+    let code = &call_depth::instrument(code)?;
     compile_with(code, compiler().as_ref()).context(CompileErr {})
 }
 
@@ -24,3 +31,14 @@ pub fn set_gas(_instance: &mut Instance, _limit: u64) {}
 pub fn get_gas(_instance: &Instance) -> u64 {
     FAKE_GAS_AVAILABLE
 }
+
+pub fn get_gas_left(_ctx: &Ctx) -> u64 {
+    FAKE_GAS_AVAILABLE
+}
+
+pub fn decrease_gas_left(_ctx: &mut Ctx, _amount: u64) {}
+
+/// Without metering a call never runs out of gas
+pub fn is_gas_depletion(_err: &RuntimeError) -> bool {
+    false
+}
@@ -16,7 +16,11 @@ pub fn compiler_for_backend(backend: &str) -> Option<Box<dyn Compiler>> {
 }
 
 #[cfg(feature = "default-cranelift")]
-pub use cranelift::{backend, compile, get_gas, set_gas};
+pub use cranelift::{
+    backend, compile, decrease_gas_left, get_gas, get_gas_left, is_gas_depletion, set_gas,
+};
 
 #[cfg(feature = "default-singlepass")]
-pub use singlepass::{backend, compile, get_gas, set_gas};
+pub use singlepass::{
+    backend, compile, decrease_gas_left, get_gas, get_gas_left, is_gas_depletion, set_gas,
+};
@@ -4,11 +4,15 @@ use wasmer_runtime_core::{
     backend::Compiler,
     codegen::{MiddlewareChain, StreamingCompiler},
     compile_with,
+    error::RuntimeError,
     instance::Instance,
     module::Module,
+    vm::Ctx,
 };
 use wasmer_singlepass_backend::ModuleCodeGenerator as SinglePassMCG;
 
+#[cfg(feature = "call-depth")]
+use crate::call_depth;
 use crate::errors::{CompileErr, Error};
 use crate::middleware::DeterministicMiddleware;
 use snafu::ResultExt;
@@ -16,6 +20,8 @@ use snafu::ResultExt;
 static GAS_LIMIT: u64 = 10_000_000_000;
 
 pub fn compile(code: &[u8]) -> Result<Module, Error> {
+    #[cfg(feature = "call-depth")]
+    let code = &call_depth::instrument(code)?;
     compile_with(code, compiler().as_ref()).context(CompileErr {})
 }
 
@@ -51,3 +57,19 @@ pub fn get_gas(instance: &Instance) -> u64 {
         GAS_LIMIT - used
     }
 }
+
+/// Same as get_gas, for use in host functions
+pub fn get_gas_left(ctx: &Ctx) -> u64 {
+    GAS_LIMIT.saturating_sub(metering::get_points_used_ctx(ctx))
+}
+
+/// Deducts gas charged by host functions from the gas left
+pub fn decrease_gas_left(ctx: &mut Ctx, amount: u64) {
+    let used = metering::get_points_used_ctx(ctx);
+    metering::set_points_used_ctx(ctx, used.saturating_add(amount));
+}
+
+/// Whether a call failed because the metering middleware stopped it when the gas ran out
+pub fn is_gas_depletion(err: &RuntimeError) -> bool {
+    err.0.is::<metering::ExecutionLimitExceededError>()
+}
@@ -0,0 +1,116 @@
+//! Harnesses measuring the call throughput of the vm, to catch performance regressions
+//! before a release. Combine them with testing::mock_instance and the test contracts.
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use cosmwasm::traits::{Api, Storage};
+use cosmwasm::types::Env;
+
+use crate::calls::{call_handle, call_init, call_query};
+use crate::errors::Error;
+use crate::instance::Instance;
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct BenchReport {
+    pub calls: u32,
+    pub duration: Duration,
+    /// used_internally of all calls
+    pub gas_used: u64,
+    /// calls of every import, summed over all calls
+    pub import_calls: BTreeMap<String, u32>,
+}
+
+impl BenchReport {
+    pub fn calls_per_second(&self) -> f64 {
+        let secs = self.duration.as_secs_f64();
+        if secs == 0.0 {
+            return 0.0;
+        }
+        f64::from(self.calls) / secs
+    }
+}
+
+/// bench runs call iterations times and sums up the reports of all calls.
+/// Errors of the call abort the benchmark, contract errors do not.
+pub fn bench<S, A, F>(
+    instance: &mut Instance<S, A>,
+    iterations: u32,
+    mut call: F,
+) -> Result<BenchReport, Error>
+where
+    S: Storage + 'static,
+    A: Api + 'static,
+    F: FnMut(&mut Instance<S, A>) -> Result<(), Error>,
+{
+    let mut report = BenchReport::default();
+    let start = Instant::now();
+    for _ in 0..iterations {
+        call(instance)?;
+        report.calls += 1;
+        report.gas_used += instance.gas_report().used_internally;
+        for (name, count) in instance.resource_report().import_calls.iter() {
+            *report.import_calls.entry(name.clone()).or_insert(0) += count;
+        }
+    }
+    report.duration = start.elapsed();
+    Ok(report)
+}
+
+pub fn bench_init<S: Storage + 'static, A: Api + 'static>(
+    instance: &mut Instance<S, A>,
+    env: &Env,
+    msg: &[u8],
+    iterations: u32,
+) -> Result<BenchReport, Error> {
+    bench(instance, iterations, |instance| {
+        call_init(instance, env, msg).map(|_| ())
+    })
+}
+
+pub fn bench_handle<S: Storage + 'static, A: Api + 'static>(
+    instance: &mut Instance<S, A>,
+    env: &Env,
+    msg: &[u8],
+    iterations: u32,
+) -> Result<BenchReport, Error> {
+    bench(instance, iterations, |instance| {
+        call_handle(instance, env, msg).map(|_| ())
+    })
+}
+
+pub fn bench_query<S: Storage + 'static, A: Api + 'static>(
+    instance: &mut Instance<S, A>,
+    msg: &[u8],
+    iterations: u32,
+) -> Result<BenchReport, Error> {
+    bench(instance, iterations, |instance| {
+        call_query(instance, msg).map(|_| ())
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::mock_instance;
+    use cosmwasm::mock::mock_env;
+    use cosmwasm::types::coin;
+
+    static CONTRACT_0_7: &[u8] = include_bytes!("../testdata/contract_0.7.wasm");
+
+    #[test]
+    fn bench_sums_up_calls() {
+        let mut instance = mock_instance(CONTRACT_0_7);
+        let env = mock_env(&instance.api, "creator", &coin("1000", "earth"), &[]);
+        let msg = br#"{"verifier": "verifies", "beneficiary": "benefits"}"#;
+
+        let report = bench_init(&mut instance, &env, msg, 3).unwrap();
+        assert_eq!(report.calls, 3);
+        // init canonicalizes verifier and beneficiary and writes the config
+        assert_eq!(report.import_calls["canonicalize_address"], 6);
+        assert_eq!(report.import_calls["write_db"], 3);
+
+        let report = bench_query(&mut instance, br#"{"verifier":{}}"#, 2).unwrap();
+        assert_eq!(report.calls, 2);
+        assert_eq!(report.import_calls["read_db"], 2);
+    }
+}
@@ -1,6 +1,8 @@
+use std::collections::HashMap;
 use std::fs::create_dir_all;
 use std::marker::PhantomData;
 use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
 
 use lru::LruCache;
 use snafu::ResultExt;
@@ -8,27 +10,96 @@ use snafu::ResultExt;
 use cosmwasm::traits::{Api, Extern, Storage};
 
 use crate::backends::{backend, compile};
-use crate::compatability::check_api_compatibility;
-use crate::errors::{Error, IntegrityErr, IoErr};
+use crate::compatability::{
+    check_api_compatibility, check_code_limits, describe_code, ContractDescription, FloatPolicy,
+};
+use crate::config::VmConfig;
+use crate::errors::{Error, IoErr};
 use crate::instance::Instance;
 use crate::modules::{FileSystemCache, WasmHash};
-use crate::wasm_store::{load, save, wasm_hash};
+use crate::stats::{ContractStats, StatsStore};
+use crate::wasm_store::{FileSystemStore, WasmStore};
 
 static WASM_DIR: &str = "wasm";
+// modules compiled with the call depth limit are kept apart, so switching the feature
+// never loads a module compiled the other way
+#[cfg(not(feature = "call-depth"))]
 static MODULES_DIR: &str = "modules";
+#[cfg(feature = "call-depth")]
+static MODULES_DIR: &str = "modules-call-depth";
+static STATS_FILE: &str = "stats";
+
+/// Limits on the code CosmCache::save_wasm accepts, so a chain can govern contract
+/// resources. The defaults accept all contracts built with the standard tooling.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CacheOptions {
+    /// size of the uncompressed wasm in bytes
+    pub max_wasm_size: usize,
+    /// functions defined in the contract, not counting imports
+    pub max_function_count: usize,
+    /// maximum memory declared by the contract (initial memory if it declares no maximum),
+    /// in pages of 64 KiB
+    pub max_memory_pages: u32,
+    pub float_policy: FloatPolicy,
+}
+
+impl Default for CacheOptions {
+    fn default() -> Self {
+        CacheOptions {
+            max_wasm_size: 2 * 1024 * 1024,
+            max_function_count: 10_000,
+            // 32 MiB
+            max_memory_pages: 512,
+            float_policy: FloatPolicy::Allow,
+        }
+    }
+}
+
+/// Where CosmCache::get_instance got an instance from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LoadSource {
+    InstanceCache,
+    ModuleCache,
+    /// compiled from the stored code
+    Wasm,
+}
+
+/// CacheObserver is told what happens in the instance cache of a CosmCache, eg. to log
+/// or export metrics. Register one with CosmCache::set_observer.
+pub trait CacheObserver {
+    fn on_load(&mut self, _id: &[u8], _source: LoadSource) {}
+
+    /// Asked before the instance of id is evicted to make room for another. Return false to
+    /// keep it, eg. during block proposal; the next least recently used one is asked then.
+    /// If all are kept, the new instance is not cached.
+    fn on_evict(&mut self, _id: &[u8]) -> bool {
+        true
+    }
+}
 
 #[derive(Debug, Default, Clone)]
 struct Stats {
     hits_instance: u32,
     hits_module: u32,
     misses: u32,
+    reclaimed_buffers: usize,
+    reclaimed_bytes: usize,
 }
 
 pub struct CosmCache<S: Storage + 'static, A: Api + 'static> {
-    wasm_path: PathBuf,
+    wasm_store: Box<dyn WasmStore>,
     modules: FileSystemCache,
     instances: Option<LruCache<WasmHash, wasmer_runtime_core::Instance>>,
+    options: CacheOptions,
     stats: Stats,
+    // when code was last instantiated through this cache, for gc
+    last_used: HashMap<Vec<u8>, SystemTime>,
+    observer: Option<Box<dyn CacheObserver>>,
+    contract_stats: StatsStore,
+    // code ids of the cached instances, for the observer
+    cached_ids: HashMap<WasmHash, Vec<u8>>,
+    // applied to every instance handed out, when created with from_config
+    config: Option<VmConfig>,
     // Those two don't store data but only fix type information
     type_storage: PhantomData<S>,
     type_api: PhantomData<A>,
@@ -50,7 +121,24 @@ where
         let base = base_dir.into();
         let wasm_path = base.join(WASM_DIR);
         create_dir_all(&wasm_path).context(IoErr {})?;
+        let wasm_store = Box::new(FileSystemStore::new(wasm_path));
+        CosmCache::with_wasm_store(base, cache_size, wasm_store)
+    }
+
+    /// Like new, but keeps the original code in wasm_store instead of base_dir.
+    /// Compiled modules are still cached under base_dir.
+    ///
+    /// # Safety
+    ///
+    /// See new
+    pub unsafe fn with_wasm_store<P: Into<PathBuf>>(
+        base_dir: P,
+        cache_size: usize,
+        wasm_store: Box<dyn WasmStore>,
+    ) -> Result<Self, Error> {
+        let base = base_dir.into();
         let modules = FileSystemCache::new(base.join(MODULES_DIR)).context(IoErr {})?;
+        let contract_stats = StatsStore::open(base.join(STATS_FILE))?;
         let instances = if cache_size > 0 {
             Some(LruCache::new(cache_size))
         } else {
@@ -58,17 +146,56 @@ where
         };
         Ok(CosmCache {
             modules,
-            wasm_path,
+            wasm_store,
             instances,
+            options: CacheOptions::default(),
             stats: Stats::default(),
+            last_used: HashMap::new(),
+            observer: None,
+            contract_stats,
+            cached_ids: HashMap::new(),
+            config: None,
             type_storage: PhantomData::<S> {},
             type_api: PhantomData::<A> {},
         })
     }
 
+    /// Like new, but takes the cache size and options from config and configures every
+    /// instance returned by get_instance with it
+    ///
+    /// # Safety
+    ///
+    /// See new
+    pub unsafe fn from_config<P: Into<PathBuf>>(
+        base_dir: P,
+        config: &VmConfig,
+    ) -> Result<Self, Error> {
+        let mut cache = CosmCache::new(base_dir, config.cache_size)?;
+        cache.set_options(config.cache_options);
+        cache.config = Some(config.clone());
+        Ok(cache)
+    }
+
+    /// Sets the limits for all code saved from now on. Stored code is not checked again.
+    pub fn set_options(&mut self, options: CacheOptions) {
+        self.options = options;
+    }
+
+    /// Registers an observer of the instance cache, replacing any registered before
+    pub fn set_observer(&mut self, observer: Box<dyn CacheObserver>) {
+        self.observer = Some(observer);
+    }
+
+    fn notify_load(&mut self, id: &[u8], source: LoadSource) {
+        if let Some(observer) = &mut self.observer {
+            observer.on_load(id, source);
+        }
+    }
+
     pub fn save_wasm(&mut self, wasm: &[u8]) -> Result<Vec<u8>, Error> {
         check_api_compatibility(wasm)?;
-        let id = save(&self.wasm_path, wasm)?;
+        check_code_limits(wasm, &self.options)?;
+        let id = self.wasm_store.save(wasm)?;
         let module = compile(wasm)?;
         let hash = WasmHash::generate(&id);
         // singlepass cannot store a module, just make best effort
@@ -76,15 +203,20 @@ where
         Ok(id)
     }
 
+    /// load_wasm returns the original code, verifying it was not corrupted on disk
     pub fn load_wasm(&self, id: &[u8]) -> Result<Vec<u8>, Error> {
-        let code = load(&self.wasm_path, id)?;
-        // verify hash matches (integrity check)
-        let hash = wasm_hash(&code);
-        if hash.ne(&id) {
-            IntegrityErr {}.fail()
-        } else {
-            Ok(code)
-        }
+        self.wasm_store.load(id)
+    }
+
+    /// Lists the entry points and embedded schema of stored code, without instantiating it
+    pub fn describe(&self, id: &[u8]) -> Result<ContractDescription, Error> {
+        describe_code(&self.load_wasm(id)?)
+    }
+
+    /// Compresses all code stored uncompressed by older versions of this cache.
+    /// Returns the number of migrated files.
+    pub fn rewrite_store(&self) -> Result<usize, Error> {
+        self.wasm_store.rewrite()
     }
 
     /// get instance returns a wasmer Instance tied to a previously saved wasm
@@ -93,14 +225,32 @@ where
         id: &[u8],
         deps: Extern<S, A>,
         gas_limit: u64,
+    ) -> Result<Instance<S, A>, Error> {
+        let mut instance = self.load_instance(id, deps, gas_limit)?;
+        if let Some(config) = &self.config {
+            config.configure(&mut instance);
+        }
+        Ok(instance)
+    }
+
+    fn load_instance(
+        &mut self,
+        id: &[u8],
+        deps: Extern<S, A>,
+        gas_limit: u64,
     ) -> Result<Instance<S, A>, Error> {
         let hash = WasmHash::generate(&id);
+        self.last_used.insert(id.to_vec(), SystemTime::now());
 
         // pop from lru cache if present
         if let Some(cache) = &mut self.instances {
             if let Some(cached_instance) = cache.pop(&hash) {
                 self.stats.hits_instance += 1;
-                return Ok(Instance::from_wasmer(cached_instance, deps, gas_limit));
+                self.cached_ids.remove(&hash);
+                let mut instance = Instance::from_wasmer(cached_instance, deps, gas_limit);
+                instance.set_code_id(id);
+                self.notify_load(id, LoadSource::InstanceCache);
+                return Ok(instance);
             }
         }
 
@@ -108,20 +258,116 @@ where
         let res = self.modules.load_with_backend(hash, backend());
         if let Ok(module) = res {
             self.stats.hits_module += 1;
-            return Instance::from_module(&module, deps, gas_limit);
+            let mut instance = Instance::from_module(&module, deps, gas_limit)?;
+            instance.set_code_id(id);
+            self.notify_load(id, LoadSource::ModuleCache);
+            return Ok(instance);
         }
 
         // fall back to wasm cache (and re-compiling) - this is for backends that don't support serialization
         let wasm = self.load_wasm(id)?;
         self.stats.misses += 1;
-        Instance::from_code(&wasm, deps, gas_limit)
+        let mut instance = Instance::from_code(&wasm, deps, gas_limit)?;
+        instance.set_code_id(id);
+        self.notify_load(id, LoadSource::Wasm);
+        Ok(instance)
     }
 
-    pub fn store_instance(&mut self, id: &[u8], instance: Instance<S, A>) -> Option<Extern<S, A>> {
+    /// Returns the calls, gas, time and errors of id since its stats were first recorded,
+    /// including past runs of the node. Calls are counted when their instance is given
+    /// back with store_instance.
+    pub fn contract_stats(&self, id: &[u8]) -> Option<ContractStats> {
+        self.contract_stats.get(id)
+    }
+
+    /// Writes the contract stats to disk. This also happens when the cache is dropped.
+    pub fn flush_stats(&self) -> Result<(), Error> {
+        self.contract_stats.flush().context(IoErr {})
+    }
+
+    /// Removes stored code that is not in keep and was neither saved nor instantiated within
+    /// retain, together with its compiled module. Pass the ids of all code still referenced on
+    /// chain as keep. Instantiations before a restart are not known, so the save time counts
+    /// then. Returns the number of removed codes.
+    pub fn gc(&mut self, retain: Duration, keep: &[&[u8]]) -> Result<usize, Error> {
+        let now = SystemTime::now();
+        let mut removed = 0;
+        for (id, saved) in self.wasm_store.list()? {
+            if keep.contains(&id.as_slice()) {
+                continue;
+            }
+            let last_used = match self.last_used.get(&id) {
+                Some(used) if *used > saved => *used,
+                _ => saved,
+            };
+            // a clock going backwards keeps the code
+            if now.duration_since(last_used).unwrap_or_default() < retain {
+                continue;
+            }
+            self.wasm_store.remove(&id)?;
+            let hash = WasmHash::generate(&id);
+            if let Some(cache) = &mut self.instances {
+                cache.pop(&hash);
+            }
+            self.cached_ids.remove(&hash);
+            self.contract_stats.remove(&id);
+            self.modules.remove(hash).context(IoErr {})?;
+            self.last_used.remove(&id);
+            removed += 1;
+        }
+        Ok(removed)
+    }
+
+    /// Loads, validates and instantiates the given contracts ahead of time, so their first
+    /// call after a restart is served from the instance cache. Run it during startup.
+    /// deps provides the dependencies each instance is created with; they are dropped again.
+    /// Returns the number of instances added to the cache, which is 0 if it is disabled.
+    pub fn prewarm<F: FnMut() -> Extern<S, A>>(
+        &mut self,
+        ids: &[&[u8]],
+        mut deps: F,
+    ) -> Result<usize, Error> {
+        let mut warmed = 0;
+        for id in ids {
+            let wasm = self.load_wasm(id)?;
+            check_api_compatibility(&wasm)?;
+            if self.instances.is_none() {
+                continue;
+            }
+            let instance = self.get_instance(id, deps(), 0)?;
+            if self.store_instance(id, instance).is_some() {
+                warmed += 1;
+            }
+        }
+        Ok(warmed)
+    }
+
+    pub fn store_instance(
+        &mut self,
+        id: &[u8],
+        mut instance: Instance<S, A>,
+    ) -> Option<Extern<S, A>> {
+        self.contract_stats.add(id, &instance.take_stats());
         if let Some(cache) = &mut self.instances {
             let hash = WasmHash::generate(&id);
+            // an instance we cannot clean up is not safe for reuse, so only keep the dependencies
+            let reclaimed = instance.reclaim_allocations();
             let (wasmer_instance, ext) = Instance::recycle(instance);
-            cache.put(hash, wasmer_instance);
+            if let Ok(reclaimed) = reclaimed {
+                self.stats.reclaimed_buffers += reclaimed.count;
+                self.stats.reclaimed_bytes += reclaimed.bytes;
+                if cache.contains(&hash) || cache.len() < cache.cap() {
+                    cache.put(hash, wasmer_instance);
+                    self.cached_ids.insert(hash, id.to_vec());
+                } else if let Some(evicted) =
+                    choose_eviction(cache, &self.cached_ids, self.observer.as_mut())
+                {
+                    cache.pop(&evicted);
+                    self.cached_ids.remove(&evicted);
+                    cache.put(hash, wasmer_instance);
+                    self.cached_ids.insert(hash, id.to_vec());
+                }
+            }
             ext
         } else {
             None
@@ -129,18 +375,230 @@ where
     }
 }
 
+impl<S, A> Drop for CosmCache<S, A>
+where
+    S: Storage + 'static,
+    A: Api + 'static,
+{
+    fn drop(&mut self) {
+        // stats are informational, losing them must not fail the shutdown
+        let _ = self.contract_stats.flush();
+    }
+}
+
+// the least recently used instance the observer lets go, if any
+fn choose_eviction(
+    cache: &LruCache<WasmHash, wasmer_runtime_core::Instance>,
+    cached_ids: &HashMap<WasmHash, Vec<u8>>,
+    observer: Option<&mut Box<dyn CacheObserver>>,
+) -> Option<WasmHash> {
+    let mut candidates = cache.iter().rev().map(|(hash, _)| *hash);
+    match observer {
+        Some(observer) => candidates.find(|hash| match cached_ids.get(hash) {
+            Some(id) => observer.on_evict(id),
+            None => true,
+        }),
+        None => candidates.next(),
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use std::sync::{Arc, Mutex};
     use tempfile::TempDir;
 
-    use crate::calls::{call_handle, call_init};
+    use crate::calls::{call_handle, call_init, call_query};
+    use crate::gas::GasVersion;
     use cosmwasm::mock::{dependencies, mock_env, MockApi, MockStorage};
     use cosmwasm::types::coin;
 
     static TESTING_GAS_LIMIT: u64 = 400_000;
     static CONTRACT_0_7: &[u8] = include_bytes!("../testdata/contract_0.7.wasm");
 
+    #[test]
+    fn policy_blocks_code_and_contracts() {
+        use crate::policy::BlockList;
+        use cosmwasm::traits::Api;
+        use cosmwasm::types::HumanAddr;
+
+        let tmp_dir = TempDir::new().unwrap();
+        let mut cache: CosmCache<MockStorage, MockApi> =
+            unsafe { CosmCache::new(tmp_dir.path(), 10).unwrap() };
+        let id = cache.save_wasm(CONTRACT_0_7).unwrap();
+        let deps = dependencies(20);
+        let mut instance = cache.get_instance(&id, deps, TESTING_GAS_LIMIT).unwrap();
+        let env = mock_env(&instance.api, "creator", &coin("1000", "earth"), &[]);
+        let msg = br#"{"verifier": "verifies", "beneficiary": "benefits"}"#;
+
+        let frozen_address = instance
+            .api
+            .canonical_address(&HumanAddr::from("frozen"))
+            .unwrap();
+        let mut policy = BlockList::new();
+        policy.block_contract(&frozen_address);
+        instance.set_execution_policy(Box::new(policy.clone()));
+        call_init(&mut instance, &env, msg).unwrap().unwrap();
+
+        let mut frozen = env.clone();
+        frozen.contract.address = frozen_address;
+        match call_init(&mut instance, &frozen, msg) {
+            Err(Error::PolicyErr { msg, .. }) => assert!(msg.starts_with("contract ")),
+            Err(e) => panic!("Unexpected error {:?}", e),
+            Ok(_) => panic!("Blocked contract was called"),
+        }
+
+        policy.block_code(&id);
+        instance.set_execution_policy(Box::new(policy));
+        match call_query(&mut instance, br#"{"verifier":{}}"#) {
+            Err(Error::PolicyErr { .. }) => {}
+            Err(e) => panic!("Unexpected error {:?}", e),
+            Ok(_) => panic!("Blocked code was called"),
+        }
+    }
+
+    #[derive(Clone, Default)]
+    struct RecordingObserver {
+        events: Arc<Mutex<Vec<String>>>,
+        keep: Vec<Vec<u8>>,
+    }
+
+    impl CacheObserver for RecordingObserver {
+        fn on_load(&mut self, id: &[u8], source: LoadSource) {
+            // singlepass compiles from wasm where cranelift uses the module cache
+            let cached = source == LoadSource::InstanceCache;
+            let event = format!("load {} {}", id[0], cached);
+            self.events.lock().unwrap().push(event);
+        }
+
+        fn on_evict(&mut self, id: &[u8]) -> bool {
+            let keep = self.keep.iter().any(|kept| kept.as_slice() == id);
+            let event = format!("evict {} {}", id[0], !keep);
+            self.events.lock().unwrap().push(event);
+            !keep
+        }
+    }
+
+    #[test]
+    fn observer_sees_loads_and_evictions() {
+        let tmp_dir = TempDir::new().unwrap();
+        let mut cache: CosmCache<MockStorage, MockApi> =
+            unsafe { CosmCache::new(tmp_dir.path(), 1).unwrap() };
+        // a custom section makes a different code with the same contract
+        let mut other_code = CONTRACT_0_7.to_vec();
+        other_code.extend_from_slice(&[0, 6, 5, b'o', b't', b'h', b'e', b'r']);
+        let first = cache.save_wasm(CONTRACT_0_7).unwrap();
+        let second = cache.save_wasm(&other_code).unwrap();
+        assert_ne!(first[0], second[0]);
+        let observer = RecordingObserver {
+            keep: vec![first.clone()],
+            ..RecordingObserver::default()
+        };
+        cache.set_observer(Box::new(observer.clone()));
+
+        let instance = cache
+            .get_instance(&first, dependencies(20), TESTING_GAS_LIMIT)
+            .unwrap();
+        cache.store_instance(&first, instance);
+        // first is kept, so second is not cached
+        let instance = cache
+            .get_instance(&second, dependencies(20), TESTING_GAS_LIMIT)
+            .unwrap();
+        cache.store_instance(&second, instance);
+        let instance = cache
+            .get_instance(&first, dependencies(20), TESTING_GAS_LIMIT)
+            .unwrap();
+
+        assert_eq!(
+            *observer.events.lock().unwrap(),
+            vec![
+                format!("load {} false", first[0]),
+                format!("load {} false", second[0]),
+                format!("evict {} false", first[0]),
+                format!("load {} true", first[0]),
+            ]
+        );
+
+        // without the veto the least recently used instance goes
+        cache.set_observer(Box::new(RecordingObserver::default()));
+        cache.store_instance(&first, instance);
+        let instance = cache
+            .get_instance(&second, dependencies(20), TESTING_GAS_LIMIT)
+            .unwrap();
+        cache.store_instance(&second, instance);
+        assert_eq!(cache.cached_ids.values().collect::<Vec<_>>(), vec![&second]);
+    }
+
+    #[test]
+    fn contract_stats_are_persisted() {
+        let tmp_dir = TempDir::new().unwrap();
+        let id = {
+            let mut cache: CosmCache<MockStorage, MockApi> =
+                unsafe { CosmCache::new(tmp_dir.path(), 10).unwrap() };
+            let id = cache.save_wasm(CONTRACT_0_7).unwrap();
+            assert_eq!(cache.contract_stats(&id), None);
+            let mut instance = cache
+                .get_instance(&id, dependencies(20), TESTING_GAS_LIMIT)
+                .unwrap();
+            // charges host gas also on backends without wasm metering
+            instance.set_gas_version(GasVersion::V1);
+            let env = mock_env(&instance.api, "creator", &coin("1000", "earth"), &[]);
+            let msg = br#"{"verifier": "verifies", "beneficiary": "benefits"}"#;
+            call_init(&mut instance, &env, msg).unwrap().unwrap();
+            call_query(&mut instance, br#"{"verifier":{}}"#)
+                .unwrap()
+                .unwrap();
+            let res = call_query(&mut instance, br#"{"unknown":{}}"#).unwrap();
+            assert!(res.is_err());
+            cache.store_instance(&id, instance);
+
+            let stats = cache.contract_stats(&id).unwrap();
+            assert_eq!(stats.calls, 3);
+            assert_eq!(stats.errors, 1);
+            assert!(stats.gas_used > 0);
+            id
+        };
+
+        // the dropped cache wrote the stats
+        let cache: CosmCache<MockStorage, MockApi> =
+            unsafe { CosmCache::new(tmp_dir.path(), 10).unwrap() };
+        assert_eq!(cache.contract_stats(&id).unwrap().calls, 3);
+    }
+
+    #[test]
+    fn gc_removes_unused_code() {
+        let tmp_dir = TempDir::new().unwrap();
+        let mut cache: CosmCache<MockStorage, MockApi> =
+            unsafe { CosmCache::new(tmp_dir.path(), 10).unwrap() };
+        let id = cache.save_wasm(CONTRACT_0_7).unwrap();
+        let instance = cache
+            .get_instance(&id, dependencies(20), TESTING_GAS_LIMIT)
+            .unwrap();
+        cache.store_instance(&id, instance);
+
+        // recently used or still referenced code stays
+        assert_eq!(cache.gc(Duration::from_secs(3600), &[]).unwrap(), 0);
+        assert_eq!(cache.gc(Duration::from_secs(0), &[&id]).unwrap(), 0);
+        cache.load_wasm(&id).unwrap();
+
+        assert_eq!(cache.gc(Duration::from_secs(0), &[]).unwrap(), 1);
+        assert!(cache.load_wasm(&id).is_err());
+        assert!(cache
+            .get_instance(&id, dependencies(20), TESTING_GAS_LIMIT)
+            .is_err());
+    }
+
+    #[test]
+    fn describe_stored_code() {
+        let tmp_dir = TempDir::new().unwrap();
+        let mut cache: CosmCache<MockStorage, MockApi> =
+            unsafe { CosmCache::new(tmp_dir.path(), 10).unwrap() };
+        let id = cache.save_wasm(CONTRACT_0_7).unwrap();
+        let description = cache.describe(&id).unwrap();
+        assert_eq!(description.entry_points, vec!["init", "handle", "query"]);
+        assert_eq!(description.schema, None);
+    }
+
     #[test]
     fn saving_rejects_invalid_contract() {
         use wabt::wat2wasm;
@@ -168,6 +626,47 @@ mod test {
         }
     }
 
+    #[test]
+    fn saving_enforces_options() {
+        let tmp_dir = TempDir::new().unwrap();
+        let mut cache: CosmCache<MockStorage, MockApi> =
+            unsafe { CosmCache::new(tmp_dir.path(), 10).unwrap() };
+        let options = CacheOptions::default();
+
+        cache.set_options(CacheOptions {
+            max_wasm_size: 1000,
+            ..options
+        });
+        match cache.save_wasm(CONTRACT_0_7) {
+            Err(Error::CodeTooLargeErr { limit, .. }) => assert_eq!(limit, 1000),
+            Err(e) => panic!("Unexpected error {:?}", e),
+            Ok(_) => panic!("Didn't reject large code"),
+        }
+
+        cache.set_options(CacheOptions {
+            max_function_count: 1,
+            ..options
+        });
+        match cache.save_wasm(CONTRACT_0_7) {
+            Err(Error::TooManyFunctionsErr { limit, .. }) => assert_eq!(limit, 1),
+            Err(e) => panic!("Unexpected error {:?}", e),
+            Ok(_) => panic!("Didn't reject code with many functions"),
+        }
+
+        cache.set_options(CacheOptions {
+            max_memory_pages: 1,
+            ..options
+        });
+        match cache.save_wasm(CONTRACT_0_7) {
+            Err(Error::MemoryTooLargeErr { limit, .. }) => assert_eq!(limit, 1),
+            Err(e) => panic!("Unexpected error {:?}", e),
+            Ok(_) => panic!("Didn't reject code with large memory"),
+        }
+
+        cache.set_options(options);
+        cache.save_wasm(CONTRACT_0_7).unwrap();
+    }
+
     #[test]
     fn finds_cached_module() {
         let tmp_dir = TempDir::new().unwrap();
@@ -180,6 +679,20 @@ mod test {
         assert_eq!(cache.stats.misses, 0);
     }
 
+    #[test]
+    fn uses_given_wasm_store() {
+        use crate::wasm_store::MemoryStore;
+
+        let tmp_dir = TempDir::new().unwrap();
+        let store = Box::new(MemoryStore::new());
+        let mut cache: CosmCache<MockStorage, MockApi> =
+            unsafe { CosmCache::with_wasm_store(tmp_dir.path(), 10, store).unwrap() };
+        let id = cache.save_wasm(CONTRACT_0_7).unwrap();
+        assert_eq!(cache.load_wasm(&id).unwrap(), CONTRACT_0_7);
+        // no code was written to the filesystem
+        assert!(!tmp_dir.path().join(WASM_DIR).exists());
+    }
+
     #[test]
     fn finds_cached_instance() {
         let tmp_dir = TempDir::new().unwrap();
@@ -199,6 +712,70 @@ mod test {
         assert_eq!(cache.stats.misses, 0);
     }
 
+    #[test]
+    fn from_config_configures_instances() {
+        let tmp_dir = TempDir::new().unwrap();
+        let mut config = VmConfig::from_toml("cache_size = 0").unwrap();
+        config.limits.max_import_calls = 2;
+        let mut cache = unsafe { CosmCache::from_config(tmp_dir.path(), &config).unwrap() };
+        assert!(cache.instances.is_none());
+        let id = cache.save_wasm(CONTRACT_0_7).unwrap();
+
+        let deps = dependencies(20);
+        let mut instance = cache.get_instance(&id, deps, TESTING_GAS_LIMIT).unwrap();
+        let env = mock_env(&instance.api, "creator", &coin("1000", "earth"), &[]);
+        let msg = br#"{"verifier": "verifies", "beneficiary": "benefits"}"#;
+        match call_init(&mut instance, &env, msg) {
+            Err(Error::ImportLimitExceededErr { limit, .. }) => assert_eq!(limit, 2),
+            res => panic!("Unexpected result {:?}", res),
+        }
+    }
+
+    #[test]
+    fn store_instance_reclaims_outstanding_buffers() {
+        let tmp_dir = TempDir::new().unwrap();
+        let mut cache = unsafe { CosmCache::new(tmp_dir.path(), 10).unwrap() };
+        let id = cache.save_wasm(CONTRACT_0_7).unwrap();
+        let deps = dependencies(20);
+        let mut instance = cache.get_instance(&id, deps, TESTING_GAS_LIMIT).unwrap();
+        instance.allocate(b"never handed over").unwrap();
+        cache.store_instance(&id, instance).unwrap();
+        assert_eq!(cache.stats.reclaimed_buffers, 1);
+        assert_eq!(cache.stats.reclaimed_bytes, 17);
+
+        // arguments consumed by the contract are not reclaimed
+        let deps = dependencies(20);
+        let mut instance = cache.get_instance(&id, deps, TESTING_GAS_LIMIT).unwrap();
+        let env = mock_env(&instance.api, "creator", &coin("1000", "earth"), &[]);
+        let msg = r#"{"verifier": "verifies", "beneficiary": "benefits"}"#.as_bytes();
+        call_init(&mut instance, &env, msg).unwrap();
+        cache.store_instance(&id, instance).unwrap();
+        assert_eq!(cache.stats.hits_instance, 1);
+        assert_eq!(cache.stats.reclaimed_buffers, 1);
+        assert_eq!(cache.stats.reclaimed_bytes, 17);
+    }
+
+    #[test]
+    fn prewarm_fills_instance_cache() {
+        let tmp_dir = TempDir::new().unwrap();
+        let mut cache = unsafe { CosmCache::new(tmp_dir.path(), 10).unwrap() };
+        let id = cache.save_wasm(CONTRACT_0_7).unwrap();
+
+        assert_eq!(cache.prewarm(&[&id], || dependencies(20)).unwrap(), 1);
+        let _instance = cache
+            .get_instance(&id, dependencies(20), TESTING_GAS_LIMIT)
+            .unwrap();
+        assert_eq!(cache.stats.hits_instance, 1);
+
+        // unknown code fails
+        let missing = vec![0u8; 32];
+        assert!(cache.prewarm(&[&missing], || dependencies(20)).is_err());
+
+        // without an instance cache the code is only validated
+        let mut uncached = unsafe { CosmCache::new(tmp_dir.path(), 0).unwrap() };
+        assert_eq!(uncached.prewarm(&[&id], || dependencies(20)).unwrap(), 0);
+    }
+
     #[test]
     fn init_cached_contract() {
         let tmp_dir = TempDir::new().unwrap();
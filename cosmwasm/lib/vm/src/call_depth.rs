@@ -0,0 +1,275 @@
+//! Limits how deep contract functions may call each other. Wasmer runs contracts on the
+//! native stack, so unbounded recursion would overflow it at a depth that depends on the
+//! backend and the machine. instrument counts the depth in a global the host sets up
+//! before every call and aborts with CallDepthExceededErr at Limits::max_call_depth,
+//! the same on every node.
+//! Only built with the call-depth feature, which makes compile instrument all code.
+use parity_wasm::elements::{
+    BlockType, ExportEntry, ExportSection, GlobalEntry, GlobalSection, GlobalType, ImportCountType,
+    InitExpr, Instruction, Internal, Module, Section, ValueType,
+};
+use wasmer_runtime_core::{
+    export::Export, global::Global, import::IsExport, typed_func::Func, types::Value, vm::Ctx,
+    Instance,
+};
+
+use cosmwasm::traits::Storage;
+
+use crate::context::do_call_depth_exceeded;
+use crate::errors::Result;
+use crate::instrumentation::{add_function_import, deserialize_module, serialize_module};
+use crate::limits::Limits;
+
+/// Called by instrumented code when a call would exceed the limit
+pub static CALL_DEPTH_EXCEEDED_IMPORT: &str = "call_depth_exceeded";
+/// Exported global holding the current call depth
+static DEPTH_GLOBAL: &str = "__cosmwasm_call_depth";
+/// Exported global holding the maximum call depth
+static LIMIT_GLOBAL: &str = "__cosmwasm_max_call_depth";
+
+/// Wraps every call of a function defined in the contract, directly or through the table,
+/// in an increment and a decrement of the depth global. If the depth exceeds the limit
+/// global, call_depth_exceeded is called first. Calls of imports are not counted, they
+/// cannot call back into the contract.
+/// Code that already exports the depth global is returned unchanged.
+pub(crate) fn instrument(wasm_code: &[u8]) -> Result<Vec<u8>> {
+    let mut module = deserialize_module(wasm_code)?;
+    let instrumented = module
+        .export_section()
+        .iter()
+        .flat_map(|exports| exports.entries())
+        .any(|entry| entry.field() == DEPTH_GLOBAL);
+    if instrumented {
+        return Ok(wasm_code.to_vec());
+    }
+
+    let exceeded = add_function_import(&mut module, CALL_DEPTH_EXCEEDED_IMPORT, Vec::new())?;
+    let (depth, limit) = add_globals(&mut module);
+    if let Some(code_section) = module.code_section_mut() {
+        for body in code_section.bodies_mut() {
+            let code = body.code_mut().elements_mut();
+            let mut counted = Vec::with_capacity(code.len());
+            for instruction in code.drain(..) {
+                let counts = match instruction {
+                    Instruction::Call(index) => index > exceeded,
+                    Instruction::CallIndirect(_, _) => true,
+                    _ => false,
+                };
+                if counts {
+                    counted.extend_from_slice(&[
+                        Instruction::GetGlobal(depth),
+                        Instruction::I32Const(1),
+                        Instruction::I32Add,
+                        Instruction::SetGlobal(depth),
+                        Instruction::GetGlobal(depth),
+                        Instruction::GetGlobal(limit),
+                        Instruction::I32GtU,
+                        Instruction::If(BlockType::NoResult),
+                        Instruction::Call(exceeded),
+                        Instruction::End,
+                    ]);
+                    counted.push(instruction);
+                    counted.extend_from_slice(&[
+                        Instruction::GetGlobal(depth),
+                        Instruction::I32Const(1),
+                        Instruction::I32Sub,
+                        Instruction::SetGlobal(depth),
+                    ]);
+                } else {
+                    counted.push(instruction);
+                }
+            }
+            *code = counted;
+        }
+    }
+    serialize_module(module)
+}
+
+// adds and exports the depth and limit globals and returns their indices
+fn add_globals(module: &mut Module) -> (u32, u32) {
+    let first = (module.import_count(ImportCountType::Global)
+        + module
+            .global_section()
+            .map_or(0, |globals| globals.entries().len())) as u32;
+    if module.global_section().is_none() {
+        module
+            .insert_section(Section::Global(GlobalSection::with_entries(Vec::new())))
+            .expect("no global section yet");
+    }
+    let globals = module
+        .global_section_mut()
+        .expect("global section exists")
+        .entries_mut();
+    // the host sets both before every call, these only matter for functions called directly
+    let initial = vec![0, Limits::default().max_call_depth as i32];
+    for value in initial {
+        globals.push(GlobalEntry::new(
+            GlobalType::new(ValueType::I32, true),
+            InitExpr::new(vec![Instruction::I32Const(value), Instruction::End]),
+        ));
+    }
+
+    if module.export_section().is_none() {
+        module
+            .insert_section(Section::Export(ExportSection::with_entries(Vec::new())))
+            .expect("no export section yet");
+    }
+    let exports = module
+        .export_section_mut()
+        .expect("export section exists")
+        .entries_mut();
+    exports.push(ExportEntry::new(
+        DEPTH_GLOBAL.to_string(),
+        Internal::Global(first),
+    ));
+    exports.push(ExportEntry::new(
+        LIMIT_GLOBAL.to_string(),
+        Internal::Global(first + 1),
+    ));
+    (first, first + 1)
+}
+
+pub(crate) fn call_depth_imports<S: Storage>() -> Vec<(String, String, Export)> {
+    let exceeded = Func::new(|ctx: &mut Ctx| -> Result<()> { do_call_depth_exceeded::<S>(ctx) });
+    vec![(
+        "env".to_string(),
+        CALL_DEPTH_EXCEEDED_IMPORT.to_string(),
+        exceeded.to_export(),
+    )]
+}
+
+/// The depth and limit globals of an instance of instrumented code
+#[derive(Clone)]
+pub(crate) struct CallDepth {
+    depth: Global,
+    limit: Global,
+}
+
+impl CallDepth {
+    /// Returns None for code compiled before it was instrumented
+    pub fn from_instance(instance: &Instance) -> Option<Self> {
+        let mut depth = None;
+        let mut limit = None;
+        for (name, export) in instance.exports() {
+            if let Export::Global(global) = export {
+                if name == DEPTH_GLOBAL {
+                    depth = Some(global);
+                } else if name == LIMIT_GLOBAL {
+                    limit = Some(global);
+                }
+            }
+        }
+        Some(CallDepth {
+            depth: depth?,
+            limit: limit?,
+        })
+    }
+
+    /// Starts a call at depth 0. A call that trapped may have left the depth above that.
+    pub fn reset(&self, max_call_depth: u32) {
+        self.depth.set(Value::I32(0));
+        self.limit.set(Value::I32(max_call_depth as i32));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::errors::Error;
+    use crate::instance::{Func, Instance};
+    use crate::limits::LimitsVersion;
+    use cosmwasm::mock::{dependencies, MockApi, MockStorage};
+    use wabt::wat2wasm;
+
+    // recurses n times
+    static RECURSE_WAT: &str = r#"
+        (module
+          (func $recurse (export "recurse") (param i32) (result i32)
+            get_local 0
+            if (result i32)
+              get_local 0
+              i32.const 1
+              i32.sub
+              call $recurse
+            else
+              i32.const 0
+            end))
+    "#;
+
+    fn recurse(instance: &Instance<MockStorage, MockApi>, n: u32) -> Result<i32> {
+        let func: Func<u32, i32> = instance.func("recurse").unwrap();
+        match func.call(n) {
+            Ok(res) => Ok(res),
+            Err(err) => match err.0.downcast::<Error>() {
+                Ok(err) => Err(*err),
+                Err(payload) => panic!("Unexpected error {:?}", payload),
+            },
+        }
+    }
+
+    #[test]
+    fn deep_calls_are_aborted() {
+        let wasm = wat2wasm(RECURSE_WAT).unwrap();
+        let mut instance: Instance<MockStorage, MockApi> =
+            Instance::from_code(&wasm, dependencies(20), 1_000_000).unwrap();
+        instance.set_limits(LimitsVersion::V1.limits());
+        instance.start_gas_report();
+        assert_eq!(recurse(&instance, 1024).unwrap(), 0);
+        match recurse(&instance, 1025) {
+            Err(Error::CallDepthExceededErr { limit, .. }) => assert_eq!(limit, 1024),
+            res => panic!("Unexpected result {:?}", res),
+        }
+
+        // calls start at depth 0 again, with the limit set on the instance
+        instance.set_limits(Limits {
+            max_call_depth: 10,
+            ..LimitsVersion::V1.limits()
+        });
+        instance.start_gas_report();
+        assert_eq!(recurse(&instance, 10).unwrap(), 0);
+        match recurse(&instance, 11) {
+            Err(Error::CallDepthExceededErr { limit, .. }) => assert_eq!(limit, 10),
+            res => panic!("Unexpected result {:?}", res),
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "default-singlepass")]
+    fn instrumentation_costs_gas() {
+        use crate::backends::singlepass::compiler;
+        use crate::calls::call_init;
+        use cosmwasm::mock::mock_env;
+        use cosmwasm::types::coin;
+        use wasmer_runtime_core::compile_with;
+
+        static CONTRACT_0_7: &[u8] = include_bytes!("../testdata/contract_0.7.wasm");
+        let init_gas = |wasm: &[u8]| {
+            let module = compile_with(wasm, compiler().as_ref()).unwrap();
+            let mut instance: Instance<MockStorage, MockApi> =
+                Instance::from_module(&module, dependencies(20), 1_000_000).unwrap();
+            let env = mock_env(&instance.api, "creator", &coin("1000", "earth"), &[]);
+            let msg = br#"{"verifier": "verifies", "beneficiary": "benefits"}"#;
+            call_init(&mut instance, &env, msg).unwrap().unwrap();
+            1_000_000 - instance.get_gas()
+        };
+
+        // as in instance::test::contract_deducts_gas_init
+        let plain = init_gas(CONTRACT_0_7);
+        assert_eq!(plain, 52_543);
+        // the added instructions are metered like the contract's own
+        let instrumented = init_gas(&instrument(CONTRACT_0_7).unwrap());
+        println!(
+            "call depth instrumentation: {} gas more for init",
+            instrumented - plain
+        );
+        assert!(instrumented > plain);
+    }
+
+    #[test]
+    fn instrument_is_idempotent() {
+        let wasm = wat2wasm(RECURSE_WAT).unwrap();
+        let once = instrument(&wasm).unwrap();
+        assert_ne!(once, wasm);
+        assert_eq!(instrument(&once).unwrap(), once);
+    }
+}
@@ -2,20 +2,25 @@ use snafu::ResultExt;
 
 use cosmwasm::serde::{from_slice, to_vec};
 use cosmwasm::traits::{Api, Storage};
-use cosmwasm::types::{ContractResult, Env, QueryResult};
+use cosmwasm::types::{ContractResult, Env, QueryResult, BLOCK_ENTROPY_LENGTH};
 
-use crate::errors::{Error, ParseErr, RuntimeErr, SerializeErr};
+use wasmer_runtime_core::error::RuntimeError;
+
+use crate::backends::is_gas_depletion;
+use crate::errors::{
+    EnvErr, Error, GasDepletionErr, HeightUnavailableErr, ParseErr, RuntimeErr, SerializeErr,
+};
 use crate::instance::{Func, Instance};
+use crate::prepare::{Overlay, PreparedExecution, Simulation};
+use crate::random::RandomState;
+use crate::trace::TraceEvent;
 
 pub fn call_init<S: Storage + 'static, A: Api + 'static>(
     instance: &mut Instance<S, A>,
     env: &Env,
     msg: &[u8],
 ) -> Result<ContractResult, Error> {
-    let env = to_vec(env).context(SerializeErr {})?;
-    let data = call_init_raw(instance, &env, msg)?;
-    let res: ContractResult = from_slice(&data).context(ParseErr {})?;
-    Ok(res)
+    call_with_env(instance, env, msg, call_init_raw)
 }
 
 pub fn call_handle<S: Storage + 'static, A: Api + 'static>(
@@ -23,35 +28,262 @@ pub fn call_handle<S: Storage + 'static, A: Api + 'static>(
     env: &Env,
     msg: &[u8],
 ) -> Result<ContractResult, Error> {
+    call_with_env(instance, env, msg, call_handle_raw)
+}
+
+/// call_sudo runs the contract's sudo entry point, which only the chain itself calls,
+/// eg. for governance or hooks of other modules, never a transaction. It behaves like
+/// handle otherwise. Contracts without the export fail with ResolveErr.
+pub fn call_sudo<S: Storage + 'static, A: Api + 'static>(
+    instance: &mut Instance<S, A>,
+    env: &Env,
+    msg: &[u8],
+) -> Result<ContractResult, Error> {
+    call_with_env(instance, env, msg, call_sudo_raw)
+}
+
+// call_init_raw, call_handle_raw or call_sudo_raw
+type RawCall<S, A> = fn(&mut Instance<S, A>, &[u8], &[u8]) -> Result<Vec<u8>, Error>;
+
+// runs init, handle or sudo with the checks, random state and atomic writes they share
+fn call_with_env<S, A>(
+    instance: &mut Instance<S, A>,
+    env: &Env,
+    msg: &[u8],
+    call_raw: RawCall<S, A>,
+) -> Result<ContractResult, Error>
+where
+    S: Storage + 'static,
+    A: Api + 'static,
+{
+    check_env(env)?;
+    instance.check_policy(Some(&env.contract.address))?;
+    let random = random_state(env, instance.call_index());
+    instance.set_random(random);
+    instance.set_contract(Some(env.contract.address.clone()));
     let env = to_vec(env).context(SerializeErr {})?;
-    let data = call_handle_raw(instance, &env, msg)?;
-    let res: ContractResult = from_slice(&data).context(ParseErr {})?;
+    let res = atomic(
+        instance,
+        |instance| {
+            let data = call_raw(instance, &env, msg)?;
+            let res = from_slice(&data).context(ParseErr {})?;
+            check_result(instance, &res)?;
+            Ok(res)
+        },
+        |res: &ContractResult| !res.is_err(),
+    );
+    instance.set_random(None);
+    instance.set_contract(None);
+    instance.record_call(res.as_ref().map_or(true, ContractResult::is_err));
+    let res = res?;
+    trace_logs(instance, &res);
     Ok(res)
 }
 
+/// call_handle_readonly runs handle like call_handle, but fails with ReadOnlyErr as soon as
+/// the contract writes to storage. Clients use it to preview the result of an execute, eg.
+/// the output of a swap, without duplicating the contract's logic into queries.
+pub fn call_handle_readonly<S: Storage + 'static, A: Api + 'static>(
+    instance: &mut Instance<S, A>,
+    env: &Env,
+    msg: &[u8],
+) -> Result<ContractResult, Error> {
+    instance.set_read_only(true);
+    let res = call_handle(instance, env, msg);
+    instance.set_read_only(false);
+    res
+}
+
+/// call_handle_batch runs handle with every message in order, on the same instance and with
+/// the same env, and returns one result per message. The messages are independent calls:
+/// the writes of a failed message are dropped and the following messages still run,
+/// seeing the writes of the earlier messages that succeeded.
+pub fn call_handle_batch<S: Storage + 'static, A: Api + 'static>(
+    instance: &mut Instance<S, A>,
+    env: &Env,
+    msgs: &[&[u8]],
+) -> Vec<Result<ContractResult, Error>> {
+    msgs.iter()
+        .map(|msg| call_handle(instance, env, msg))
+        .collect()
+}
+
+/// call_handle_with_hints runs handle like call_handle, after reading the given keys from
+/// storage with a single get_many. Pass the keys the call is expected to read, eg. from
+/// simulating it, so a storage behind a slow connection fetches them in one round trip.
+/// Wrong or missing hints only cost the prefetch, reads and gas are the same either way.
+pub fn call_handle_with_hints<S: Storage + 'static, A: Api + 'static>(
+    instance: &mut Instance<S, A>,
+    env: &Env,
+    msg: &[u8],
+    keys: &[Vec<u8>],
+) -> Result<ContractResult, Error> {
+    // a refused call must not cost the storage a round trip either
+    instance.check_policy(Some(&env.contract.address))?;
+    instance.prefetch(keys);
+    let res = call_handle(instance, env, msg);
+    instance.clear_prefetched();
+    res
+}
+
+/// prepare_execute runs handle without modifying storage. The returned handle holds the
+/// result and all writes of the call, which `commit` applies later if the state the call
+/// read is still the same. This allows executing calls ahead of time.
+pub fn prepare_execute<S: Storage + 'static, A: Api + 'static>(
+    instance: &mut Instance<S, A>,
+    env: &Env,
+    msg: &[u8],
+) -> Result<PreparedExecution, Error> {
+    let gas_before = instance.get_gas();
+    instance.set_overlay(Some(Overlay::default()));
+    let res = call_handle(instance, env, msg);
+    // always remove the overlay, so following calls write to storage again
+    let overlay = instance.take_overlay().unwrap_or_default();
+    let gas_used = gas_before.saturating_sub(instance.get_gas());
+    Ok(PreparedExecution::new(res?, gas_used, overlay))
+}
+
+/// call_handle_simulate runs handle like prepare_execute, but drops the writes instead of
+/// offering to commit them. Use it to estimate the gas of a call, storage never changes.
+pub fn call_handle_simulate<S: Storage + 'static, A: Api + 'static>(
+    instance: &mut Instance<S, A>,
+    env: &Env,
+    msg: &[u8],
+) -> Result<Simulation, Error> {
+    let prepared = prepare_execute(instance, env, msg)?;
+    let writes = prepared.writes().len();
+    let (result, _) = prepared.into_parts();
+    Ok(Simulation {
+        result,
+        gas_report: instance.gas_report(),
+        writes,
+    })
+}
+
+/// commit applies a prepared execution to the instance's storage and returns its result.
+/// Fails with StateChangedErr, without writing anything, if any value read while
+/// preparing has changed since.
+pub fn commit<S: Storage + 'static, A: Api + 'static>(
+    instance: &mut Instance<S, A>,
+    prepared: PreparedExecution,
+) -> Result<ContractResult, Error> {
+    let mut verified = None;
+    instance.with_storage(|store| verified = Some(prepared.verify(store)));
+    // storage is only taken out when the instance is recycled
+    verified.expect("instance holds no storage")?;
+    let (result, writes) = prepared.into_parts();
+    instance.apply_writes(&writes)?;
+    Ok(result)
+}
+
 pub fn call_query<S: Storage + 'static, A: Api + 'static>(
     instance: &mut Instance<S, A>,
     msg: &[u8],
 ) -> Result<QueryResult, Error> {
-    let data = call_query_raw(instance, msg)?;
-    let res: QueryResult = from_slice(&data).context(ParseErr {})?;
-    Ok(res)
+    let res = call_query_raw(instance, msg)
+        .and_then(|data| from_slice::<QueryResult>(&data).context(ParseErr {}));
+    instance.record_call(res.as_ref().map_or(true, QueryResult::is_err));
+    res
+}
+
+/// call_query_with_gas_limit runs a query with its own gas limit, so public nodes can keep
+/// free queries tight without limiting executions. The query's gas is not taken from the
+/// gas the instance has left. Running out fails with GasDepletionErr in context "Query".
+pub fn call_query_with_gas_limit<S: Storage + 'static, A: Api + 'static>(
+    instance: &mut Instance<S, A>,
+    msg: &[u8],
+    gas_limit: u64,
+) -> Result<QueryResult, Error> {
+    let gas_left = instance.get_gas();
+    instance.set_gas(gas_limit);
+    let res = call_query(instance, msg);
+    instance.set_gas(gas_left);
+    match res {
+        Err(Error::RuntimeErr { ref source, .. }) if is_gas_depletion(source) => {
+            GasDepletionErr { context: "Query" }.fail()
+        }
+        res => res,
+    }
+}
+
+/// call_query_at_height runs a query against the state of an earlier block. snapshot returns
+/// the storage as of a height, eg. from the versioned store of an archive node, or None if
+/// that state is gone. The instance's own storage is used again afterwards.
+pub fn call_query_at_height<S, A, F>(
+    instance: &mut Instance<S, A>,
+    height: u64,
+    msg: &[u8],
+    snapshot: F,
+) -> Result<QueryResult, Error>
+where
+    S: Storage + 'static,
+    A: Api + 'static,
+    F: FnOnce(u64) -> Option<S>,
+{
+    let storage = match snapshot(height) {
+        Some(storage) => storage,
+        None => return HeightUnavailableErr { height }.fail(),
+    };
+    let current = instance.swap_storage(Some(storage));
+    let res = call_query(instance, msg);
+    instance.swap_storage(current);
+    res
 }
 
 pub fn call_query_raw<S: Storage + 'static, A: Api + 'static>(
     instance: &mut Instance<S, A>,
     msg: &[u8],
 ) -> Result<Vec<u8>, Error> {
-    // we cannot resuse the call_raw functionality as it assumes a param variable... just do it inline
-    let msg_offset = instance.allocate(msg)?;
-    let func: Func<u32, u32> = instance.func("query")?;
-    let res_offset = func.call(msg_offset).context(RuntimeErr {})?;
-    let data = instance.memory(res_offset);
+    let res_offset = run_query(instance, msg)?;
+    let data = instance.read_result(res_offset)?;
     // free return value in wasm (arguments were freed in wasm code)
     instance.deallocate(res_offset)?;
     Ok(data)
 }
 
+/// call_query_chunked runs a query like call_query_raw, but passes the raw result to
+/// consumer in chunks of at most chunk_size bytes instead of returning it.
+/// The result is never copied out of wasm memory as a whole, so max_result_length does not
+/// apply and very large responses are possible. An error of consumer stops the transfer
+/// and is returned. Returns the length of the result.
+pub fn call_query_chunked<S, A, F>(
+    instance: &mut Instance<S, A>,
+    msg: &[u8],
+    chunk_size: usize,
+    consumer: F,
+) -> Result<usize, Error>
+where
+    S: Storage + 'static,
+    A: Api + 'static,
+    F: FnMut(&[u8]) -> Result<(), Error>,
+{
+    let res_offset = run_query(instance, msg)?;
+    let res = instance.read_result_chunks(res_offset, chunk_size, consumer);
+    // free return value in wasm, even if the consumer failed
+    instance.deallocate(res_offset)?;
+    res
+}
+
+// calls query and returns the offset of the result region
+fn run_query<S: Storage + 'static, A: Api + 'static>(
+    instance: &mut Instance<S, A>,
+    msg: &[u8],
+) -> Result<u32, Error> {
+    instance.check_policy(None)?;
+    // we cannot resuse the call_raw functionality as it assumes a param variable... just do it inline
+    let msg_offset = instance.allocate(msg)?;
+    let gas_before = instance.start_gas_report();
+    let func: Func<u32, u32> = instance.func("query")?;
+    trace_entry_point(instance, "query");
+    let res = func.call(msg_offset);
+    instance.finish_gas_report(gas_before);
+    instance.trace_slow_call("query", msg.len());
+    trace_gas(instance);
+    // the contract owns the argument once called, even if the call failed
+    instance.hand_over(msg_offset);
+    res.map_err(host_error)
+}
+
 pub fn call_init_raw<S: Storage + 'static, A: Api + 'static>(
     instance: &mut Instance<S, A>,
     env: &[u8],
@@ -68,20 +300,1268 @@ pub fn call_handle_raw<S: Storage + 'static, A: Api + 'static>(
     call_raw(instance, "handle", env, msg)
 }
 
+pub fn call_sudo_raw<S: Storage + 'static, A: Api + 'static>(
+    instance: &mut Instance<S, A>,
+    env: &[u8],
+    msg: &[u8],
+) -> Result<Vec<u8>, Error> {
+    call_raw(instance, "sudo", env, msg)
+}
+
+// init, handle and sudo keep their writes only if the data does not parse as an error
+// result, so embedders calling the raw functions get the same atomicity as call_init etc.
 fn call_raw<S: Storage + 'static, A: Api + 'static>(
     instance: &mut Instance<S, A>,
     name: &str,
     env: &[u8],
     msg: &[u8],
 ) -> Result<Vec<u8>, Error> {
+    atomic(
+        instance,
+        |instance| run_raw(instance, name, env, msg),
+        |data: &Vec<u8>| !is_err_result(data),
+    )
+}
+
+fn is_err_result(data: &[u8]) -> bool {
+    match from_slice::<ContractResult>(data) {
+        Ok(res) => res.is_err(),
+        Err(_) => false,
+    }
+}
+
+fn run_raw<S: Storage + 'static, A: Api + 'static>(
+    instance: &mut Instance<S, A>,
+    name: &str,
+    env: &[u8],
+    msg: &[u8],
+) -> Result<Vec<u8>, Error> {
+    instance.check_policy(None)?;
     let param_offset = instance.allocate(env)?;
     let msg_offset = instance.allocate(msg)?;
 
+    let gas_before = instance.start_gas_report();
     let func: Func<(u32, u32), u32> = instance.func(name)?;
-    let res_offset = func.call(param_offset, msg_offset).context(RuntimeErr {})?;
+    trace_entry_point(instance, name);
+    let res = func.call(param_offset, msg_offset);
+    instance.finish_gas_report(gas_before);
+    instance.trace_slow_call(name, env.len() + msg.len());
+    trace_gas(instance);
+    // the contract owns the arguments once called, even if the call failed
+    instance.hand_over(param_offset);
+    instance.hand_over(msg_offset);
+    let res_offset = res.map_err(host_error)?;
 
-    let data = instance.memory(res_offset);
+    let data = instance.read_result(res_offset)?;
     // free return value in wasm (arguments were freed in wasm code)
     instance.deallocate(res_offset)?;
     Ok(data)
 }
+
+/// Runs the call with all storage writes buffered and applies them only if it returned a
+/// result for which succeeded is true, so a failing call never leaves partial writes behind.
+/// Nested calls, eg. the raw call within call_init, run in the outer buffer and leave the
+/// decision to it. Within prepare_execute the writes are already buffered and kept for commit.
+fn atomic<S, A, T, F>(
+    instance: &mut Instance<S, A>,
+    call: F,
+    succeeded: fn(&T) -> bool,
+) -> Result<T, Error>
+where
+    S: Storage + 'static,
+    A: Api + 'static,
+    F: FnOnce(&mut Instance<S, A>) -> Result<T, Error>,
+{
+    let outer = instance.take_overlay();
+    if outer.is_some() {
+        instance.set_overlay(outer);
+        return call(instance);
+    }
+    instance.set_overlay(Some(Overlay::default()));
+    let res = call(instance);
+    let overlay = instance.take_overlay().unwrap_or_default();
+    let keep = match &res {
+        Ok(value) => succeeded(value),
+        Err(_) => false,
+    };
+    if keep {
+        instance.apply_writes(overlay.writes())?;
+    } else {
+        instance.discard_storage_usage();
+    }
+    res
+}
+
+/// Host functions abort a call by returning an Error, which arrives as the payload
+/// of the RuntimeError. Everything else, such as traps and panics, stays a RuntimeErr.
+fn host_error(err: RuntimeError) -> Error {
+    match err.0.downcast::<Error>() {
+        Ok(err) => *err,
+        Err(payload) => {
+            let res: Result<(), RuntimeError> = Err(RuntimeError(payload));
+            res.context(RuntimeErr {}).unwrap_err()
+        }
+    }
+}
+
+fn random_state(env: &Env, call_index: u64) -> Option<RandomState> {
+    env.block.entropy.as_ref().map(|entropy| {
+        RandomState::new(
+            entropy.as_slice(),
+            env.contract.address.as_slice(),
+            call_index,
+        )
+    })
+}
+
+/// Rejects an Env the chain should never have produced, before it reaches the contract
+pub(crate) fn check_env(env: &Env) -> Result<(), Error> {
+    if let Some(entropy) = &env.block.entropy {
+        if entropy.len() != BLOCK_ENTROPY_LENGTH {
+            return EnvErr {
+                msg: format!(
+                    "block entropy must be {} bytes, got {}",
+                    BLOCK_ENTROPY_LENGTH,
+                    entropy.len()
+                ),
+            }
+            .fail();
+        }
+    }
+    Ok(())
+}
+
+fn trace_entry_point<S: Storage + 'static, A: Api + 'static>(
+    instance: &Instance<S, A>,
+    name: &str,
+) {
+    instance.record_trace(TraceEvent::EntryPoint {
+        name: name.to_string(),
+    });
+    trace_gas(instance);
+}
+
+fn trace_gas<S: Storage + 'static, A: Api + 'static>(instance: &Instance<S, A>) {
+    instance.record_trace(TraceEvent::GasCheckpoint {
+        remaining: instance.get_gas(),
+    });
+}
+
+// a log over the limits or a message the check rejects fails the call, so its writes are dropped
+fn check_result<S: Storage + 'static, A: Api + 'static>(
+    instance: &Instance<S, A>,
+    res: &ContractResult,
+) -> Result<(), Error> {
+    match res {
+        ContractResult::Ok(response) => {
+            instance.limits().check_log(&response.log)?;
+            match instance.message_check() {
+                Some(check) => check.check(&instance.api, &response.messages),
+                None => Ok(()),
+            }
+        }
+        ContractResult::Err(_) => Ok(()),
+    }
+}
+
+fn trace_logs<S: Storage + 'static, A: Api + 'static>(
+    instance: &Instance<S, A>,
+    res: &ContractResult,
+) {
+    if let ContractResult::Ok(response) = res {
+        for attr in response.log.iter() {
+            instance.record_trace(TraceEvent::Log {
+                key: attr.key.clone(),
+                value: attr.value.clone(),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::errors::HostCallErr;
+    use crate::gas::{GasConfig, GasVersion};
+    use crate::host_functions::{HostFunction, HostFunctions};
+    use crate::limits::Limits;
+    use crate::policy::BlockList;
+    use crate::testing::{mock_instance, with_allocator};
+    use crate::trace::{diff_gas_traces, hash_key, TraceRecorder};
+    use cosmwasm::encoding::Binary;
+    use cosmwasm::mock::{dependencies, mock_entropy, mock_env, MockApi, MockStorage};
+    use cosmwasm::traits::Extern;
+    use cosmwasm::traits::ReadonlyStorage;
+    use cosmwasm::types::coin;
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use std::time::Duration;
+    use wabt::wat2wasm;
+
+    static CONTRACT_0_7: &[u8] = include_bytes!("../testdata/contract_0.7.wasm");
+
+    #[test]
+    fn check_env_validates_entropy() {
+        let api = cosmwasm::mock::MockApi::new(20);
+        let mut env = mock_env(&api, "creator", &[], &[]);
+        check_env(&env).unwrap();
+
+        env.block.entropy = Some(mock_entropy(b"block 12345"));
+        check_env(&env).unwrap();
+
+        env.block.entropy = Some(Binary(vec![1, 2, 3]));
+        match check_env(&env) {
+            Err(Error::EnvErr { msg, .. }) => {
+                assert_eq!(msg, "block entropy must be 32 bytes, got 3")
+            }
+            Err(e) => panic!("unexpected error, {:?}", e),
+            Ok(_) => panic!("short entropy must be rejected"),
+        }
+    }
+
+    #[test]
+    fn trace_sink_receives_events() {
+        let mut instance = mock_instance(&CONTRACT_0_7);
+        let recorder = TraceRecorder::new();
+        instance.set_trace_sink(Box::new(recorder.clone()));
+
+        let env = mock_env(&instance.api, "creator", &coin("1000", "earth"), &[]);
+        let msg = r#"{"verifier": "verifies", "beneficiary": "benefits"}"#.as_bytes();
+        call_init(&mut instance, &env, msg).unwrap().unwrap();
+
+        let events = recorder.events();
+        assert_eq!(
+            events[0],
+            TraceEvent::EntryPoint {
+                name: "init".to_string()
+            }
+        );
+        match &events[1] {
+            TraceEvent::GasCheckpoint { .. } => {}
+            e => panic!("unexpected event {:?}", e),
+        }
+        // hackatom stores its config under the "config" key
+        let writes: Vec<&TraceEvent> = events
+            .iter()
+            .filter(|e| match e {
+                TraceEvent::WriteDb { .. } => true,
+                _ => false,
+            })
+            .collect();
+        assert_eq!(writes.len(), 1);
+        match writes[0] {
+            TraceEvent::WriteDb { key_hash, .. } => assert_eq!(key_hash, &hash_key(b"config")),
+            e => panic!("unexpected event {:?}", e),
+        }
+
+        // removed sinks get no more events
+        let count = recorder.events().len();
+        assert!(instance.take_trace_sink().is_some());
+        call_query(&mut instance, br#"{"verifier":{}}"#)
+            .unwrap()
+            .unwrap();
+        assert_eq!(recorder.events().len(), count);
+    }
+
+    #[test]
+    fn storage_usage_is_reported() {
+        let mut instance = mock_instance(&CONTRACT_0_7);
+        let env = mock_env(&instance.api, "creator", &coin("1000", "earth"), &[]);
+        let msg = r#"{"verifier": "verifies", "beneficiary": "benefits"}"#.as_bytes();
+        call_init(&mut instance, &env, msg).unwrap().unwrap();
+        let usage = instance.storage_usage();
+        assert_eq!(usage.writes, 1);
+        let mut config_len = 0;
+        instance.with_storage(|store| config_len = store.get(b"config").unwrap().len());
+        assert_eq!(usage.bytes_written, (b"config".len() + config_len) as u64);
+        assert_eq!(usage.net_bytes, usage.bytes_written as i64);
+
+        // the same config again does not grow the state
+        call_init(&mut instance, &env, msg).unwrap().unwrap();
+        assert_eq!(instance.storage_usage().net_bytes, 0);
+
+        // writes after a read of the same key do not look the value up again
+        let wasm = wat2wasm(with_allocator(READ_WRITE_WAT)).unwrap();
+        let mut instance: Instance<MockStorage, MockApi> =
+            Instance::from_code(&wasm, dependencies(20), 1_000_000).unwrap();
+        let gas = GasVersion::V1.config();
+        instance.set_gas_config(gas);
+        instance.with_storage(|store| store.set(b"foo", b"old value"));
+        call_init(&mut instance, &env, b"{}").unwrap().unwrap();
+        assert_eq!(instance.storage_usage().net_bytes, 3 - 9);
+        assert_eq!(
+            instance.gas_report().storage_gas,
+            gas.read_cost(b"foo", &Some(b"old value".to_vec())) + gas.write_cost(b"foo", b"bar")
+        );
+
+        // calls without writes report none
+        let res = call_handle(&mut instance, &env, b"{}").unwrap();
+        assert!(res.is_err());
+        assert_eq!(instance.storage_usage(), Default::default());
+    }
+
+    // init reads foo into the region at 32 and then writes foo=bar.
+    // Regions at 8 and 16 point to key and value, 24 to the result.
+    static READ_WRITE_WAT: &'static str = r#"
+        (import "env" "read_db" (func $read_db (param i32 i32) (result i32)))
+        (import "env" "write_db" (func $write_db (param i32 i32)))
+        (memory (export "memory") 1)
+        (data (i32.const 8) "\40\00\00\00\03\00\00\00")
+        (data (i32.const 16) "\48\00\00\00\03\00\00\00")
+        (data (i32.const 24) "\50\00\00\00\2b\00\00\00")
+        (data (i32.const 32) "\80\00\00\00\10\00\00\00")
+        (data (i32.const 64) "foo")
+        (data (i32.const 72) "bar")
+        (data (i32.const 80) "{\"ok\":{\"messages\":[],\"log\":[],\"data\":null}}")
+        (func (export "init") (param i32 i32) (result i32)
+          i32.const 8
+          i32.const 32
+          call $read_db
+          drop
+          i32.const 8
+          i32.const 16
+          call $write_db
+          i32.const 24)
+        (func (export "handle") (param i32 i32) (result i32)
+          i32.const 24)
+    "#;
+
+    #[test]
+    fn slow_calls_are_traced() {
+        let mut instance = mock_instance(&CONTRACT_0_7);
+        let recorder = TraceRecorder::new();
+        instance.set_trace_sink(Box::new(recorder.clone()));
+        let slow_calls = || -> Vec<TraceEvent> {
+            recorder
+                .events()
+                .into_iter()
+                .filter(|e| match e {
+                    TraceEvent::SlowCall { .. } => true,
+                    _ => false,
+                })
+                .collect()
+        };
+
+        let env = mock_env(&instance.api, "creator", &coin("1000", "earth"), &[]);
+        let msg = r#"{"verifier": "verifies", "beneficiary": "benefits"}"#.as_bytes();
+        call_init(&mut instance, &env, msg).unwrap().unwrap();
+        assert_eq!(slow_calls().len(), 0);
+
+        // every call takes longer than nothing
+        instance.set_slow_call_threshold(Some(Duration::from_secs(0)));
+        call_init(&mut instance, &env, msg).unwrap().unwrap();
+        call_query(&mut instance, br#"{"verifier":{}}"#)
+            .unwrap()
+            .unwrap();
+        let events = slow_calls();
+        assert_eq!(events.len(), 2);
+        match &events[0] {
+            TraceEvent::SlowCall {
+                entry_point,
+                contract,
+                gas_used,
+                import_calls,
+                input_len,
+                ..
+            } => {
+                assert_eq!(entry_point, "init");
+                assert_eq!(contract, &Some(env.contract.address.clone()));
+                assert!(*gas_used > 0);
+                assert_eq!(import_calls["write_db"], 1);
+                assert_eq!(*input_len, to_vec(&env).unwrap().len() + msg.len());
+            }
+            e => panic!("unexpected event {:?}", e),
+        }
+        match &events[1] {
+            TraceEvent::SlowCall {
+                entry_point,
+                contract,
+                ..
+            } => {
+                assert_eq!(entry_point, "query");
+                assert_eq!(contract, &None);
+            }
+            e => panic!("unexpected event {:?}", e),
+        }
+    }
+
+    #[test]
+    fn gas_audit_traces_match_on_identical_calls() {
+        let audited_init = || {
+            let mut instance = mock_instance(&CONTRACT_0_7);
+            let recorder = TraceRecorder::new();
+            instance.set_trace_sink(Box::new(recorder.clone()));
+            instance.set_gas_audit(true);
+            let env = mock_env(&instance.api, "creator", &coin("1000", "earth"), &[]);
+            let msg = r#"{"verifier": "verifies", "beneficiary": "benefits"}"#.as_bytes();
+            call_init(&mut instance, &env, msg).unwrap().unwrap();
+            (recorder.events(), instance.gas_report())
+        };
+
+        let (left, report) = audited_init();
+        let (right, _) = audited_init();
+        assert_eq!(diff_gas_traces(&left, &right), None);
+
+        // the charges add up to the gas of the call
+        let total: u64 = left
+            .iter()
+            .map(|e| match e {
+                TraceEvent::GasCharge { amount, .. } => *amount,
+                _ => 0,
+            })
+            .sum();
+        assert_eq!(total, report.used_internally);
+    }
+
+    #[test]
+    fn prepare_execute_and_commit() {
+        let mut instance = mock_instance(&CONTRACT_0_7);
+        let env = mock_env(&instance.api, "creator", &coin("1000", "earth"), &[]);
+        let msg = r#"{"verifier": "verifies", "beneficiary": "benefits"}"#.as_bytes();
+        call_init(&mut instance, &env, msg).unwrap().unwrap();
+
+        let env = mock_env(
+            &instance.api,
+            "verifies",
+            &coin("15", "earth"),
+            &coin("1015", "earth"),
+        );
+        let msg = br#"{"release":{}}"#;
+        let prepared = prepare_execute(&mut instance, &env, msg).unwrap();
+        assert!(prepared.writes().is_empty());
+        let res = commit(&mut instance, prepared).unwrap().unwrap();
+        assert_eq!(res.messages.len(), 1);
+
+        // the config was read, so changing it invalidates the prepared call
+        let prepared = prepare_execute(&mut instance, &env, msg).unwrap();
+        instance.with_storage(|store| store.set(b"config", b"{}"));
+        match commit(&mut instance, prepared) {
+            Err(Error::StateChangedErr { .. }) => {}
+            Err(e) => panic!("unexpected error, {:?}", e),
+            Ok(_) => panic!("commit must detect the changed config"),
+        }
+    }
+
+    #[test]
+    fn oversized_log_fails_call() {
+        let mut instance = mock_instance(&CONTRACT_0_7);
+        let env = mock_env(&instance.api, "creator", &coin("1000", "earth"), &[]);
+        let msg = r#"{"verifier": "verifies", "beneficiary": "benefits"}"#.as_bytes();
+        call_init(&mut instance, &env, msg).unwrap().unwrap();
+
+        let env = mock_env(
+            &instance.api,
+            "verifies",
+            &coin("15", "earth"),
+            &coin("1015", "earth"),
+        );
+        // release logs action and destination
+        instance.set_limits(Limits {
+            max_log_attributes: 1,
+            ..Limits::default()
+        });
+        match call_handle(&mut instance, &env, br#"{"release":{}}"#) {
+            Err(Error::ResultTooLargeErr {
+                limit, size, max, ..
+            }) => {
+                assert_eq!(limit, "max_log_attributes");
+                assert_eq!(size, 2);
+                assert_eq!(max, 1);
+            }
+            Err(e) => panic!("Unexpected error {:?}", e),
+            Ok(_) => panic!("Oversized log was accepted"),
+        }
+
+        instance.set_limits(Limits::default());
+        call_handle(&mut instance, &env, br#"{"release":{}}"#)
+            .unwrap()
+            .unwrap();
+    }
+
+    #[test]
+    fn import_limit_aborts_call() {
+        let mut instance = mock_instance(&CONTRACT_0_7);
+        // init canonicalizes two addresses and writes the config
+        instance.set_limits(Limits {
+            max_import_calls: 2,
+            ..Limits::default()
+        });
+        let env = mock_env(&instance.api, "creator", &coin("1000", "earth"), &[]);
+        let msg = br#"{"verifier": "verifies", "beneficiary": "benefits"}"#;
+        match call_init(&mut instance, &env, msg) {
+            Err(Error::ImportLimitExceededErr { limit, .. }) => assert_eq!(limit, 2),
+            res => panic!("Unexpected result {:?}", res),
+        }
+
+        // the count starts over with every call
+        instance.set_limits(Limits {
+            max_import_calls: 3,
+            ..Limits::default()
+        });
+        call_init(&mut instance, &env, msg).unwrap().unwrap();
+        call_init(&mut instance, &env, msg).unwrap().unwrap();
+    }
+
+    #[test]
+    fn simulate_does_not_write() {
+        let mut instance = writing_instance();
+        let env = mock_env(&instance.api, "creator", &[], &[]);
+
+        let simulation = call_handle_simulate(&mut instance, &env, b"{}").unwrap();
+        assert_eq!(simulation.result, ContractResult::Err("boom".to_string()));
+        assert_eq!(simulation.writes, 0);
+
+        let mut instance = mock_instance(&CONTRACT_0_7);
+        instance.set_gas_version(GasVersion::V1);
+        let env = mock_env(&instance.api, "creator", &coin("1000", "earth"), &[]);
+        let msg = r#"{"verifier": "verifies", "beneficiary": "benefits"}"#.as_bytes();
+        call_init(&mut instance, &env, msg).unwrap().unwrap();
+        let env = mock_env(
+            &instance.api,
+            "verifies",
+            &coin("15", "earth"),
+            &coin("1015", "earth"),
+        );
+        let simulation = call_handle_simulate(&mut instance, &env, br#"{"release":{}}"#).unwrap();
+        assert_eq!(simulation.result.unwrap().messages.len(), 1);
+        assert_eq!(simulation.gas_report, instance.gas_report());
+        assert!(simulation.gas_report.storage_gas > 0);
+    }
+
+    #[test]
+    fn resource_report_counts_imports() {
+        let mut instance = mock_instance(&CONTRACT_0_7);
+        let env = mock_env(&instance.api, "creator", &coin("1000", "earth"), &[]);
+        let msg = r#"{"verifier": "verifies", "beneficiary": "benefits"}"#.as_bytes();
+        call_init(&mut instance, &env, msg).unwrap().unwrap();
+
+        let report = instance.resource_report().clone();
+        let calls: Vec<(&str, u32)> = report
+            .import_calls
+            .iter()
+            .map(|(name, count)| (name.as_str(), *count))
+            .collect();
+        assert_eq!(calls, vec![("canonicalize_address", 2), ("write_db", 1)]);
+        assert!(report.memory_pages > 0);
+
+        // every call starts counting from zero
+        call_query(&mut instance, br#"{"verifier":{}}"#)
+            .unwrap()
+            .unwrap();
+        let report = instance.resource_report();
+        assert_eq!(report.import_calls.get("read_db"), Some(&1));
+        assert_eq!(report.import_calls.get("write_db"), None);
+    }
+
+    #[test]
+    fn query_gas_limit_does_not_use_instance_gas() {
+        let mut instance = mock_instance(&CONTRACT_0_7);
+        let env = mock_env(&instance.api, "creator", &coin("1000", "earth"), &[]);
+        let msg = r#"{"verifier": "verifies", "beneficiary": "benefits"}"#.as_bytes();
+        call_init(&mut instance, &env, msg).unwrap().unwrap();
+        let expected = call_query(&mut instance, br#"{"verifier":{}}"#).unwrap();
+
+        let gas_before = instance.get_gas();
+        let res = call_query_with_gas_limit(&mut instance, br#"{"verifier":{}}"#, 10_000_000);
+        assert_eq!(res.unwrap(), expected);
+        assert_eq!(instance.get_gas(), gas_before);
+    }
+
+    #[test]
+    #[cfg(feature = "default-singlepass")]
+    fn query_gas_limit_fails_with_gas_depletion() {
+        let mut instance = mock_instance(&CONTRACT_0_7);
+        let env = mock_env(&instance.api, "creator", &coin("1000", "earth"), &[]);
+        let msg = r#"{"verifier": "verifies", "beneficiary": "benefits"}"#.as_bytes();
+        call_init(&mut instance, &env, msg).unwrap().unwrap();
+
+        let gas_before = instance.get_gas();
+        let res = call_query_with_gas_limit(&mut instance, br#"{"verifier":{}}"#, 1_000);
+        match res {
+            Err(Error::GasDepletionErr { context, .. }) => assert_eq!(context, "Query"),
+            res => panic!("Unexpected result {:?}", res),
+        }
+        assert_eq!(instance.get_gas(), gas_before);
+
+        // the instance can still be queried afterwards
+        call_query(&mut instance, br#"{"verifier":{}}"#)
+            .unwrap()
+            .unwrap();
+    }
+
+    #[test]
+    fn query_at_height_uses_snapshot() {
+        let mut instance = mock_instance(&CONTRACT_0_7);
+        let env = mock_env(&instance.api, "creator", &coin("1000", "earth"), &[]);
+        let query = br#"{"verifier":{}}"#;
+        let msg = r#"{"verifier": "verifies", "beneficiary": "benefits"}"#.as_bytes();
+        call_init(&mut instance, &env, msg).unwrap().unwrap();
+        let old = call_query(&mut instance, query).unwrap().unwrap();
+        let mut snapshot = None;
+        instance.with_storage(|store| snapshot = Some(store.clone()));
+
+        let msg = r#"{"verifier": "other", "beneficiary": "benefits"}"#.as_bytes();
+        call_init(&mut instance, &env, msg).unwrap().unwrap();
+        let current = call_query(&mut instance, query).unwrap().unwrap();
+        assert_ne!(old, current);
+
+        let res = call_query_at_height(&mut instance, 10, query, |height| {
+            assert_eq!(height, 10);
+            snapshot
+        });
+        assert_eq!(res.unwrap().unwrap(), old);
+        // the current state is back
+        assert_eq!(call_query(&mut instance, query).unwrap().unwrap(), current);
+
+        match call_query_at_height(&mut instance, 11, query, |_| None) {
+            Err(Error::HeightUnavailableErr { height, .. }) => assert_eq!(height, 11),
+            Err(e) => panic!("Unexpected error {:?}", e),
+            Ok(_) => panic!("Query without state succeeded"),
+        }
+    }
+
+    #[test]
+    fn query_chunked_streams_result() {
+        let mut instance = mock_instance(&CONTRACT_0_7);
+        let env = mock_env(&instance.api, "creator", &coin("1000", "earth"), &[]);
+        let msg = r#"{"verifier": "verifies", "beneficiary": "benefits"}"#.as_bytes();
+        call_init(&mut instance, &env, msg).unwrap().unwrap();
+        let query = br#"{"verifier":{}}"#;
+        let expected = call_query_raw(&mut instance, query).unwrap();
+
+        let mut chunks: Vec<Vec<u8>> = vec![];
+        let len = call_query_chunked(&mut instance, query, 5, |chunk| {
+            chunks.push(chunk.to_vec());
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(len, expected.len());
+        assert!(chunks.iter().all(|chunk| chunk.len() <= 5));
+        assert_eq!(chunks.concat(), expected);
+
+        // the result limit does not apply
+        instance.set_limits(Limits {
+            max_result_length: 1,
+            ..Limits::default()
+        });
+        let mut streamed = vec![];
+        call_query_chunked(&mut instance, query, 1024, |chunk| {
+            streamed.extend_from_slice(chunk);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(streamed, expected);
+        assert_eq!(instance.reclaim_allocations().unwrap().count, 0);
+    }
+
+    #[test]
+    fn gas_report_includes_host_gas() {
+        let mut instance = mock_instance(&CONTRACT_0_7);
+        let gas = GasVersion::V1.config();
+        instance.set_gas_config(gas);
+        let env = mock_env(&instance.api, "creator", &coin("1000", "earth"), &[]);
+        let msg = r#"{"verifier": "verifies", "beneficiary": "benefits"}"#.as_bytes();
+        call_init(&mut instance, &env, msg).unwrap().unwrap();
+
+        let mut config = Vec::new();
+        instance.with_storage(|store| config = store.get(b"config").unwrap());
+        let report = instance.gas_report();
+        // config is new, so looking up the value it replaces finds nothing
+        assert_eq!(
+            report.storage_gas,
+            gas.write_cost(b"config", &config) + gas.read_cost(b"config", &None)
+        );
+        // verifier and beneficiary are canonicalized
+        assert_eq!(
+            report.used_externally,
+            report.storage_gas + 2 * gas.api_call
+        );
+        assert_eq!(report.remaining, instance.get_gas());
+
+        // a cheaper config applies to the next call
+        instance.set_gas_config(GasConfig {
+            read_base: 1,
+            read_per_byte: 0,
+            ..gas
+        });
+        call_query(&mut instance, br#"{"verifier":{}}"#)
+            .unwrap()
+            .unwrap();
+        let report = instance.gas_report();
+        assert_eq!(report.storage_gas, 1);
+        assert_eq!(report.used_externally, 1 + gas.api_call);
+    }
+
+    // init and handle both write foo=bar, but only init succeeds.
+    // Regions at 8 and 16 point to key and value, 24 and 32 to the results.
+    static WRITING_WAT: &'static str = r#"
+        (import "env" "write_db" (func $write_db (param i32 i32)))
+        (memory (export "memory") 1)
+        (data (i32.const 8) "\40\00\00\00\03\00\00\00")
+        (data (i32.const 16) "\48\00\00\00\03\00\00\00")
+        (data (i32.const 24) "\50\00\00\00\2b\00\00\00")
+        (data (i32.const 32) "\80\00\00\00\0e\00\00\00")
+        (data (i32.const 64) "foo")
+        (data (i32.const 72) "bar")
+        (data (i32.const 80) "{\"ok\":{\"messages\":[],\"log\":[],\"data\":null}}")
+        (data (i32.const 128) "{\"err\":\"boom\"}")
+        (func (export "init") (param i32 i32) (result i32)
+          i32.const 8
+          i32.const 16
+          call $write_db
+          i32.const 24)
+        (func (export "handle") (param i32 i32) (result i32)
+          i32.const 8
+          i32.const 16
+          call $write_db
+          i32.const 32)
+    "#;
+
+    fn writing_instance() -> Instance<MockStorage, MockApi> {
+        let wasm = wat2wasm(with_allocator(WRITING_WAT)).unwrap();
+        Instance::from_code(&wasm, dependencies(20), 1_000_000).unwrap()
+    }
+
+    #[test]
+    fn call_sudo_runs_sudo_export() {
+        let wat = WRITING_WAT.replace(r#"(export "init")"#, r#"(export "sudo")"#);
+        let wasm = wat2wasm(with_allocator(&wat)).unwrap();
+        let mut instance: Instance<MockStorage, MockApi> =
+            Instance::from_code(&wasm, dependencies(20), 1_000_000).unwrap();
+        let env = mock_env(&instance.api, "creator", &[], &[]);
+        call_sudo(&mut instance, &env, b"{}").unwrap().unwrap();
+        let mut value = None;
+        instance.with_storage(|store| value = store.get(b"foo"));
+        assert_eq!(value, Some(b"bar".to_vec()));
+
+        let mut instance = mock_instance(&CONTRACT_0_7);
+        match call_sudo(&mut instance, &env, b"{}") {
+            Err(Error::ResolveErr { .. }) => {}
+            res => panic!("Unexpected result {:?}", res),
+        }
+    }
+
+    #[test]
+    fn failed_call_does_not_write() {
+        let mut instance = writing_instance();
+        let env = mock_env(&instance.api, "creator", &[], &[]);
+        let read = |instance: &Instance<MockStorage, MockApi>| {
+            let mut value = None;
+            instance.with_storage(|store| value = store.get(b"foo"));
+            value
+        };
+
+        let res = call_handle(&mut instance, &env, b"{}").unwrap();
+        assert_eq!(res, ContractResult::Err("boom".to_string()));
+        assert_eq!(read(&instance), None);
+
+        // a failed prepared execution has nothing to commit either
+        let prepared = prepare_execute(&mut instance, &env, b"{}").unwrap();
+        assert_eq!(prepared.writes().len(), 0);
+
+        // nor does a raw call returning an error result
+        let data = call_handle_raw(&mut instance, &to_vec(&env).unwrap(), b"{}").unwrap();
+        assert_eq!(data, br#"{"err":"boom"}"#.to_vec());
+        assert_eq!(read(&instance), None);
+
+        call_init(&mut instance, &env, b"{}").unwrap().unwrap();
+        assert_eq!(read(&instance), Some(b"bar".to_vec()));
+    }
+
+    #[test]
+    fn prepared_blind_write_sees_changed_value() {
+        // handle writes foo=bar without reading foo first
+        let wat = WRITING_WAT
+            .replace(r#"(export "handle")"#, r#"(export "failing")"#)
+            .replace(r#"(export "init")"#, r#"(export "handle")"#);
+        let wasm = wat2wasm(with_allocator(&wat)).unwrap();
+        let mut instance: Instance<MockStorage, MockApi> =
+            Instance::from_code(&wasm, dependencies(20), 1_000_000).unwrap();
+        let env = mock_env(&instance.api, "creator", &[], &[]);
+        instance.with_storage(|store| store.set(b"foo", b"old"));
+        let prepared = prepare_execute(&mut instance, &env, b"{}").unwrap();
+        assert_eq!(prepared.writes().len(), 1);
+
+        // the replaced value was charged for, so it must still be the same
+        instance.with_storage(|store| store.set(b"foo", b"changed value"));
+        match commit(&mut instance, prepared) {
+            Err(Error::StateChangedErr { .. }) => {}
+            res => panic!("Unexpected result {:?}", res),
+        }
+    }
+
+    #[test]
+    fn journal_is_removed_after_writes() {
+        use crate::journal::Journal;
+        use tempfile::TempDir;
+
+        let tmp_dir = TempDir::new().unwrap();
+        let path = tmp_dir.path().join("journal");
+        let mut instance = writing_instance();
+        instance.set_journal(Journal::new(&path));
+        let env = mock_env(&instance.api, "creator", &[], &[]);
+
+        call_init(&mut instance, &env, b"{}").unwrap().unwrap();
+        let mut value = None;
+        instance.with_storage(|store| value = store.get(b"foo"));
+        assert_eq!(value, Some(b"bar".to_vec()));
+        assert!(!path.exists());
+        assert!(instance.take_journal().is_some());
+    }
+
+    #[test]
+    fn oversized_data_aborts_call() {
+        let mut instance = writing_instance();
+        let env = mock_env(&instance.api, "creator", &[], &[]);
+        let limits = Limits::default();
+
+        // the value is 3 bytes
+        instance.set_limits(Limits {
+            max_value_length: 2,
+            ..limits
+        });
+        match call_init(&mut instance, &env, b"{}") {
+            Err(Error::ValueTooLargeErr {
+                kind, size, limit, ..
+            }) => {
+                assert_eq!(kind, "Value");
+                assert_eq!(size, 3);
+                assert_eq!(limit, 2);
+            }
+            Err(e) => panic!("Unexpected error {:?}", e),
+            Ok(_) => panic!("Oversized value was accepted"),
+        }
+        let mut value = None;
+        instance.with_storage(|store| value = store.get(b"foo"));
+        assert_eq!(value, None);
+
+        // the ok result is 43 bytes
+        instance.set_limits(Limits {
+            max_result_length: 42,
+            ..limits
+        });
+        match call_init(&mut instance, &env, b"{}") {
+            Err(Error::ValueTooLargeErr { kind, .. }) => assert_eq!(kind, "Result"),
+            Err(e) => panic!("Unexpected error {:?}", e),
+            Ok(_) => panic!("Oversized result was accepted"),
+        }
+
+        instance.set_limits(limits);
+        call_init(&mut instance, &env, b"{}").unwrap().unwrap();
+    }
+
+    // init fills the 8 bytes at 72 with random and stores them under "rnd".
+    // Regions at 8 and 16 point to key and buffer, 24 to the result.
+    static RANDOM_WAT: &'static str = r#"
+        (import "env" "write_db" (func $write_db (param i32 i32)))
+        (import "env" "random" (func $random (param i32) (result i32)))
+        (memory (export "memory") 1)
+        (data (i32.const 8) "\40\00\00\00\03\00\00\00")
+        (data (i32.const 16) "\48\00\00\00\08\00\00\00")
+        (data (i32.const 24) "\50\00\00\00\2b\00\00\00")
+        (data (i32.const 64) "rnd")
+        (data (i32.const 72) "--------")
+        (data (i32.const 80) "{\"ok\":{\"messages\":[],\"log\":[],\"data\":null}}")
+        (func (export "init") (param i32 i32) (result i32)
+          i32.const 16
+          call $random
+          drop
+          i32.const 8
+          i32.const 16
+          call $write_db
+          i32.const 24)
+        (func (export "handle") (param i32 i32) (result i32)
+          i32.const 24)
+    "#;
+
+    #[test]
+    fn random_depends_on_block_entropy() {
+        let wasm = wat2wasm(with_allocator(RANDOM_WAT)).unwrap();
+        let mut instance: Instance<MockStorage, MockApi> =
+            Instance::from_code(&wasm, dependencies(20), 1_000_000).unwrap();
+        let mut env = mock_env(&instance.api, "creator", &[], &[]);
+        let init = |instance: &mut Instance<MockStorage, MockApi>, env: &Env| {
+            call_init(instance, env, b"{}").unwrap().unwrap();
+            let mut value = None;
+            instance.with_storage(|store| value = store.get(b"rnd"));
+            value.unwrap()
+        };
+
+        // without entropy the buffer is left alone
+        assert_eq!(init(&mut instance, &env), b"--------".to_vec());
+
+        env.block.entropy = Some(mock_entropy(b"block 12345"));
+        let first = init(&mut instance, &env);
+        let mut expected = RandomState::new(
+            env.block.entropy.as_ref().unwrap().as_slice(),
+            env.contract.address.as_slice(),
+            0,
+        );
+        assert_eq!(first, expected.fill(8));
+        // every node gets the same bytes for the same call
+        assert_eq!(init(&mut instance, &env), first);
+
+        // the next call to the contract in the same block gets other bytes
+        instance.set_call_index(1);
+        let second = init(&mut instance, &env);
+        assert_ne!(second, first);
+        instance.set_call_index(0);
+
+        env.block.entropy = Some(mock_entropy(b"block 12346"));
+        assert_ne!(init(&mut instance, &env), first);
+    }
+
+    static CHECK_GAS_WAT: &'static str = r#"
+        (import "env" "check_gas" (func $check_gas (param i32) (result i32)))
+        (memory (export "memory") 1)
+        (data (i32.const 8) "\40\00\00\00\10\00\00\00")
+        (func (export "init") (param i32 i32) (result i32)
+          i32.const 8
+          call $check_gas
+          drop
+          i32.const 8)
+        (func (export "handle") (param i32 i32) (result i32)
+          i32.const 8)
+    "#;
+
+    #[test]
+    fn check_gas_reports_gas_and_progress() {
+        let wasm = wat2wasm(with_allocator(CHECK_GAS_WAT)).unwrap();
+        let mut instance: Instance<MockStorage, MockApi> =
+            Instance::from_code(&wasm, dependencies(20), 1_000_000).unwrap();
+        instance.set_gas_version(GasVersion::V1);
+        let gas_before = instance.get_gas();
+        let data = call_init_raw(&mut instance, b"{}", b"{}").unwrap();
+        assert_eq!(data.len(), 16);
+        let mut gas_left = [0u8; 8];
+        let mut progress = [0u8; 8];
+        gas_left.copy_from_slice(&data[..8]);
+        progress.copy_from_slice(&data[8..]);
+        let gas_left = u64::from_be_bytes(gas_left);
+        let progress = u64::from_be_bytes(progress);
+
+        // the call itself is charged
+        assert!(progress >= GasVersion::V1.config().check_gas);
+        assert!(gas_left <= gas_before);
+        assert!(gas_left >= instance.get_gas());
+        assert_eq!(instance.resource_report().import_calls["check_gas"], 1);
+    }
+
+    #[test]
+    fn call_handle_readonly_previews_handle() {
+        let mut instance = mock_instance(&CONTRACT_0_7);
+        let env = mock_env(&instance.api, "creator", &coin("1000", "earth"), &[]);
+        let msg = r#"{"verifier": "verifies", "beneficiary": "benefits"}"#.as_bytes();
+        call_init(&mut instance, &env, msg).unwrap().unwrap();
+
+        // release only reads the config
+        let env = mock_env(
+            &instance.api,
+            "verifies",
+            &coin("15", "earth"),
+            &coin("1015", "earth"),
+        );
+        let msg = br#"{"release":{}}"#;
+        let preview = call_handle_readonly(&mut instance, &env, msg).unwrap();
+        assert_eq!(preview.clone().unwrap().messages.len(), 1);
+        let res = call_handle(&mut instance, &env, msg).unwrap();
+        assert_eq!(preview, res);
+    }
+
+    // counts the single reads and the batches reaching the storage
+    struct CountingStorage {
+        inner: MockStorage,
+        gets: Rc<Cell<u32>>,
+        batches: Rc<Cell<u32>>,
+    }
+
+    impl ReadonlyStorage for CountingStorage {
+        fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+            self.gets.set(self.gets.get() + 1);
+            self.inner.get(key)
+        }
+
+        fn get_many(&self, keys: &[Vec<u8>]) -> Vec<Option<Vec<u8>>> {
+            self.batches.set(self.batches.get() + 1);
+            keys.iter().map(|key| self.inner.get(key)).collect()
+        }
+    }
+
+    impl Storage for CountingStorage {
+        fn set(&mut self, key: &[u8], value: &[u8]) {
+            self.inner.set(key, value)
+        }
+    }
+
+    #[test]
+    fn call_handle_with_hints_prefetches_reads() {
+        let gets = Rc::new(Cell::new(0));
+        let batches = Rc::new(Cell::new(0));
+        let deps = Extern {
+            storage: CountingStorage {
+                inner: MockStorage::new(),
+                gets: gets.clone(),
+                batches: batches.clone(),
+            },
+            api: MockApi::new(20),
+        };
+        let mut instance = Instance::from_code(CONTRACT_0_7, deps, 1_000_000).unwrap();
+        let env = mock_env(&instance.api, "creator", &coin("1000", "earth"), &[]);
+        let msg = br#"{"verifier": "verifies", "beneficiary": "benefits"}"#;
+        call_init(&mut instance, &env, msg).unwrap().unwrap();
+
+        // release only reads the config
+        let env = mock_env(
+            &instance.api,
+            "verifies",
+            &coin("15", "earth"),
+            &coin("1015", "earth"),
+        );
+        let msg = br#"{"release":{}}"#;
+        let gets_before = gets.get();
+        let expected = call_handle(&mut instance, &env, msg).unwrap();
+        let expected_gas = instance.gas_report();
+        assert_eq!(gets.get(), gets_before + 1);
+
+        let hints = vec![b"config".to_vec(), b"unused".to_vec()];
+        let res = call_handle_with_hints(&mut instance, &env, msg, &hints).unwrap();
+        assert_eq!(res, expected);
+        assert_eq!(instance.gas_report(), expected_gas);
+        assert_eq!(gets.get(), gets_before + 1);
+        assert_eq!(batches.get(), 1);
+
+        // hints only apply to their call
+        call_handle(&mut instance, &env, msg).unwrap();
+        assert_eq!(gets.get(), gets_before + 2);
+
+        // blocked contracts are refused before prefetching
+        let mut policy = BlockList::new();
+        policy.block_contract(&env.contract.address);
+        instance.set_execution_policy(Box::new(policy));
+        match call_handle_with_hints(&mut instance, &env, msg, &hints) {
+            Err(Error::PolicyErr { .. }) => {}
+            res => panic!("Unexpected result {:?}", res),
+        }
+        assert_eq!(batches.get(), 1);
+    }
+
+    #[test]
+    fn call_handle_batch_runs_every_message() {
+        let mut instance = mock_instance(&CONTRACT_0_7);
+        let env = mock_env(&instance.api, "creator", &coin("1000", "earth"), &[]);
+        let msg = br#"{"verifier": "verifies", "beneficiary": "benefits"}"#;
+        call_init(&mut instance, &env, msg).unwrap().unwrap();
+
+        let env = mock_env(
+            &instance.api,
+            "verifies",
+            &coin("15", "earth"),
+            &coin("1015", "earth"),
+        );
+        let release: &[u8] = br#"{"release":{}}"#;
+        let results = call_handle_batch(&mut instance, &env, &[release, b"{}", release]);
+        assert_eq!(results.len(), 3);
+        assert_eq!(
+            results[0].as_ref().unwrap().clone().unwrap().messages.len(),
+            1
+        );
+        assert!(results[1].as_ref().unwrap().is_err());
+        // the failed message does not stop the batch
+        assert_eq!(results[2].as_ref().unwrap(), results[0].as_ref().unwrap());
+        assert!(call_handle_batch(&mut instance, &env, &[]).is_empty());
+    }
+
+    #[test]
+    fn call_handle_readonly_rejects_writes() {
+        let wasm = wat2wasm(with_allocator(WRITE_WAT)).unwrap();
+        let mut instance: Instance<MockStorage, MockApi> =
+            Instance::from_code(&wasm, dependencies(20), 1_000_000).unwrap();
+        let env = mock_env(&instance.api, "creator", &[], &[]);
+        match call_handle_readonly(&mut instance, &env, b"{}") {
+            Err(Error::ReadOnlyErr { .. }) => {}
+            Err(e) => panic!("unexpected error, {:?}", e),
+            Ok(_) => panic!("write must be rejected"),
+        }
+        instance.with_storage(|store| assert_eq!(store.get(b"key"), None));
+
+        // later calls can write again
+        call_handle_raw(&mut instance, b"{}", b"{}").unwrap();
+        instance.with_storage(|store| assert_eq!(store.get(b"key"), Some(b"value".to_vec())));
+    }
+
+    // init passes the single byte at 64 as every list of ed25519_batch_verify,
+    // which does not decode. Region 8 points to it and is also the result.
+    static BATCH_VERIFY_WAT: &'static str = r#"
+        (import "env" "ed25519_batch_verify" (func $ed25519_batch_verify (param i32 i32 i32) (result i32)))
+        (memory (export "memory") 1)
+        (data (i32.const 8) "\40\00\00\00\01\00\00\00")
+        (data (i32.const 64) "\ff")
+        (func (export "init") (param i32 i32) (result i32)
+          i32.const 8
+          i32.const 8
+          i32.const 8
+          call $ed25519_batch_verify
+          drop
+          i32.const 8)
+        (func (export "handle") (param i32 i32) (result i32)
+          i32.const 8)
+    "#;
+
+    #[test]
+    fn malformed_batch_verify_is_charged() {
+        let wasm = wat2wasm(with_allocator(BATCH_VERIFY_WAT)).unwrap();
+        let mut instance: Instance<MockStorage, MockApi> =
+            Instance::from_code(&wasm, dependencies(20), 1_000_000).unwrap();
+        instance.set_gas_version(GasVersion::V1);
+        call_init_raw(&mut instance, b"{}", b"{}").unwrap();
+        assert_eq!(
+            instance.gas_report().used_externally,
+            GasVersion::V1.config().ed25519_batch_verify_per_signature
+        );
+    }
+
+    static WRITE_WAT: &'static str = r#"
+        (import "env" "write_db" (func $write_db (param i32 i32)))
+        (memory (export "memory") 1)
+        (data (i32.const 8) "\40\00\00\00\03\00\00\00")
+        (data (i32.const 16) "\48\00\00\00\05\00\00\00")
+        (data (i32.const 64) "key")
+        (data (i32.const 72) "value")
+        (func (export "init") (param i32 i32) (result i32)
+          i32.const 8)
+        (func (export "handle") (param i32 i32) (result i32)
+          i32.const 8
+          i32.const 16
+          call $write_db
+          i32.const 8)
+    "#;
+
+    // init calls the host function "oracle" with payload "ping" and returns
+    // the 8 byte result region at 24, which is "--------" if nothing was written.
+    static HOST_CALL_WAT: &'static str = r#"
+        (import "env" "host_call" (func $host_call (param i32 i32 i32) (result i32)))
+        (memory (export "memory") 1)
+        (data (i32.const 8) "\40\00\00\00\06\00\00\00")
+        (data (i32.const 16) "\48\00\00\00\04\00\00\00")
+        (data (i32.const 24) "\50\00\00\00\08\00\00\00")
+        (data (i32.const 64) "oracle")
+        (data (i32.const 72) "ping")
+        (data (i32.const 80) "--------")
+        (func (export "init") (param i32 i32) (result i32)
+          i32.const 8
+          i32.const 16
+          i32.const 24
+          call $host_call
+          drop
+          i32.const 24)
+        (func (export "handle") (param i32 i32) (result i32)
+          i32.const 24)
+    "#;
+
+    #[test]
+    fn host_call_runs_allowed_functions() {
+        let wasm = wat2wasm(with_allocator(HOST_CALL_WAT)).unwrap();
+        let mut instance: Instance<MockStorage, MockApi> =
+            Instance::from_code(&wasm, dependencies(20), 1_000_000).unwrap();
+        let oracle = || -> HostFunction {
+            Box::new(|payload| {
+                assert_eq!(payload, b"ping");
+                Ok((b"response".to_vec(), 500))
+            })
+        };
+
+        // without functions nothing is written
+        let data = call_init_raw(&mut instance, b"{}", b"{}").unwrap();
+        assert_eq!(data, b"--------".to_vec());
+
+        // registered but not allowed
+        let mut functions = HostFunctions::new();
+        functions.register("oracle", oracle());
+        instance.set_host_functions(functions);
+        let data = call_init_raw(&mut instance, b"{}", b"{}").unwrap();
+        assert_eq!(data, b"--------".to_vec());
+
+        let mut functions = HostFunctions::new();
+        functions.register("oracle", oracle());
+        functions.set_allowed(&["oracle"]);
+        instance.set_host_functions(functions);
+        let data = call_init_raw(&mut instance, b"{}", b"{}").unwrap();
+        assert_eq!(data, b"response".to_vec());
+        assert_eq!(instance.gas_report().used_externally, 500);
+        assert_eq!(instance.resource_report().import_calls["host_call"], 1);
+
+        // errors abort the call
+        let mut functions = HostFunctions::new();
+        functions.register(
+            "oracle",
+            Box::new(|_| {
+                HostCallErr {
+                    name: "oracle",
+                    msg: "no price",
+                }
+                .fail()
+            }),
+        );
+        functions.set_allowed(&["oracle"]);
+        instance.set_host_functions(functions);
+        match call_init_raw(&mut instance, b"{}", b"{}") {
+            Err(Error::HostCallErr { name, msg, .. }) => {
+                assert_eq!(name, "oracle");
+                assert_eq!(msg, "no price");
+            }
+            Err(e) => panic!("Unexpected error {:?}", e),
+            Ok(_) => panic!("Failed host function did not abort"),
+        }
+    }
+
+    #[test]
+    fn host_call_has_own_limits() {
+        let wasm = wat2wasm(with_allocator(HOST_CALL_WAT)).unwrap();
+        let mut instance: Instance<MockStorage, MockApi> =
+            Instance::from_code(&wasm, dependencies(20), 1_000_000).unwrap();
+        let mut functions = HostFunctions::new();
+        functions.register("oracle", Box::new(|_| Ok((b"response".to_vec(), 0))));
+        functions.set_allowed(&["oracle"]);
+        instance.set_host_functions(functions);
+        let limits = Limits::default();
+
+        // storage limits do not apply
+        instance.set_limits(Limits {
+            max_key_length: 1,
+            max_value_length: 1,
+            ..limits
+        });
+        let data = call_init_raw(&mut instance, b"{}", b"{}").unwrap();
+        assert_eq!(data, b"response".to_vec());
+
+        // the name is 6 bytes, the payload 4
+        let cases = vec![
+            (
+                Limits {
+                    max_host_call_name: 5,
+                    ..limits
+                },
+                "Name",
+                6,
+            ),
+            (
+                Limits {
+                    max_host_call_payload: 3,
+                    ..limits
+                },
+                "Payload",
+                4,
+            ),
+        ];
+        for (limits, expected, expected_size) in cases {
+            instance.set_limits(limits);
+            match call_init_raw(&mut instance, b"{}", b"{}") {
+                Err(Error::ValueTooLargeErr { kind, size, .. }) => {
+                    assert_eq!(kind, expected);
+                    assert_eq!(size, expected_size);
+                }
+                res => panic!("Unexpected result {:?}", res),
+            }
+        }
+    }
+}
@@ -1,6 +1,14 @@
-use parity_wasm::elements::{Deserialize, Module};
+use parity_wasm::elements::{
+    Deserialize, External, FunctionType, ImportCountType, Instruction, Internal, Module, Type,
+    ValueType,
+};
 
-use crate::errors::{Result, ValidationErr};
+use crate::backends::backend;
+use crate::cache::CacheOptions;
+use crate::errors::{
+    CodeTooLargeErr, FloatsNotAllowedErr, MemoryTooLargeErr, Result, TooManyFunctionsErr,
+    ValidationErr,
+};
 
 /// Lists all imports we provide upon instantiating the instance in Instance::from_module()
 /// This should be updated when new imports are added
@@ -9,6 +17,14 @@ static SUPPORTED_IMPORTS: &[&str] = &[
     "env.write_db",
     "env.canonicalize_address",
     "env.humanize_address",
+    "env.secp256k1_verify",
+    "env.secp256k1_recover_pubkey",
+    "env.ed25519_verify",
+    "env.ed25519_batch_verify",
+    "env.debug_print",
+    "env.random",
+    "env.check_gas",
+    "env.host_call",
 ];
 
 /// Lists all entry points we expect to be present when calling a contract.
@@ -23,9 +39,232 @@ static REQUIRED_EXPORTS: &[&str] = &[
     "cosmwasm_api_0_6",
 ];
 
+/// The API version marker export of the contracts this VM runs
+static API_MARKER: &str = "cosmwasm_api_0_6";
+
+/// Prefixes of the version marker exports used by newer cosmwasm releases.
+/// These contracts use different imports and entry point signatures, which are not supported.
+static NEWER_API_MARKER_PREFIXES: &[&str] = &["cosmwasm_vm_version_", "interface_version_"];
+
+/// Entry points a contract can export to be called by the VM
+static ENTRY_POINTS: &[&str] = &["init", "handle", "query", "sudo"];
+
+/// Rough number of bytes of machine code produced by the compiler per wasm instruction.
+/// Only used to give an order of magnitude in CodeAnalysis.
+static ESTIMATED_BYTES_PER_INSTRUCTION: usize = 12;
+
+/// How code using floating point types or instructions is treated, selected per chain
+/// with CacheOptions::float_policy
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FloatPolicy {
+    /// Code using floats is stored. The singlepass backend still refuses to compile float
+    /// instructions, so this only lets them run with cranelift. Wasm defines float arithmetic
+    /// by IEEE 754, so results are the same on every node, except for the bit pattern of NaN
+    /// results, which depends on the CPU and compiler. Contracts must not store, hash or
+    /// reinterpret NaNs to stay in consensus.
+    Allow,
+    /// Code using floats is rejected when it is stored, naming the functions that use them
+    Reject,
+}
+
+/// A report about a wasm blob, generated without compiling or instantiating it
+#[derive(Debug, Clone, PartialEq)]
+pub struct CodeAnalysis {
+    /// entry points from ENTRY_POINTS which are exported by the contract
+    pub entry_points: Vec<String>,
+    /// all exports, including the entry points
+    pub exports: Vec<String>,
+    /// required exports that are missing
+    pub missing_exports: Vec<String>,
+    /// all imports as "<module>.<name>"
+    pub imports: Vec<String>,
+    /// imports the VM does not provide
+    pub unsupported_imports: Vec<String>,
+    /// number of functions defined in the contract (not counting imports)
+    pub function_count: usize,
+    /// number of floating point instructions in all function bodies
+    pub float_instructions: usize,
+    /// true if any function signature, local or global uses a float type
+    pub float_types: bool,
+    /// defined functions using float instructions, locals or parameters, by export name
+    /// or as "function <index>"
+    pub float_functions: Vec<String>,
+    /// initial memory size in pages of 64 KiB, if the contract has a memory
+    pub memory_initial_pages: Option<u32>,
+    /// maximum memory size in pages of 64 KiB, if the contract sets one
+    pub memory_maximum_pages: Option<u32>,
+    /// total number of instructions in all function bodies
+    pub instruction_count: usize,
+    /// very rough estimate of the size of the compiled module in bytes
+    pub estimated_compiled_size: usize,
+}
+
+impl CodeAnalysis {
+    pub fn uses_floats(&self) -> bool {
+        self.float_instructions > 0 || self.float_types
+    }
+
+    /// true if check_api_compatibility would accept this code
+    pub fn is_compatible(&self) -> bool {
+        self.missing_exports.is_empty() && self.unsupported_imports.is_empty()
+    }
+}
+
+/// Identifies this VM build, so node tooling can check it runs the expected version
+#[derive(Debug, Clone, PartialEq)]
+pub struct VmInfo {
+    /// version of the cosmwasm-vm crate
+    pub version: &'static str,
+    /// compiler backend, "cranelift" or "singlepass"
+    pub backend: &'static str,
+    /// the API marker export contracts must have
+    pub api_marker: &'static str,
+    /// imports provided to contracts, as "<module>.<name>"
+    pub supported_imports: Vec<&'static str>,
+}
+
+pub fn vm_info() -> VmInfo {
+    VmInfo {
+        version: env!("CARGO_PKG_VERSION"),
+        backend: backend(),
+        api_marker: API_MARKER,
+        supported_imports: SUPPORTED_IMPORTS.to_vec(),
+    }
+}
+
+/// Name of the custom section a contract can embed its JSON schema in
+pub static SCHEMA_SECTION: &str = "cosmwasm_schema";
+
+/// What a contract offers to callers, read without executing it
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContractDescription {
+    /// entry points from ENTRY_POINTS which are exported by the contract
+    pub entry_points: Vec<String>,
+    /// content of the SCHEMA_SECTION custom section, if the contract has one
+    pub schema: Option<String>,
+}
+
+pub fn describe_code(wasm_code: &[u8]) -> Result<ContractDescription> {
+    let module = deserialize_wasm(wasm_code)?;
+    let exports = export_names(&module);
+    let entry_points = entry_points(&exports);
+    let schema = match module
+        .custom_sections()
+        .find(|section| section.name() == SCHEMA_SECTION)
+    {
+        Some(section) => match String::from_utf8(section.payload().to_vec()) {
+            Ok(schema) => Some(schema),
+            Err(_) => {
+                return ValidationErr {
+                    msg: format!("Custom section {} is not valid UTF-8", SCHEMA_SECTION),
+                }
+                .fail()
+            }
+        },
+        None => None,
+    };
+    Ok(ContractDescription {
+        entry_points,
+        schema,
+    })
+}
+
+/// Inspects wasm code before it is stored, so incompatible contracts can be rejected
+/// with a precise reason instead of failing at instantiation
+pub fn analyze_code(wasm_code: &[u8]) -> Result<CodeAnalysis> {
+    let module = deserialize_wasm(wasm_code)?;
+
+    let exports = export_names(&module);
+    let imports = import_names(&module);
+    let entry_points = entry_points(&exports);
+    let missing_exports = REQUIRED_EXPORTS
+        .iter()
+        .filter(|name| !exports.iter().any(|x| x == *name))
+        .map(|name| name.to_string())
+        .collect();
+    let unsupported_imports = imports
+        .iter()
+        .filter(|name| !SUPPORTED_IMPORTS.contains(&name.as_str()))
+        .cloned()
+        .collect();
+
+    let bodies = match module.code_section() {
+        Some(code_section) => code_section.bodies(),
+        None => &[],
+    };
+    let types = match module.type_section() {
+        Some(type_section) => type_section.types(),
+        None => &[],
+    };
+    let functions = match module.function_section() {
+        Some(function_section) => function_section.entries(),
+        None => &[],
+    };
+    let imported_functions = module.import_count(ImportCountType::Function);
+    let mut instruction_count = 0;
+    let mut float_instructions = 0;
+    let mut float_types = false;
+    let mut float_functions = Vec::new();
+    for (i, body) in bodies.iter().enumerate() {
+        let instructions = body.code().elements();
+        instruction_count += instructions.len();
+        let floats = count_floats(instructions);
+        float_instructions += floats;
+        let float_locals = body
+            .locals()
+            .iter()
+            .any(|local| is_float_type(local.value_type()));
+        float_types |= float_locals;
+        let float_signature = match functions
+            .get(i)
+            .and_then(|func| types.get(func.type_ref() as usize))
+        {
+            Some(Type::Function(f)) => uses_float_types(f),
+            None => false,
+        };
+        if floats > 0 || float_locals || float_signature {
+            float_functions.push(function_name(&module, imported_functions + i));
+        }
+    }
+    float_types |= types.iter().any(|Type::Function(f)| uses_float_types(f));
+    if let Some(global_section) = module.global_section() {
+        float_types |= global_section
+            .entries()
+            .iter()
+            .any(|g| is_float_type(g.global_type().content_type()));
+    }
+
+    let (memory_initial_pages, memory_maximum_pages) = memory_limits(&module);
+
+    Ok(CodeAnalysis {
+        entry_points,
+        exports,
+        missing_exports,
+        imports,
+        unsupported_imports,
+        function_count: bodies.len(),
+        float_instructions,
+        float_types,
+        float_functions,
+        memory_initial_pages,
+        memory_maximum_pages,
+        instruction_count,
+        estimated_compiled_size: instruction_count * ESTIMATED_BYTES_PER_INSTRUCTION,
+    })
+}
+
 pub fn check_api_compatibility(wasm_code: &[u8]) -> Result<()> {
-    let mut reader = std::io::Cursor::new(wasm_code);
-    let module = Module::deserialize(&mut reader).unwrap();
+    let module = deserialize_wasm(wasm_code)?;
+    // check this first, as such contracts fail all other checks with less helpful messages
+    if let Some(marker) = find_newer_api_marker(&module) {
+        return ValidationErr {
+            msg: format!(
+                "Wasm contract was built for a newer cosmwasm API (marker export \"{}\"). This VM only runs contracts exporting \"{}\".",
+                marker, API_MARKER
+            ),
+        }
+        .fail();
+    }
     if let Some(missing) = find_missing_import(&module, SUPPORTED_IMPORTS) {
         return ValidationErr {
             msg: format!(
@@ -47,46 +286,239 @@ pub fn check_api_compatibility(wasm_code: &[u8]) -> Result<()> {
     Ok(())
 }
 
-/// Checks if the import requirements of the contract are satisfied.
-/// When this is not the case, we either have an incompatibility between contract and VM
-/// or a error in the contract.
-fn find_missing_import(module: &Module, supported_imports: &[&str]) -> Option<String> {
-    let required_imports: Vec<String> = match module.import_section() {
-        Some(import_section) => Vec::from(import_section.entries())
+/// Rejects code exceeding the resource limits set in options
+pub fn check_code_limits(wasm_code: &[u8], options: &CacheOptions) -> Result<()> {
+    if wasm_code.len() > options.max_wasm_size {
+        return CodeTooLargeErr {
+            size: wasm_code.len(),
+            limit: options.max_wasm_size,
+        }
+        .fail();
+    }
+    let analysis = analyze_code(wasm_code)?;
+    if analysis.function_count > options.max_function_count {
+        return TooManyFunctionsErr {
+            count: analysis.function_count,
+            limit: options.max_function_count,
+        }
+        .fail();
+    }
+    // the maximum, if set, bounds memory at runtime; otherwise only the initial size is known
+    let pages = analysis
+        .memory_maximum_pages
+        .or(analysis.memory_initial_pages)
+        .unwrap_or(0);
+    if pages > options.max_memory_pages {
+        return MemoryTooLargeErr {
+            pages,
+            limit: options.max_memory_pages,
+        }
+        .fail();
+    }
+    if options.float_policy == FloatPolicy::Reject && analysis.uses_floats() {
+        let mut locations = analysis.float_functions;
+        if locations.is_empty() {
+            locations.push("globals or unused signatures".to_string());
+        }
+        return FloatsNotAllowedErr { locations }.fail();
+    }
+    Ok(())
+}
+
+fn entry_points(exports: &[String]) -> Vec<String> {
+    ENTRY_POINTS
+        .iter()
+        .filter(|name| exports.iter().any(|x| x == *name))
+        .map(|name| name.to_string())
+        .collect()
+}
+
+fn deserialize_wasm(wasm_code: &[u8]) -> Result<Module> {
+    let mut reader = std::io::Cursor::new(wasm_code);
+    match Module::deserialize(&mut reader) {
+        Ok(module) => Ok(module),
+        Err(err) => ValidationErr {
+            msg: format!("Wasm bytecode could not be deserialized: {}", err),
+        }
+        .fail(),
+    }
+}
+
+fn import_names(module: &Module) -> Vec<String> {
+    match module.import_section() {
+        Some(import_section) => import_section
+            .entries()
             .iter()
             .map(|entry| format!("{}.{}", entry.module(), entry.field()))
             .collect(),
         None => vec![],
-    };
-
-    for required_import in required_imports {
-        if !supported_imports.contains(&required_import.as_str()) {
-            return Some(required_import);
-        }
     }
-    None
 }
 
-fn find_missing_export(module: &Module, required_exports: &[&str]) -> Option<String> {
-    let available_exports: Vec<String> = match module.export_section() {
-        Some(export_section) => Vec::from(export_section.entries())
+fn export_names(module: &Module) -> Vec<String> {
+    match module.export_section() {
+        Some(export_section) => export_section
+            .entries()
             .iter()
-            .map(|entry| format!("{}", entry.field()))
+            .map(|entry| entry.field().to_string())
             .collect(),
         None => vec![],
-    };
+    }
+}
 
-    for required_export in required_exports {
-        if !available_exports.iter().any(|x| x == required_export) {
-            return Some(String::from(*required_export));
+/// Returns the limits of the memory defined or imported by the module
+fn memory_limits(module: &Module) -> (Option<u32>, Option<u32>) {
+    if let Some(memory_section) = module.memory_section() {
+        if let Some(memory) = memory_section.entries().first() {
+            return (Some(memory.limits().initial()), memory.limits().maximum());
+        }
+    }
+    if let Some(import_section) = module.import_section() {
+        for entry in import_section.entries() {
+            if let External::Memory(memory) = entry.external() {
+                return (Some(memory.limits().initial()), memory.limits().maximum());
+            }
         }
     }
-    None
+    (None, None)
+}
+
+fn is_float_type(value_type: ValueType) -> bool {
+    value_type == ValueType::F32 || value_type == ValueType::F64
+}
+
+fn uses_float_types(func: &FunctionType) -> bool {
+    func.params()
+        .iter()
+        .cloned()
+        .chain(func.return_type())
+        .any(is_float_type)
+}
+
+// the export name of the function at index, which counts imported functions first
+fn function_name(module: &Module, index: usize) -> String {
+    module
+        .export_section()
+        .and_then(|section| {
+            section
+                .entries()
+                .iter()
+                .find(|entry| match entry.internal() {
+                    Internal::Function(func) => *func as usize == index,
+                    _ => false,
+                })
+        })
+        .map(|entry| entry.field().to_string())
+        .unwrap_or_else(|| format!("function {}", index))
+}
+
+fn count_floats(instructions: &[Instruction]) -> usize {
+    let mut count = 0;
+    for instruction in instructions {
+        match instruction {
+            Instruction::F32Load(..)
+            | Instruction::F64Load(..)
+            | Instruction::F32Store(..)
+            | Instruction::F64Store(..)
+            | Instruction::F32Const(..)
+            | Instruction::F64Const(..)
+            | Instruction::F32Eq
+            | Instruction::F32Ne
+            | Instruction::F32Lt
+            | Instruction::F32Gt
+            | Instruction::F32Le
+            | Instruction::F32Ge
+            | Instruction::F64Eq
+            | Instruction::F64Ne
+            | Instruction::F64Lt
+            | Instruction::F64Gt
+            | Instruction::F64Le
+            | Instruction::F64Ge
+            | Instruction::F32Abs
+            | Instruction::F32Neg
+            | Instruction::F32Ceil
+            | Instruction::F32Floor
+            | Instruction::F32Trunc
+            | Instruction::F32Nearest
+            | Instruction::F32Sqrt
+            | Instruction::F32Add
+            | Instruction::F32Sub
+            | Instruction::F32Mul
+            | Instruction::F32Div
+            | Instruction::F32Min
+            | Instruction::F32Max
+            | Instruction::F32Copysign
+            | Instruction::F64Abs
+            | Instruction::F64Neg
+            | Instruction::F64Ceil
+            | Instruction::F64Floor
+            | Instruction::F64Trunc
+            | Instruction::F64Nearest
+            | Instruction::F64Sqrt
+            | Instruction::F64Add
+            | Instruction::F64Sub
+            | Instruction::F64Mul
+            | Instruction::F64Div
+            | Instruction::F64Min
+            | Instruction::F64Max
+            | Instruction::F64Copysign
+            | Instruction::I32TruncSF32
+            | Instruction::I32TruncUF32
+            | Instruction::I32TruncSF64
+            | Instruction::I32TruncUF64
+            | Instruction::I64TruncSF32
+            | Instruction::I64TruncUF32
+            | Instruction::I64TruncSF64
+            | Instruction::I64TruncUF64
+            | Instruction::F32ConvertSI32
+            | Instruction::F32ConvertUI32
+            | Instruction::F32ConvertSI64
+            | Instruction::F32ConvertUI64
+            | Instruction::F32DemoteF64
+            | Instruction::F64ConvertSI32
+            | Instruction::F64ConvertUI32
+            | Instruction::F64ConvertSI64
+            | Instruction::F64ConvertUI64
+            | Instruction::F64PromoteF32
+            | Instruction::I32ReinterpretF32
+            | Instruction::I64ReinterpretF64
+            | Instruction::F32ReinterpretI32
+            | Instruction::F64ReinterpretI64 => count += 1,
+            _ => (),
+        }
+    }
+    count
+}
+
+/// Checks if the import requirements of the contract are satisfied.
+/// When this is not the case, we either have an incompatibility between contract and VM
+/// or a error in the contract.
+fn find_newer_api_marker(module: &Module) -> Option<String> {
+    export_names(module).into_iter().find(|name| {
+        NEWER_API_MARKER_PREFIXES
+            .iter()
+            .any(|prefix| name.starts_with(prefix))
+    })
+}
+
+fn find_missing_import(module: &Module, supported_imports: &[&str]) -> Option<String> {
+    import_names(module)
+        .into_iter()
+        .find(|required_import| !supported_imports.contains(&required_import.as_str()))
+}
+
+fn find_missing_export(module: &Module, required_exports: &[&str]) -> Option<String> {
+    let available_exports = export_names(module);
+    required_exports
+        .iter()
+        .find(|required_export| !available_exports.iter().any(|x| x == *required_export))
+        .map(|missing| missing.to_string())
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::errors::Error;
 
     static CONTRACT_0_6: &[u8] = include_bytes!("../testdata/contract_0.6.wasm");
     static CONTRACT_0_7: &[u8] = include_bytes!("../testdata/contract_0.7.wasm");
@@ -173,6 +605,117 @@ mod test {
         }
     }
 
+    #[test]
+    fn vm_info_describes_build() {
+        let info = vm_info();
+        assert_eq!(info.version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(info.api_marker, "cosmwasm_api_0_6");
+        assert!(info.supported_imports.contains(&"env.read_db"));
+        #[cfg(feature = "default-cranelift")]
+        assert_eq!(info.backend, "cranelift");
+    }
+
+    #[test]
+    fn analyze_code_reports_contract() {
+        let analysis = analyze_code(CONTRACT_0_7).unwrap();
+        assert!(analysis.is_compatible());
+        assert_eq!(analysis.entry_points, vec!["init", "handle", "query"]);
+        assert!(analysis.exports.contains(&"cosmwasm_api_0_6".to_string()));
+        assert_eq!(analysis.imports.len(), 4);
+        assert!(analysis.function_count > 0);
+        assert!(!analysis.uses_floats());
+        assert!(analysis.memory_initial_pages.is_some());
+        assert_eq!(
+            analysis.estimated_compiled_size,
+            analysis.instruction_count * ESTIMATED_BYTES_PER_INSTRUCTION
+        );
+
+        let analysis = analyze_code(CONTRACT_0_6).unwrap();
+        assert!(!analysis.is_compatible());
+        assert_eq!(
+            analysis.unsupported_imports,
+            vec![
+                "env.c_read",
+                "env.c_write",
+                "env.c_canonical_address",
+                "env.c_human_address"
+            ]
+        );
+    }
+
+    #[test]
+    fn analyze_code_finds_floats() {
+        use wabt::wat2wasm;
+
+        static WAT_FLOATS: &'static str = r#"
+            (module
+              (func $to_float (export "to_float") (param i32) (result f32)
+                get_local 0
+                f32.convert_u/i32))
+        "#;
+        let wasm = wat2wasm(WAT_FLOATS).unwrap();
+        let analysis = analyze_code(&wasm).unwrap();
+        assert_eq!(analysis.float_instructions, 1);
+        assert!(analysis.float_types);
+        assert_eq!(analysis.float_functions, vec!["to_float".to_string()]);
+        assert!(analysis.entry_points.is_empty());
+        assert_eq!(analysis.missing_exports.len(), REQUIRED_EXPORTS.len());
+        assert_eq!(analysis.memory_initial_pages, None);
+    }
+
+    #[test]
+    fn check_code_limits_rejects_floats() {
+        use wabt::wat2wasm;
+
+        static WAT_FLOATS: &'static str = r#"
+            (module
+              (func $to_float (export "to_float") (param i32) (result f32)
+                get_local 0
+                f32.convert_u/i32)
+              (func $halve (param i32) (result i32)
+                get_local 0
+                f32.convert_u/i32
+                f32.const 0.5
+                f32.mul
+                i32.trunc_u/f32)
+              (func $add (export "add") (param i32 i32) (result i32)
+                get_local 0
+                get_local 1
+                i32.add))
+        "#;
+        let wasm = wat2wasm(WAT_FLOATS).unwrap();
+        let options = CacheOptions::default();
+        check_code_limits(&wasm, &options).unwrap();
+
+        let options = CacheOptions {
+            float_policy: FloatPolicy::Reject,
+            ..options
+        };
+        match check_code_limits(&wasm, &options) {
+            Err(Error::FloatsNotAllowedErr { locations, .. }) => {
+                assert_eq!(
+                    locations,
+                    vec!["to_float".to_string(), "function 1".to_string()]
+                )
+            }
+            Err(e) => panic!("Unexpected error {:?}", e),
+            Ok(_) => panic!("Didn't reject floats"),
+        }
+    }
+
+    #[test]
+    fn analyze_code_rejects_garbage() {
+        use crate::errors::Error;
+
+        match analyze_code(b"not wasm") {
+            Err(Error::ValidationErr { msg }) => {
+                assert!(msg.starts_with("Wasm bytecode could not be deserialized"))
+            }
+            Err(e) => panic!("Unexpected error {:?}", e),
+            Ok(_) => panic!("Didn't reject invalid wasm"),
+        }
+    }
+
     #[test]
     fn test_api_compatibility_exports() {
         use crate::errors::Error;
@@ -198,4 +741,50 @@ mod test {
             Ok(_) => panic!("Didn't reject wasm with invalid api"),
         }
     }
+
+    #[test]
+    fn test_api_compatibility_rejects_newer_api() {
+        use crate::errors::Error;
+        use wabt::wat2wasm;
+
+        static WAT_NEWER_API: &'static str = r#"
+            (module
+              (import "env" "db_read" (func $db_read (param i32) (result i32)))
+              (func (export "interface_version_8"))
+              (func (export "instantiate") (param i32 i32 i32) (result i32)
+                i32.const 0))
+        "#;
+
+        let wasm = wat2wasm(WAT_NEWER_API).unwrap();
+        match check_api_compatibility(&wasm) {
+            Err(Error::ValidationErr { msg }) => {
+                assert!(msg.starts_with(
+                    "Wasm contract was built for a newer cosmwasm API (marker export \"interface_version_8\")"
+                ));
+            }
+            Err(e) => panic!("Unexpected error {:?}", e),
+            Ok(_) => panic!("Didn't reject wasm with newer api"),
+        }
+    }
+
+    #[test]
+    fn describe_code_reads_schema_section() {
+        use parity_wasm::elements::{serialize, CustomSection, Section};
+
+        let description = describe_code(CONTRACT_0_7).unwrap();
+        assert_eq!(description.entry_points, vec!["init", "handle", "query"]);
+        assert_eq!(description.schema, None);
+
+        let mut module = deserialize_wasm(CONTRACT_0_7).unwrap();
+        let schema = r#"{"title":"InitMsg"}"#;
+        module
+            .sections_mut()
+            .push(Section::Custom(CustomSection::new(
+                SCHEMA_SECTION.to_string(),
+                schema.as_bytes().to_vec(),
+            )));
+        let wasm = serialize(module).unwrap();
+        let description = describe_code(&wasm).unwrap();
+        assert_eq!(description.schema, Some(schema.to_string()));
+    }
 }
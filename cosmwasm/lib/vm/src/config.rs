@@ -0,0 +1,199 @@
+//! VmConfig gathers the settings of a node in one place.
+//!
+//! Only node local settings, which cannot change the outcome of a call, can come from a TOML
+//! file or environment variables. Both sources use the same flat keys, in the environment
+//! prefixed with COSMWASM_VM_ and upper case, eg. `cache_size = 500` or
+//! `COSMWASM_VM_CACHE_SIZE=500`. Settings that are not given keep their defaults.
+//!
+//! The limits and code options decide which code is accepted and which calls fail, so every
+//! node of a chain needs the same. The embedder sets them from the chain parameters.
+use std::time::Duration;
+
+use cosmwasm::traits::{Api, Storage};
+
+use crate::cache::CacheOptions;
+use crate::errors::{ConfigErr, Result};
+use crate::instance::Instance;
+use crate::limits::Limits;
+
+/// Prefix of the environment variables read by VmConfig::from_env
+pub static ENV_PREFIX: &str = "COSMWASM_VM_";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VmConfig {
+    /// instances kept in memory by CosmCache, 0 disables the instance cache
+    pub cache_size: usize,
+    /// from the chain parameters, not settable through set
+    pub cache_options: CacheOptions,
+    /// from the chain parameters, not settable through set
+    pub limits: Limits,
+    /// see Instance::set_slow_call_threshold
+    pub slow_call_threshold: Option<Duration>,
+}
+
+impl Default for VmConfig {
+    fn default() -> Self {
+        VmConfig {
+            cache_size: 100,
+            cache_options: CacheOptions::default(),
+            limits: Limits::default(),
+            slow_call_threshold: None,
+        }
+    }
+}
+
+impl VmConfig {
+    /// Reads a TOML document of keys and values. Unknown keys are an error, to catch typos.
+    pub fn from_toml(document: &str) -> Result<Self> {
+        let table = match document.parse::<toml::Value>() {
+            Ok(toml::Value::Table(table)) => table,
+            Ok(_) => {
+                return ConfigErr {
+                    msg: "expected a table".to_string(),
+                }
+                .fail()
+            }
+            Err(err) => {
+                return ConfigErr {
+                    msg: err.to_string(),
+                }
+                .fail()
+            }
+        };
+        let mut config = VmConfig::default();
+        for (key, value) in table.iter() {
+            let value = match value {
+                toml::Value::String(value) => value.clone(),
+                toml::Value::Integer(value) => value.to_string(),
+                toml::Value::Boolean(value) => value.to_string(),
+                _ => {
+                    return ConfigErr {
+                        msg: format!("{} must be a string, an integer or a boolean", key),
+                    }
+                    .fail()
+                }
+            };
+            config.set(key, &value)?;
+        }
+        Ok(config)
+    }
+
+    /// Reads all environment variables starting with ENV_PREFIX
+    pub fn from_env() -> Result<Self> {
+        VmConfig::from_vars(std::env::vars())
+    }
+
+    fn from_vars<I: IntoIterator<Item = (String, String)>>(vars: I) -> Result<Self> {
+        let mut config = VmConfig::default();
+        for (name, value) in vars {
+            if let Some(field) = name.strip_prefix(ENV_PREFIX) {
+                config.set(&field.to_lowercase(), &value)?;
+            }
+        }
+        Ok(config)
+    }
+
+    /// Sets the node local setting named key, parsing value
+    pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        match key {
+            "cache_size" => self.cache_size = parse(key, value)?,
+            // in milliseconds, 0 turns it off
+            "slow_call_threshold_ms" => {
+                let millis: u64 = parse(key, value)?;
+                self.slow_call_threshold = if millis > 0 {
+                    Some(Duration::from_millis(millis))
+                } else {
+                    None
+                };
+            }
+            _ => {
+                return ConfigErr {
+                    msg: format!("unknown setting {}", key),
+                }
+                .fail()
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies the settings for instances. CosmCache::from_config does this for all
+    /// instances it returns, instances created directly need it called.
+    pub fn configure<S: Storage + 'static, A: Api + 'static>(&self, instance: &mut Instance<S, A>) {
+        instance.set_limits(self.limits);
+        instance.set_slow_call_threshold(self.slow_call_threshold);
+    }
+}
+
+fn parse<T: std::str::FromStr>(key: &str, value: &str) -> Result<T> {
+    match value.trim().parse() {
+        Ok(value) => Ok(value),
+        Err(_) => ConfigErr {
+            msg: format!("invalid value {:?} for {}", value, key),
+        }
+        .fail(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::errors::Error;
+
+    #[test]
+    fn from_toml_overrides_defaults() {
+        let config = VmConfig::from_toml(
+            r#"
+            cache_size = 5
+            slow_call_threshold_ms = 250
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.cache_size, 5);
+        assert_eq!(config.slow_call_threshold, Some(Duration::from_millis(250)));
+        assert_eq!(config.limits, Limits::default());
+
+        match VmConfig::from_toml("cache_sise = 5") {
+            Err(Error::ConfigErr { msg, .. }) => assert_eq!(msg, "unknown setting cache_sise"),
+            res => panic!("Unexpected result {:?}", res),
+        }
+        match VmConfig::from_toml("cache_size = -1") {
+            Err(Error::ConfigErr { .. }) => {}
+            res => panic!("Unexpected result {:?}", res),
+        }
+    }
+
+    #[test]
+    fn chain_settings_are_not_configurable() {
+        for key in &[
+            "max_call_depth",
+            "max_import_calls",
+            "allow_floats",
+            "max_wasm_size",
+        ] {
+            match VmConfig::from_toml(&format!("{} = 1", key)) {
+                Err(Error::ConfigErr { .. }) => {}
+                res => panic!("Unexpected result {:?}", res),
+            }
+        }
+    }
+
+    #[test]
+    fn from_vars_reads_prefixed_variables() {
+        let vars = vec![
+            (
+                "COSMWASM_VM_SLOW_CALL_THRESHOLD_MS".to_string(),
+                "10".to_string(),
+            ),
+            ("COSMWASM_VM_CACHE_SIZE".to_string(), "0".to_string()),
+            ("PATH".to_string(), "/bin".to_string()),
+        ];
+        let config = VmConfig::from_vars(vars).unwrap();
+        assert_eq!(config.slow_call_threshold, Some(Duration::from_millis(10)));
+        assert_eq!(config.cache_size, 0);
+
+        let vars = vec![("COSMWASM_VM_CACHE_SIZE".to_string(), "many".to_string())];
+        assert!(VmConfig::from_vars(vars).is_err());
+        let vars = vec![("COSMWASM_VM_MAX_IMPORT_CALLS".to_string(), "10".to_string())];
+        assert!(VmConfig::from_vars(vars).is_err());
+    }
+}
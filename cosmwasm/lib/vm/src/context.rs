@@ -1,17 +1,32 @@
 /**
 Internal details to be used by instance.rs only
 **/
+use std::collections::BTreeMap;
+#[cfg(feature = "coverage")]
+use std::collections::BTreeSet;
 use std::convert::TryInto;
 use std::ffi::c_void;
 use std::mem;
+use std::panic::{self, AssertUnwindSafe};
 
 use wasmer_runtime_core::vm::Ctx;
 
 use cosmwasm::traits::{Api, Storage};
 
-use crate::errors::Error;
-use crate::memory::{read_region, write_region};
-use cosmwasm::encoding::Binary;
+use crate::backends::{decrease_gas_left, get_gas_left};
+#[cfg(feature = "call-depth")]
+use crate::errors::CallDepthExceededErr;
+use crate::errors::{Error, ImportLimitExceededErr, ReadOnlyErr};
+use crate::gas::{GasConfig, GasReport, GasState};
+use crate::host_functions::HostFunctions;
+use crate::journal::Journal;
+use crate::limits::Limits;
+use crate::memory::{read_region, read_region_limited, region_len, write_region};
+use crate::prepare::Overlay;
+use crate::random::RandomState;
+use crate::resources::StorageUsageReport;
+use crate::trace::{hash_key, LogSink, TraceEvent, TraceSink};
+use cosmwasm::encoding::{decode_sections, Binary};
 use cosmwasm::types::{CanonicalAddr, HumanAddr};
 
 /// An unknown error occurred when writing to region
@@ -19,63 +34,428 @@ static ERROR_WRITE_TO_REGION_UNKNONW: i32 = -1000001;
 /// Could not write to region because it is too small
 static ERROR_WRITE_TO_REGION_TOO_SMALL: i32 = -1000002;
 
-pub fn do_read<T: Storage>(ctx: &Ctx, key_ptr: u32, value_ptr: u32) -> i32 {
-    let key = read_region(ctx, key_ptr);
+pub fn do_read<T: Storage>(ctx: &mut Ctx, key_ptr: u32, value_ptr: u32) -> Result<i32, Error> {
+    count_import::<T>(ctx, "read_db")?;
+    let key = read_region_limited(ctx, key_ptr, limits::<T>(ctx).max_key_length, "Key")?;
     let mut value: Option<Vec<u8>> = None;
-    with_storage_from_context(ctx, |store: &mut T| value = store.get(&key));
-    match value {
+    with_overlay::<T, _>(ctx, |overlay| {
+        if let Some(overlay) = overlay {
+            value = overlay.pending(&key);
+        }
+    });
+    if value.is_none() {
+        // prefetched values came from the same storage, so gas and results do not change
+        let b = unsafe { get_data::<T>(ctx.data) };
+        let prefetched = b.prefetched.get(&key).cloned();
+        mem::forget(b); // we do this to avoid cleanup
+        match prefetched {
+            Some(prefetched) => value = prefetched,
+            None => with_storage_from_context(ctx, |store: &mut T| value = store.get(&key)),
+        }
+        with_overlay::<T, _>(ctx, |overlay| {
+            if let Some(overlay) = overlay {
+                overlay.record_read(&key, &value);
+            }
+        });
+    }
+    let cost = gas_config::<T>(ctx).read_cost(&key, &value);
+    charge_storage_gas::<T>(ctx, "read_db", cost);
+    record_trace::<T>(
+        ctx,
+        TraceEvent::ReadDb {
+            key_hash: hash_key(&key),
+            value_len: value.as_ref().map(|v| v.len()),
+        },
+    );
+    let res = match value {
         Some(buf) => match write_region(ctx, value_ptr, &buf) {
             Ok(bytes_written) => bytes_written.try_into().unwrap(),
             Err(Error::RegionTooSmallErr { .. }) => ERROR_WRITE_TO_REGION_TOO_SMALL,
             Err(_) => ERROR_WRITE_TO_REGION_UNKNONW,
         },
         None => 0,
+    };
+    Ok(res)
+}
+
+pub fn do_write<T: Storage>(ctx: &mut Ctx, key_ptr: u32, value_ptr: u32) -> Result<(), Error> {
+    count_import::<T>(ctx, "write_db")?;
+    let b = unsafe { get_data::<T>(ctx.data) };
+    let read_only = b.read_only;
+    mem::forget(b); // we do this to avoid cleanup
+    if read_only {
+        return ReadOnlyErr {}.fail();
     }
+    let limits = limits::<T>(ctx);
+    let key = read_region_limited(ctx, key_ptr, limits.max_key_length, "Key")?;
+    let value = read_region_limited(ctx, value_ptr, limits.max_value_length, "Value")?;
+    let previous = stored_len::<T>(ctx, &key);
+    let mut b = unsafe { get_data::<T>(ctx.data) };
+    b.storage_usage
+        .record_write(key.len(), value.len(), previous);
+    b.prefetched.remove(&key);
+    mem::forget(b); // we do this to avoid cleanup
+    let mut prepared = false;
+    with_overlay::<T, _>(ctx, |overlay| {
+        if let Some(overlay) = overlay {
+            overlay.write(&key, &value);
+            prepared = true;
+        }
+    });
+    if !prepared {
+        with_storage_from_context(ctx, |store: &mut T| store.set(&key, &value));
+    }
+    let cost = gas_config::<T>(ctx).write_cost(&key, &value);
+    charge_storage_gas::<T>(ctx, "write_db", cost);
+    record_trace::<T>(
+        ctx,
+        TraceEvent::WriteDb {
+            key_hash: hash_key(&key),
+            value_len: value.len(),
+        },
+    );
+    Ok(())
 }
 
-pub fn do_write<T: Storage>(ctx: &Ctx, key_ptr: u32, value_ptr: u32) {
-    let key = read_region(ctx, key_ptr);
-    let value = read_region(ctx, value_ptr);
-    with_storage_from_context(ctx, |store: &mut T| store.set(&key, &value));
+// the length of the value a write replaces, for storage usage. Values the call already read
+// or wrote are known, anything else is looked up and charged like a read. The lookup decides
+// gas, so it is recorded like a read and commit refuses a prepared execution if it changed.
+fn stored_len<T: Storage>(ctx: &mut Ctx, key: &[u8]) -> Option<usize> {
+    let mut known = None;
+    with_overlay::<T, _>(ctx, |overlay| {
+        if let Some(overlay) = overlay {
+            known = overlay.known_len(key);
+        }
+    });
+    if let Some(len) = known {
+        return len;
+    }
+    let b = unsafe { get_data::<T>(ctx.data) };
+    let prefetched = b.prefetched.get(key).cloned();
+    mem::forget(b); // we do this to avoid cleanup
+    let mut value: Option<Vec<u8>> = None;
+    match prefetched {
+        Some(prefetched) => value = prefetched,
+        None => with_storage_from_context(ctx, |store: &mut T| value = store.get(key)),
+    }
+    with_overlay::<T, _>(ctx, |overlay| {
+        if let Some(overlay) = overlay {
+            overlay.record_read(key, &value);
+        }
+    });
+    let cost = gas_config::<T>(ctx).read_cost(key, &value);
+    charge_storage_gas::<T>(ctx, "read_db", cost);
+    value.map(|v| v.len())
 }
 
-pub fn do_canonical_address<A: Api>(
+pub fn do_canonical_address<S: Storage, A: Api>(
     api: A,
     ctx: &mut Ctx,
     human_ptr: u32,
     canonical_ptr: u32,
-) -> i32 {
+) -> Result<i32, Error> {
+    count_import::<S>(ctx, "canonicalize_address")?;
+    charge_api_gas::<S>(ctx, "canonicalize_address");
     let human = read_region(ctx, human_ptr);
     let human = match String::from_utf8(human) {
         Ok(human_str) => HumanAddr(human_str),
-        Err(_) => return -2,
+        Err(_) => return Ok(-2),
     };
-    match api.canonical_address(&human) {
+    let res = match api.canonical_address(&human) {
         Ok(canon) => match write_region(ctx, canonical_ptr, canon.as_slice()) {
             Ok(bytes_written) => bytes_written.try_into().unwrap(),
             Err(Error::RegionTooSmallErr { .. }) => ERROR_WRITE_TO_REGION_TOO_SMALL,
             Err(_) => ERROR_WRITE_TO_REGION_UNKNONW,
         },
         Err(_) => -1,
-    }
+    };
+    Ok(res)
 }
 
-pub fn do_human_address<A: Api>(api: A, ctx: &mut Ctx, canonical_ptr: u32, human_ptr: u32) -> i32 {
+pub fn do_human_address<S: Storage, A: Api>(
+    api: A,
+    ctx: &mut Ctx,
+    canonical_ptr: u32,
+    human_ptr: u32,
+) -> Result<i32, Error> {
+    count_import::<S>(ctx, "humanize_address")?;
+    charge_api_gas::<S>(ctx, "humanize_address");
     let canon = Binary(read_region(ctx, canonical_ptr));
-    match api.human_address(&CanonicalAddr(canon)) {
+    let res = match api.human_address(&CanonicalAddr(canon)) {
         Ok(human) => match write_region(ctx, human_ptr, human.as_str().as_bytes()) {
             Ok(bytes_written) => bytes_written.try_into().unwrap(),
             Err(Error::RegionTooSmallErr { .. }) => ERROR_WRITE_TO_REGION_TOO_SMALL,
             Err(_) => ERROR_WRITE_TO_REGION_UNKNONW,
         },
         Err(_) => -1,
+    };
+    Ok(res)
+}
+
+/// Result codes of the signature verification imports
+static VERIFICATION_VALID: i32 = 0;
+static VERIFICATION_INVALID: i32 = 1;
+/// The arguments were malformed, eg. a signature of the wrong length
+static ERROR_VERIFICATION_INPUT: i32 = -1;
+/// The api of the host does not implement the scheme
+static ERROR_VERIFICATION_UNSUPPORTED: i32 = -2;
+
+fn verification_result(result: cosmwasm::errors::Result<bool>) -> i32 {
+    match result {
+        Ok(true) => VERIFICATION_VALID,
+        Ok(false) => VERIFICATION_INVALID,
+        Err(err) => verification_error(&err),
     }
 }
 
+fn verification_error(err: &cosmwasm::errors::Error) -> i32 {
+    match err {
+        cosmwasm::errors::Error::Unsupported { .. } => ERROR_VERIFICATION_UNSUPPORTED,
+        _ => ERROR_VERIFICATION_INPUT,
+    }
+}
+
+pub fn do_secp256k1_verify<S: Storage, A: Api>(
+    api: A,
+    ctx: &mut Ctx,
+    hash_ptr: u32,
+    signature_ptr: u32,
+    pubkey_ptr: u32,
+) -> Result<i32, Error> {
+    count_import::<S>(ctx, "secp256k1_verify")?;
+    let cost = gas_config::<S>(ctx).secp256k1_verify;
+    charge_external_gas::<S>(ctx, "secp256k1_verify", cost);
+    let hash = read_region(ctx, hash_ptr);
+    let signature = read_region(ctx, signature_ptr);
+    let pubkey = read_region(ctx, pubkey_ptr);
+    Ok(verification_result(
+        api.secp256k1_verify(&hash, &signature, &pubkey),
+    ))
+}
+
+pub fn do_secp256k1_recover_pubkey<S: Storage, A: Api>(
+    api: A,
+    ctx: &mut Ctx,
+    hash_ptr: u32,
+    signature_ptr: u32,
+    recovery_param: u32,
+    pubkey_ptr: u32,
+) -> Result<i32, Error> {
+    count_import::<S>(ctx, "secp256k1_recover_pubkey")?;
+    let cost = gas_config::<S>(ctx).secp256k1_recover_pubkey;
+    charge_external_gas::<S>(ctx, "secp256k1_recover_pubkey", cost);
+    let hash = read_region(ctx, hash_ptr);
+    let signature = read_region(ctx, signature_ptr);
+    let recovery_param = match recovery_param.try_into() {
+        Ok(param) => param,
+        Err(_) => return Ok(ERROR_VERIFICATION_INPUT),
+    };
+    let res = match api.secp256k1_recover_pubkey(&hash, &signature, recovery_param) {
+        Ok(pubkey) => match write_region(ctx, pubkey_ptr, &pubkey) {
+            Ok(bytes_written) => bytes_written.try_into().unwrap(),
+            Err(Error::RegionTooSmallErr { .. }) => ERROR_WRITE_TO_REGION_TOO_SMALL,
+            Err(_) => ERROR_WRITE_TO_REGION_UNKNONW,
+        },
+        Err(err) => verification_error(&err),
+    };
+    Ok(res)
+}
+
+pub fn do_ed25519_verify<S: Storage, A: Api>(
+    api: A,
+    ctx: &mut Ctx,
+    message_ptr: u32,
+    signature_ptr: u32,
+    pubkey_ptr: u32,
+) -> Result<i32, Error> {
+    count_import::<S>(ctx, "ed25519_verify")?;
+    let cost = gas_config::<S>(ctx).ed25519_verify;
+    charge_external_gas::<S>(ctx, "ed25519_verify", cost);
+    let message = read_region(ctx, message_ptr);
+    let signature = read_region(ctx, signature_ptr);
+    let pubkey = read_region(ctx, pubkey_ptr);
+    Ok(verification_result(
+        api.ed25519_verify(&message, &signature, &pubkey),
+    ))
+}
+
+pub fn do_ed25519_batch_verify<S: Storage, A: Api>(
+    api: A,
+    ctx: &mut Ctx,
+    messages_ptr: u32,
+    signatures_ptr: u32,
+    pubkeys_ptr: u32,
+) -> Result<i32, Error> {
+    count_import::<S>(ctx, "ed25519_batch_verify")?;
+    // the first signature is paid before decoding, so malformed input is not free
+    let per_signature = gas_config::<S>(ctx).ed25519_batch_verify_per_signature;
+    charge_external_gas::<S>(ctx, "ed25519_batch_verify", per_signature);
+    let messages = read_region(ctx, messages_ptr);
+    let signatures = read_region(ctx, signatures_ptr);
+    let pubkeys = read_region(ctx, pubkeys_ptr);
+    let (messages, signatures, pubkeys) = match (
+        decode_sections(&messages),
+        decode_sections(&signatures),
+        decode_sections(&pubkeys),
+    ) {
+        (Ok(messages), Ok(signatures), Ok(pubkeys)) => (messages, signatures, pubkeys),
+        _ => return Ok(ERROR_VERIFICATION_INPUT),
+    };
+    let cost = per_signature * signatures.len().saturating_sub(1) as u64;
+    charge_external_gas::<S>(ctx, "ed25519_batch_verify", cost);
+    Ok(verification_result(api.ed25519_batch_verify(
+        &messages,
+        &signatures,
+        &pubkeys,
+    )))
+}
+
+/// debug_print messages beyond this count are dropped for the rest of the call
+pub static MAX_DEBUG_MESSAGES: u32 = 100;
+/// Longer debug_print messages are cut to this many bytes
+pub static MAX_DEBUG_MESSAGE_LENGTH: usize = 1024;
+
+pub fn do_debug_print<S: Storage>(ctx: &mut Ctx, message_ptr: u32) -> Result<(), Error> {
+    count_import::<S>(ctx, "debug_print")?;
+    let message = read_region(ctx, message_ptr);
+    let config = gas_config::<S>(ctx);
+    let cost = config.debug_print_base + config.debug_print_per_byte * message.len() as u64;
+    charge_external_gas::<S>(ctx, "debug_print", cost);
+
+    let mut b = unsafe { get_data::<S>(ctx.data) };
+    if let Some(sink) = &mut b.log {
+        if b.debug_messages < MAX_DEBUG_MESSAGES {
+            b.debug_messages += 1;
+            let end = message.len().min(MAX_DEBUG_MESSAGE_LENGTH);
+            sink.log(&String::from_utf8_lossy(&message[..end]));
+        }
+    }
+    mem::forget(b); // we do this to avoid cleanup
+    Ok(())
+}
+
+/// The random import returns this when the call has no block entropy
+static ERROR_RANDOM_NO_ENTROPY: i32 = -1;
+/// The random import returns this when more than MAX_RANDOM_LENGTH bytes are requested
+static ERROR_RANDOM_TOO_LONG: i32 = -2;
+/// Maximum number of bytes a single random call returns
+pub static MAX_RANDOM_LENGTH: usize = 64 * 1024;
+
+pub fn do_random<S: Storage>(ctx: &mut Ctx, buffer_ptr: u32) -> Result<i32, Error> {
+    count_import::<S>(ctx, "random")?;
+    let len = region_len(ctx, buffer_ptr);
+    if len > MAX_RANDOM_LENGTH {
+        return Ok(ERROR_RANDOM_TOO_LONG);
+    }
+    let config = gas_config::<S>(ctx);
+    let cost = config.random_base + config.random_per_byte * len as u64;
+    charge_external_gas::<S>(ctx, "random", cost);
+
+    let mut b = unsafe { get_data::<S>(ctx.data) };
+    let bytes = b.random.as_mut().map(|random| random.fill(len));
+    mem::forget(b); // we do this to avoid cleanup
+    let res = match bytes {
+        Some(bytes) => match write_region(ctx, buffer_ptr, &bytes) {
+            Ok(bytes_written) => bytes_written.try_into().unwrap(),
+            Err(Error::RegionTooSmallErr { .. }) => ERROR_WRITE_TO_REGION_TOO_SMALL,
+            Err(_) => ERROR_WRITE_TO_REGION_UNKNONW,
+        },
+        None => ERROR_RANDOM_NO_ENTROPY,
+    };
+    Ok(res)
+}
+
+/// Writes the gas left and the execution progress (all gas used by the call so far) as two
+/// big endian u64 to the region at result_ptr. Both are derived from gas only, never from time.
+pub fn do_check_gas<S: Storage>(ctx: &mut Ctx, result_ptr: u32) -> Result<i32, Error> {
+    count_import::<S>(ctx, "check_gas")?;
+    let cost = gas_config::<S>(ctx).check_gas;
+    charge_external_gas::<S>(ctx, "check_gas", cost);
+
+    let gas_left = get_gas_left(ctx);
+    let mut progress = 0;
+    with_gas_state::<S, _>(ctx, |gas| progress = gas.progress(gas_left));
+    let mut out = gas_left.to_be_bytes().to_vec();
+    out.extend_from_slice(&progress.to_be_bytes());
+    let res = match write_region(ctx, result_ptr, &out) {
+        Ok(bytes_written) => bytes_written.try_into().unwrap(),
+        Err(Error::RegionTooSmallErr { .. }) => ERROR_WRITE_TO_REGION_TOO_SMALL,
+        Err(_) => ERROR_WRITE_TO_REGION_UNKNONW,
+    };
+    Ok(res)
+}
+
+/// Aborts the call, instrumented code calls this when a call would exceed max_call_depth
+#[cfg(feature = "call-depth")]
+pub fn do_call_depth_exceeded<S: Storage>(ctx: &mut Ctx) -> Result<(), Error> {
+    let limit = limits::<S>(ctx).max_call_depth;
+    CallDepthExceededErr { limit }.fail()
+}
+
+/// The host_call import returns this if the function is not registered or not allowed
+static ERROR_HOST_CALL_UNKNOWN: i32 = -1;
+
+/// Calls the host function named by the region at name_ptr with the payload at payload_ptr
+/// and writes its response to the region at result_ptr. The function's gas is charged,
+/// an error of the function aborts the call.
+pub fn do_host_call<S: Storage>(
+    ctx: &mut Ctx,
+    name_ptr: u32,
+    payload_ptr: u32,
+    result_ptr: u32,
+) -> Result<i32, Error> {
+    count_import::<S>(ctx, "host_call")?;
+    let limits = limits::<S>(ctx);
+    let name = read_region_limited(ctx, name_ptr, limits.max_host_call_name, "Name")?;
+    let payload = read_region_limited(ctx, payload_ptr, limits.max_host_call_payload, "Payload")?;
+    let name = String::from_utf8_lossy(&name);
+
+    let b = unsafe { get_data::<S>(ctx.data) };
+    let res = b
+        .host_functions
+        .as_ref()
+        .and_then(|functions| functions.call(&name, &payload));
+    mem::forget(b); // we do this to avoid cleanup
+    let (response, gas) = match res {
+        Some(res) => res?,
+        None => return Ok(ERROR_HOST_CALL_UNKNOWN),
+    };
+    charge_external_gas::<S>(ctx, "host_call", gas);
+    let res = match write_region(ctx, result_ptr, &response) {
+        Ok(bytes_written) => bytes_written.try_into().unwrap(),
+        Err(Error::RegionTooSmallErr { .. }) => ERROR_WRITE_TO_REGION_TOO_SMALL,
+        Err(_) => ERROR_WRITE_TO_REGION_UNKNONW,
+    };
+    Ok(res)
+}
+
 /** context data **/
 
 struct ContextData<S: Storage> {
     data: Option<S>,
+    trace: Option<Box<dyn TraceSink>>,
+    log: Option<Box<dyn LogSink>>,
+    /// debug_print messages passed to the log sink in the current call
+    debug_messages: u32,
+    /// set while a prepared execution runs, storage is only read then
+    overlay: Option<Overlay>,
+    gas: GasState,
+    limits: Limits,
+    /// set by init and handle if the block has entropy
+    random: Option<RandomState>,
+    journal: Option<Journal>,
+    /// calls of every import in the current call
+    import_calls: BTreeMap<&'static str, u32>,
+    host_functions: Option<HostFunctions>,
+    /// set by call_handle_readonly, writes abort the call
+    read_only: bool,
+    /// storage writes of the current call
+    storage_usage: StorageUsageReport,
+    /// values fetched ahead of the call by call_handle_with_hints, dropped once written
+    prefetched: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+    /// blocks entered by instrumented code since the last take_coverage
+    #[cfg(feature = "coverage")]
+    coverage: BTreeSet<u32>,
 }
 
 pub fn setup_context<S: Storage>() -> (*mut c_void, fn(*mut c_void)) {
@@ -86,7 +466,24 @@ pub fn setup_context<S: Storage>() -> (*mut c_void, fn(*mut c_void)) {
 }
 
 fn create_unmanaged_storage<S: Storage>() -> *mut c_void {
-    let data = ContextData::<S> { data: None };
+    let data = ContextData::<S> {
+        data: None,
+        trace: None,
+        log: None,
+        debug_messages: 0,
+        overlay: None,
+        gas: GasState::default(),
+        limits: Limits::default(),
+        random: None,
+        journal: None,
+        import_calls: BTreeMap::new(),
+        host_functions: None,
+        read_only: false,
+        storage_usage: StorageUsageReport::default(),
+        prefetched: BTreeMap::new(),
+        #[cfg(feature = "coverage")]
+        coverage: BTreeSet::new(),
+    };
     let state = Box::new(data);
     Box::into_raw(state) as *mut c_void
 }
@@ -102,12 +499,21 @@ fn destroy_unmanaged_storage<S: Storage>(ptr: *mut c_void) {
     }
 }
 
+/// Runs func with the storage of the instance.
+/// If func (usually the storage backend) panics, the storage is put back before the
+/// panic continues, so the instance does not see an empty storage on later calls.
+/// The panic message reaches the caller as RuntimeErr.
 pub fn with_storage_from_context<S: Storage, F: FnMut(&mut S)>(ctx: &Ctx, mut func: F) {
     let mut storage: Option<S> = take_storage(ctx);
-    if let Some(data) = &mut storage {
-        func(data);
-    }
+    let res = panic::catch_unwind(AssertUnwindSafe(|| {
+        if let Some(data) = &mut storage {
+            func(data);
+        }
+    }));
     leave_storage(ctx, storage);
+    if let Err(payload) = res {
+        panic::resume_unwind(payload);
+    }
 }
 
 pub fn take_storage<S: Storage>(ctx: &Ctx) -> Option<S> {
@@ -124,3 +530,239 @@ pub fn leave_storage<S: Storage>(ctx: &Ctx, storage: Option<S>) {
     b.data = storage;
     mem::forget(b); // we do this to avoid cleanup
 }
+
+pub fn set_trace_sink<S: Storage>(ctx: &Ctx, sink: Option<Box<dyn TraceSink>>) {
+    let mut b = unsafe { get_data::<S>(ctx.data) };
+    b.trace = sink;
+    mem::forget(b); // we do this to avoid cleanup
+}
+
+pub fn take_trace_sink<S: Storage>(ctx: &Ctx) -> Option<Box<dyn TraceSink>> {
+    let mut b = unsafe { get_data::<S>(ctx.data) };
+    let res = b.trace.take();
+    mem::forget(b); // we do this to avoid cleanup
+    res
+}
+
+pub fn set_log_sink<S: Storage>(ctx: &Ctx, sink: Option<Box<dyn LogSink>>) {
+    let mut b = unsafe { get_data::<S>(ctx.data) };
+    b.log = sink;
+    mem::forget(b); // we do this to avoid cleanup
+}
+
+pub fn take_log_sink<S: Storage>(ctx: &Ctx) -> Option<Box<dyn LogSink>> {
+    let mut b = unsafe { get_data::<S>(ctx.data) };
+    let res = b.log.take();
+    mem::forget(b); // we do this to avoid cleanup
+    res
+}
+
+/// Passes the event to the registered trace sink, if any
+pub fn record_trace<S: Storage>(ctx: &Ctx, event: TraceEvent) {
+    let mut b = unsafe { get_data::<S>(ctx.data) };
+    if let Some(sink) = &mut b.trace {
+        sink.record(event);
+    }
+    mem::forget(b); // we do this to avoid cleanup
+}
+
+/// Reads keys from storage in one get_many call, for the reads of the following call
+pub fn prefetch<S: Storage>(ctx: &Ctx, keys: &[Vec<u8>]) {
+    let mut values = Vec::new();
+    with_storage_from_context(ctx, |store: &mut S| values = store.get_many(keys));
+    let mut b = unsafe { get_data::<S>(ctx.data) };
+    b.prefetched = keys.iter().cloned().zip(values).collect();
+    mem::forget(b); // we do this to avoid cleanup
+}
+
+pub fn clear_prefetched<S: Storage>(ctx: &Ctx) {
+    let mut b = unsafe { get_data::<S>(ctx.data) };
+    b.prefetched.clear();
+    mem::forget(b); // we do this to avoid cleanup
+}
+
+pub fn set_overlay<S: Storage>(ctx: &Ctx, overlay: Option<Overlay>) {
+    let mut b = unsafe { get_data::<S>(ctx.data) };
+    b.overlay = overlay;
+    mem::forget(b); // we do this to avoid cleanup
+}
+
+pub fn take_overlay<S: Storage>(ctx: &Ctx) -> Option<Overlay> {
+    let mut b = unsafe { get_data::<S>(ctx.data) };
+    let res = b.overlay.take();
+    mem::forget(b); // we do this to avoid cleanup
+    res
+}
+
+fn with_overlay<S: Storage, F: FnMut(Option<&mut Overlay>)>(ctx: &Ctx, mut func: F) {
+    let mut overlay = take_overlay::<S>(ctx);
+    func(overlay.as_mut());
+    set_overlay::<S>(ctx, overlay);
+}
+
+fn with_gas_state<S: Storage, F: FnMut(&mut GasState)>(ctx: &Ctx, mut func: F) {
+    let mut b = unsafe { get_data::<S>(ctx.data) };
+    func(&mut b.gas);
+    mem::forget(b); // we do this to avoid cleanup
+}
+
+pub fn set_gas_config<S: Storage>(ctx: &Ctx, config: GasConfig) {
+    with_gas_state::<S, _>(ctx, |gas| gas.config = config);
+}
+
+fn gas_config<S: Storage>(ctx: &Ctx) -> GasConfig {
+    let mut config = GasConfig::default();
+    with_gas_state::<S, _>(ctx, |gas| config = gas.config);
+    config
+}
+
+/// Starts the host side gas accounting of a new call
+pub fn reset_gas_state<S: Storage>(ctx: &Ctx, gas_left: u64) {
+    with_gas_state::<S, _>(ctx, |gas| gas.reset(gas_left));
+}
+
+pub fn set_gas_audit<S: Storage>(ctx: &Ctx, enabled: bool) {
+    with_gas_state::<S, _>(ctx, |gas| gas.audit = enabled);
+}
+
+pub fn set_limits<S: Storage>(ctx: &Ctx, limits: Limits) {
+    let mut b = unsafe { get_data::<S>(ctx.data) };
+    b.limits = limits;
+    mem::forget(b); // we do this to avoid cleanup
+}
+
+pub fn limits<S: Storage>(ctx: &Ctx) -> Limits {
+    let b = unsafe { get_data::<S>(ctx.data) };
+    let res = b.limits;
+    mem::forget(b); // we do this to avoid cleanup
+    res
+}
+
+pub fn set_random<S: Storage>(ctx: &Ctx, random: Option<RandomState>) {
+    let mut b = unsafe { get_data::<S>(ctx.data) };
+    b.random = random;
+    mem::forget(b); // we do this to avoid cleanup
+}
+
+pub fn set_read_only<S: Storage>(ctx: &Ctx, read_only: bool) {
+    let mut b = unsafe { get_data::<S>(ctx.data) };
+    b.read_only = read_only;
+    mem::forget(b); // we do this to avoid cleanup
+}
+
+pub fn set_host_functions<S: Storage>(ctx: &Ctx, functions: Option<HostFunctions>) {
+    let mut b = unsafe { get_data::<S>(ctx.data) };
+    b.host_functions = functions;
+    mem::forget(b); // we do this to avoid cleanup
+}
+
+pub fn set_journal<S: Storage>(ctx: &Ctx, journal: Option<Journal>) {
+    let mut b = unsafe { get_data::<S>(ctx.data) };
+    b.journal = journal;
+    mem::forget(b); // we do this to avoid cleanup
+}
+
+pub fn take_journal<S: Storage>(ctx: &Ctx) -> Option<Journal> {
+    let mut b = unsafe { get_data::<S>(ctx.data) };
+    let res = b.journal.take();
+    mem::forget(b); // we do this to avoid cleanup
+    res
+}
+
+// counts the import call, failing once the call made more than max_import_calls
+fn count_import<S: Storage>(ctx: &Ctx, name: &'static str) -> Result<(), Error> {
+    let mut b = unsafe { get_data::<S>(ctx.data) };
+    *b.import_calls.entry(name).or_insert(0) += 1;
+    let calls: u32 = b.import_calls.values().sum();
+    let limit = b.limits.max_import_calls;
+    mem::forget(b); // we do this to avoid cleanup
+    if calls > limit {
+        return ImportLimitExceededErr { limit }.fail();
+    }
+    Ok(())
+}
+
+/// Returns the import calls counted since the last time and starts counting from zero
+pub fn take_import_calls<S: Storage>(ctx: &Ctx) -> BTreeMap<&'static str, u32> {
+    let mut b = unsafe { get_data::<S>(ctx.data) };
+    let res = mem::take(&mut b.import_calls);
+    mem::forget(b); // we do this to avoid cleanup
+    res
+}
+
+/// Returns the storage usage since the last time and starts counting from zero
+pub fn take_storage_usage<S: Storage>(ctx: &Ctx) -> StorageUsageReport {
+    let mut b = unsafe { get_data::<S>(ctx.data) };
+    let res = mem::take(&mut b.storage_usage);
+    mem::forget(b); // we do this to avoid cleanup
+    res
+}
+
+/// Called by instrumented code for every block it enters. Neither charged nor counted as
+/// an import call, so coverage does not change the gas of a call.
+#[cfg(feature = "coverage")]
+pub fn record_coverage<S: Storage>(ctx: &mut Ctx, block: u32) {
+    let mut b = unsafe { get_data::<S>(ctx.data) };
+    b.coverage.insert(block);
+    mem::forget(b); // we do this to avoid cleanup
+}
+
+#[cfg(feature = "coverage")]
+pub fn take_coverage<S: Storage>(ctx: &Ctx) -> BTreeSet<u32> {
+    let mut b = unsafe { get_data::<S>(ctx.data) };
+    let res = mem::take(&mut b.coverage);
+    mem::forget(b); // we do this to avoid cleanup
+    res
+}
+
+/// Restarts the debug_print limit for a new call
+pub fn reset_debug_messages<S: Storage>(ctx: &Ctx) {
+    let mut b = unsafe { get_data::<S>(ctx.data) };
+    b.debug_messages = 0;
+    mem::forget(b); // we do this to avoid cleanup
+}
+
+pub fn gas_report<S: Storage>(ctx: &Ctx, gas_before: u64, gas_after: u64) -> GasReport {
+    let mut report = GasReport::default();
+    with_gas_state::<S, _>(ctx, |gas| report = gas.report(gas_before, gas_after));
+    report
+}
+
+fn charge_storage_gas<S: Storage>(ctx: &mut Ctx, source: &'static str, amount: u64) {
+    audit_gas::<S>(ctx, source, amount);
+    with_gas_state::<S, _>(ctx, |gas| {
+        gas.externally_used += amount;
+        gas.storage_used += amount;
+    });
+    decrease_gas_left(ctx, amount);
+}
+
+fn charge_api_gas<S: Storage>(ctx: &mut Ctx, source: &'static str) {
+    let amount = gas_config::<S>(ctx).api_call;
+    charge_external_gas::<S>(ctx, source, amount);
+}
+
+fn charge_external_gas<S: Storage>(ctx: &mut Ctx, source: &'static str, amount: u64) {
+    audit_gas::<S>(ctx, source, amount);
+    with_gas_state::<S, _>(ctx, |gas| gas.externally_used += amount);
+    decrease_gas_left(ctx, amount);
+}
+
+// in audit mode, records the wasm gas used since the last charge and then this charge
+fn audit_gas<S: Storage>(ctx: &mut Ctx, source: &'static str, amount: u64) {
+    let gas_left = get_gas_left(ctx);
+    let mut events = Vec::new();
+    with_gas_state::<S, _>(ctx, |gas| events = gas.audit(source, amount, gas_left));
+    for event in events {
+        record_trace::<S>(ctx, event);
+    }
+}
+
+/// Records the wasm gas used after the last host charge of a call, in audit mode
+pub fn finish_gas_audit<S: Storage>(ctx: &Ctx, gas_left: u64) {
+    let mut events = Vec::new();
+    with_gas_state::<S, _>(ctx, |gas| events = gas.audit_wasm(gas_left));
+    for event in events {
+        record_trace::<S>(ctx, event);
+    }
+}
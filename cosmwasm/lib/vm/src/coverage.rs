@@ -0,0 +1,218 @@
+//! Basic block coverage of contracts, to measure how much of a contract its tests run.
+//! instrument rewrites a contract to report every block it enters to the coverage_hit
+//! import, which only builds with the coverage feature provide. Instrumented code is for
+//! tests only, it must never be stored on chain.
+use std::collections::BTreeSet;
+
+use parity_wasm::elements::{Instruction, ValueType};
+use wasmer_runtime_core::{export::Export, import::IsExport, typed_func::Func, vm::Ctx};
+
+use cosmwasm::traits::Storage;
+
+use crate::context::record_coverage;
+use crate::errors::Result;
+use crate::instrumentation::{add_function_import, deserialize_module, serialize_module};
+
+pub static COVERAGE_IMPORT: &str = "coverage_hit";
+
+/// A contract rewritten by instrument
+#[derive(Debug, Clone, PartialEq)]
+pub struct Instrumented {
+    pub code: Vec<u8>,
+    /// number of blocks, the ids reported are 0..blocks
+    pub blocks: u32,
+}
+
+/// Inserts a call of coverage_hit with the id of the block at the start of every function
+/// and after every block, loop, if, else, end and br_if instruction. Instantiate the result
+/// with Instance::from_code and read the blocks entered with Instance::take_coverage.
+pub fn instrument(wasm_code: &[u8]) -> Result<Instrumented> {
+    let mut module = deserialize_module(wasm_code)?;
+    let hit = add_function_import(&mut module, COVERAGE_IMPORT, vec![ValueType::I32])?;
+
+    let mut blocks = 0;
+    if let Some(code_section) = module.code_section_mut() {
+        for body in code_section.bodies_mut() {
+            let code = body.code_mut().elements_mut();
+            let last = code.len().saturating_sub(1);
+            let mut probed = Vec::with_capacity(code.len() * 2);
+            push_probe(&mut probed, &mut blocks, hit);
+            for (i, instruction) in code.drain(..).enumerate() {
+                let starts_block = match instruction {
+                    Instruction::Block(_)
+                    | Instruction::Loop(_)
+                    | Instruction::If(_)
+                    | Instruction::Else
+                    | Instruction::BrIf(_) => true,
+                    // the last end closes the function
+                    Instruction::End => i != last,
+                    _ => false,
+                };
+                probed.push(instruction);
+                if starts_block {
+                    push_probe(&mut probed, &mut blocks, hit);
+                }
+            }
+            *code = probed;
+        }
+    }
+
+    let code = serialize_module(module)?;
+    Ok(Instrumented { code, blocks })
+}
+
+fn push_probe(code: &mut Vec<Instruction>, blocks: &mut u32, hit: u32) {
+    code.push(Instruction::I32Const(*blocks as i32));
+    code.push(Instruction::Call(hit));
+    *blocks += 1;
+}
+
+pub(crate) fn coverage_imports<S: Storage>() -> Vec<(String, String, Export)> {
+    let hit = Func::new(|ctx: &mut Ctx, block: u32| record_coverage::<S>(ctx, block));
+    vec![(
+        "env".to_string(),
+        COVERAGE_IMPORT.to_string(),
+        hit.to_export(),
+    )]
+}
+
+/// CoverageMap sums up the blocks entered by many calls, eg. of a whole test suite
+#[derive(Debug, Clone, PartialEq)]
+pub struct CoverageMap {
+    // number of calls that entered each block
+    calls: Vec<u32>,
+}
+
+impl CoverageMap {
+    pub fn new(blocks: u32) -> Self {
+        CoverageMap {
+            calls: vec![0; blocks as usize],
+        }
+    }
+
+    /// Adds the blocks one call entered, as returned by Instance::take_coverage
+    pub fn record(&mut self, entered: &BTreeSet<u32>) {
+        for block in entered {
+            if let Some(calls) = self.calls.get_mut(*block as usize) {
+                *calls += 1;
+            }
+        }
+    }
+
+    /// Adds another map of the same code, eg. collected by another test
+    pub fn merge(&mut self, other: &CoverageMap) {
+        if other.calls.len() > self.calls.len() {
+            self.calls.resize(other.calls.len(), 0);
+        }
+        for (calls, other) in self.calls.iter_mut().zip(other.calls.iter()) {
+            *calls += other;
+        }
+    }
+
+    pub fn blocks(&self) -> usize {
+        self.calls.len()
+    }
+
+    /// number of blocks entered at least once
+    pub fn covered(&self) -> usize {
+        self.calls.iter().filter(|calls| **calls > 0).count()
+    }
+
+    /// covered blocks divided by all blocks, 1.0 for code without blocks
+    pub fn ratio(&self) -> f64 {
+        if self.calls.is_empty() {
+            return 1.0;
+        }
+        self.covered() as f64 / self.calls.len() as f64
+    }
+
+    pub fn uncovered(&self) -> Vec<u32> {
+        (0..self.calls.len() as u32)
+            .filter(|block| self.calls[*block as usize] == 0)
+            .collect()
+    }
+
+    /// One bit per block, set if the block was entered. Block i is bit i % 8 of byte i / 8.
+    pub fn bitmap(&self) -> Vec<u8> {
+        let mut bitmap = Vec::new();
+        for (block, calls) in self.calls.iter().enumerate() {
+            if block % 8 == 0 {
+                bitmap.push(0u8);
+            }
+            if *calls > 0 {
+                bitmap[block / 8] |= 1 << (block % 8);
+            }
+        }
+        bitmap
+    }
+
+    pub fn calls(&self, block: u32) -> u32 {
+        self.calls.get(block as usize).cloned().unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::calls::call_init;
+    use crate::instance::Instance;
+    use cosmwasm::mock::{dependencies, mock_env, MockApi, MockStorage};
+    use cosmwasm::types::coin;
+    use wabt::wat2wasm;
+
+    static CONTRACT_0_7: &[u8] = include_bytes!("../testdata/contract_0.7.wasm");
+
+    static PICK_WAT: &str = r#"
+        (module
+          (func $pick (export "pick") (param i32) (result i32)
+            get_local 0
+            if (result i32)
+              i32.const 1
+            else
+              i32.const 2
+            end))
+    "#;
+
+    #[test]
+    fn instrument_reports_entered_blocks() {
+        let instrumented = instrument(&wat2wasm(PICK_WAT).unwrap()).unwrap();
+        // function entry, then, else and after the if
+        assert_eq!(instrumented.blocks, 4);
+        let mut instance: Instance<MockStorage, MockApi> =
+            Instance::from_code(&instrumented.code, dependencies(20), 1_000_000).unwrap();
+        let pick: Func<u32, u32> = instance.func("pick").unwrap();
+        assert_eq!(pick.call(1).unwrap(), 1);
+        let entered = instance.take_coverage();
+        assert_eq!(entered.iter().cloned().collect::<Vec<u32>>(), vec![0, 1, 3]);
+
+        let mut map = CoverageMap::new(instrumented.blocks);
+        map.record(&entered);
+        assert_eq!(map.uncovered(), vec![2]);
+        assert_eq!(map.bitmap(), vec![0b1011]);
+
+        let pick: Func<u32, u32> = instance.func("pick").unwrap();
+        assert_eq!(pick.call(0).unwrap(), 2);
+        let mut other = CoverageMap::new(instrumented.blocks);
+        other.record(&instance.take_coverage());
+        map.merge(&other);
+        assert_eq!(map.covered(), 4);
+        assert_eq!(map.calls(0), 2);
+        assert_eq!(map.ratio(), 1.0);
+    }
+
+    #[test]
+    fn instrumented_contract_still_works() {
+        let instrumented = instrument(CONTRACT_0_7).unwrap();
+        assert!(instrumented.blocks > 0);
+        let mut instance =
+            Instance::from_code(&instrumented.code, dependencies(20), 1_000_000).unwrap();
+        let env = mock_env(&instance.api, "creator", &coin("1000", "earth"), &[]);
+        let msg = br#"{"verifier": "verifies", "beneficiary": "benefits"}"#;
+        call_init(&mut instance, &env, msg).unwrap().unwrap();
+
+        let mut map = CoverageMap::new(instrumented.blocks);
+        map.record(&instance.take_coverage());
+        assert!(map.covered() > 0);
+        assert!(map.covered() < map.blocks());
+    }
+}
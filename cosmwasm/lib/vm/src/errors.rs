@@ -8,18 +8,52 @@ use wasmer_runtime_core::error as core_error;
 #[derive(Debug, Snafu)]
 #[snafu(visibility = "pub")]
 pub enum Error {
+    #[snafu(display("Call depth limit of {} exceeded", limit))]
+    CallDepthExceededErr {
+        limit: u32,
+        #[cfg(feature = "backtraces")]
+        backtrace: snafu::Backtrace,
+    },
     #[snafu(display("Cache error: {}", msg))]
     CacheErr {
         msg: String,
         #[cfg(feature = "backtraces")]
         backtrace: snafu::Backtrace,
     },
+    #[snafu(display("Config error: {}", msg))]
+    ConfigErr {
+        msg: String,
+        #[cfg(feature = "backtraces")]
+        backtrace: snafu::Backtrace,
+    },
+    #[snafu(display("Wasm code too large. Got {} bytes, limit is {}", size, limit))]
+    CodeTooLargeErr {
+        size: usize,
+        limit: usize,
+        #[cfg(feature = "backtraces")]
+        backtrace: snafu::Backtrace,
+    },
     #[snafu(display("Compiling wasm: {}", source))]
     CompileErr {
         source: core_error::CompileError,
         #[cfg(feature = "backtraces")]
         backtrace: snafu::Backtrace,
     },
+    #[snafu(display("Invalid env: {}", msg))]
+    EnvErr {
+        msg: String,
+        #[cfg(feature = "backtraces")]
+        backtrace: snafu::Backtrace,
+    },
+    #[snafu(display(
+        "Wasm code uses floating point operations, which are not allowed, in: {}",
+        locations.join(", ")
+    ))]
+    FloatsNotAllowedErr {
+        locations: Vec<String>,
+        #[cfg(feature = "backtraces")]
+        backtrace: snafu::Backtrace,
+    },
     #[snafu(display("Filesystem error: {}", source))]
     IoErr {
         source: io::Error,
@@ -31,6 +65,31 @@ pub enum Error {
         #[cfg(feature = "backtraces")]
         backtrace: snafu::Backtrace,
     },
+    #[snafu(display("Import call limit of {} exceeded", limit))]
+    ImportLimitExceededErr {
+        limit: u32,
+        #[cfg(feature = "backtraces")]
+        backtrace: snafu::Backtrace,
+    },
+    #[snafu(display("Invalid message {}: {}", index, msg))]
+    InvalidMessageErr {
+        index: usize,
+        msg: String,
+        #[cfg(feature = "backtraces")]
+        backtrace: snafu::Backtrace,
+    },
+    #[snafu(display("Wasm memory too large. Declares {} pages, limit is {}", pages, limit))]
+    MemoryTooLargeErr {
+        pages: u32,
+        limit: u32,
+        #[cfg(feature = "backtraces")]
+        backtrace: snafu::Backtrace,
+    },
+    #[snafu(display("State changed since the execution was prepared"))]
+    StateChangedErr {
+        #[cfg(feature = "backtraces")]
+        backtrace: snafu::Backtrace,
+    },
     #[snafu(display("Parse error: {}", source))]
     ParseErr {
         source: serde_json_wasm::de::Error,
@@ -43,6 +102,55 @@ pub enum Error {
         #[cfg(feature = "backtraces")]
         backtrace: snafu::Backtrace,
     },
+    #[snafu(display(
+        "Query quota of {} exceeded. Used {} gas, limit is {}",
+        tag,
+        used,
+        limit
+    ))]
+    QuotaExceededErr {
+        tag: String,
+        used: u64,
+        limit: u64,
+        #[cfg(feature = "backtraces")]
+        backtrace: snafu::Backtrace,
+    },
+    #[snafu(display("Call refused by execution policy: {}", msg))]
+    PolicyErr {
+        msg: String,
+        #[cfg(feature = "backtraces")]
+        backtrace: snafu::Backtrace,
+    },
+    #[snafu(display("Ran out of gas during {}", context))]
+    GasDepletionErr {
+        context: &'static str,
+        #[cfg(feature = "backtraces")]
+        backtrace: snafu::Backtrace,
+    },
+    #[snafu(display("No state available at height {}", height))]
+    HeightUnavailableErr {
+        height: u64,
+        #[cfg(feature = "backtraces")]
+        backtrace: snafu::Backtrace,
+    },
+    #[snafu(display("Host function {} failed: {}", name, msg))]
+    HostCallErr {
+        name: String,
+        msg: String,
+        #[cfg(feature = "backtraces")]
+        backtrace: snafu::Backtrace,
+    },
+    #[snafu(display("Contract wrote to storage in a read-only call"))]
+    ReadOnlyErr {
+        #[cfg(feature = "backtraces")]
+        backtrace: snafu::Backtrace,
+    },
+    #[snafu(display("Invalid request: {}", msg))]
+    RequestErr {
+        msg: String,
+        #[cfg(feature = "backtraces")]
+        backtrace: snafu::Backtrace,
+    },
     #[snafu(display("Resolving wasm function: {}", source))]
     ResolveErr {
         source: core_error::ResolveError,
@@ -62,6 +170,29 @@ pub enum Error {
         #[cfg(feature = "backtraces")]
         backtrace: snafu::Backtrace,
     },
+    #[snafu(display("Wasm code has too many functions. Got {}, limit is {}", count, limit))]
+    TooManyFunctionsErr {
+        count: usize,
+        limit: usize,
+        #[cfg(feature = "backtraces")]
+        backtrace: snafu::Backtrace,
+    },
+    #[snafu(display("{} too large. Got {} bytes, limit is {}", kind, size, limit))]
+    ValueTooLargeErr {
+        kind: &'static str,
+        size: usize,
+        limit: usize,
+        #[cfg(feature = "backtraces")]
+        backtrace: snafu::Backtrace,
+    },
+    #[snafu(display("Result exceeds {}. Got {}, limit is {}", limit, size, max))]
+    ResultTooLargeErr {
+        limit: &'static str,
+        size: usize,
+        max: usize,
+        #[cfg(feature = "backtraces")]
+        backtrace: snafu::Backtrace,
+    },
     #[snafu(display("Validating Wasm: {}", msg))]
     ValidationErr {
         msg: String,
@@ -78,6 +209,83 @@ pub enum Error {
 
 pub type Result<T, E = Error> = core::result::Result<T, E>;
 
+/// ErrorClass tells callers whether repeating a failed call can help
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorClass {
+    /// Caused by the contract or its input. Every node gets the same error for the same call,
+    /// so retrying is pointless.
+    Deterministic,
+    /// Caused by the local node (disk, caches, resources, concurrent state changes).
+    /// The same call may succeed on a retry.
+    Transient,
+}
+
+impl Error {
+    pub fn class(&self) -> ErrorClass {
+        match self {
+            Error::CacheErr { .. } => ErrorClass::Transient,
+            Error::IoErr { .. } => ErrorClass::Transient,
+            Error::IntegrityErr { .. } => ErrorClass::Transient,
+            Error::StateChangedErr { .. } => ErrorClass::Transient,
+            // memory or table could not be allocated for the instance
+            Error::WasmerErr {
+                source: core_error::Error::CreationError(_),
+                ..
+            } => ErrorClass::Transient,
+            _ => ErrorClass::Deterministic,
+        }
+    }
+
+    pub fn is_retryable(&self) -> bool {
+        self.class() == ErrorClass::Transient
+    }
+
+    /// Returns the codespace and a code for the error, for chains to return to clients.
+    /// Codes are stable: they never change or get reused, new errors get new codes.
+    /// Code 1 is left to the chain for internal errors and CONTRACT_ERR_CODE is for
+    /// calls where the contract itself returned an error.
+    pub fn abci_code(&self) -> (&'static str, u32) {
+        let code = match self {
+            Error::GasDepletionErr { .. } => 2,
+            Error::CompileErr { .. } => 4,
+            Error::ValidationErr { .. } => 5,
+            Error::CodeTooLargeErr { .. } => 6,
+            Error::MemoryTooLargeErr { .. } => 7,
+            Error::TooManyFunctionsErr { .. } => 8,
+            Error::FloatsNotAllowedErr { .. } => 9,
+            Error::ParseErr { .. } => 10,
+            Error::SerializeErr { .. } => 11,
+            Error::EnvErr { .. } => 12,
+            Error::RuntimeErr { .. } => 13,
+            Error::ResolveErr { .. } => 14,
+            Error::RegionTooSmallErr { .. } => 15,
+            Error::ValueTooLargeErr { .. } => 16,
+            Error::ResultTooLargeErr { .. } => 17,
+            Error::QuotaExceededErr { .. } => 18,
+            Error::PolicyErr { .. } => 19,
+            Error::ReadOnlyErr { .. } => 20,
+            Error::HostCallErr { .. } => 21,
+            Error::ImportLimitExceededErr { .. } => 22,
+            Error::CallDepthExceededErr { .. } => 23,
+            Error::RequestErr { .. } => 24,
+            Error::HeightUnavailableErr { .. } => 25,
+            Error::StateChangedErr { .. } => 26,
+            Error::CacheErr { .. } => 27,
+            Error::IoErr { .. } => 28,
+            Error::IntegrityErr { .. } => 29,
+            Error::WasmerErr { .. } => 30,
+            Error::ConfigErr { .. } => 31,
+            Error::InvalidMessageErr { .. } => 32,
+        };
+        (CODESPACE, code)
+    }
+}
+
+/// Codespace of the codes returned by Error::abci_code
+pub static CODESPACE: &str = "wasmvm";
+/// Code for a call the contract completed with an error result
+pub static CONTRACT_ERR_CODE: u32 = 3;
+
 pub trait CacheExt<T: Debug> {
     fn convert_cache(self) -> Result<T>;
 }
@@ -92,3 +300,45 @@ impl<T: Debug> CacheExt<T> for Result<T, CacheError> {
         })
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use snafu::ResultExt;
+
+    #[test]
+    fn classifies_errors() {
+        let err = ValidationErr { msg: "foo" }.fail::<()>().unwrap_err();
+        assert_eq!(err.class(), ErrorClass::Deterministic);
+        assert!(!err.is_retryable());
+
+        let err = IntegrityErr {}.fail::<()>().unwrap_err();
+        assert_eq!(err.class(), ErrorClass::Transient);
+        assert!(err.is_retryable());
+
+        let err = Err::<(), _>(io::Error::from(io::ErrorKind::NotFound))
+            .context(IoErr {})
+            .unwrap_err();
+        assert!(err.is_retryable());
+
+        let creation =
+            core_error::Error::CreationError(core_error::CreationError::UnableToCreateMemory);
+        let err = Err::<(), _>(creation).context(WasmerErr {}).unwrap_err();
+        assert!(err.is_retryable());
+    }
+
+    #[test]
+    fn abci_codes_are_stable() {
+        let err = GasDepletionErr { context: "Wasm" }
+            .fail::<()>()
+            .unwrap_err();
+        assert_eq!(err.abci_code(), ("wasmvm", 2));
+        let err = ValidationErr { msg: "foo" }.fail::<()>().unwrap_err();
+        assert_eq!(err.abci_code(), (CODESPACE, 5));
+        let err = CallDepthExceededErr { limit: 3u32 }
+            .fail::<()>()
+            .unwrap_err();
+        assert_eq!(err.abci_code(), (CODESPACE, 23));
+        assert_eq!(CONTRACT_ERR_CODE, 3);
+    }
+}
@@ -0,0 +1,96 @@
+//! Entry points for fuzzing the parsers that handle untrusted input on the host: the results
+//! contracts return, the Env passed in by the chain and uploaded code. Every function is
+//! deterministic and catches panics, so a fuzz target only has to fail on
+//! FuzzOutcome::Panicked. The targets for cargo-fuzz are in lib/vm/fuzz.
+use std::panic::{self, AssertUnwindSafe};
+
+use cosmwasm::serde::from_slice;
+use cosmwasm::types::{ContractResult, Env, QueryResult};
+
+use crate::calls::check_env;
+use crate::compatability::{analyze_code, check_api_compatibility, describe_code};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FuzzOutcome {
+    Accepted,
+    /// the parser returned an error, which is the expected result for most input
+    Rejected,
+    /// the parser panicked, with the panic message if it is a string
+    Panicked(String),
+}
+
+/// Parses data as the result of init or handle, like call_init and call_handle do
+pub fn parse_contract_result(data: &[u8]) -> FuzzOutcome {
+    run(|| from_slice::<ContractResult>(data).is_ok())
+}
+
+/// Parses data as the result of a query, like call_query does
+pub fn parse_query_result(data: &[u8]) -> FuzzOutcome {
+    run(|| from_slice::<QueryResult>(data).is_ok())
+}
+
+/// Parses data as an Env and checks it the way calls do before entering the contract
+pub fn parse_env(data: &[u8]) -> FuzzOutcome {
+    run(|| match from_slice::<Env>(data) {
+        Ok(env) => check_env(&env).is_ok(),
+        Err(_) => false,
+    })
+}
+
+/// Runs all checks CosmCache::save_wasm does on uploaded code before compiling it
+pub fn check_code(data: &[u8]) -> FuzzOutcome {
+    run(|| {
+        let analyzed = analyze_code(data).is_ok();
+        let described = describe_code(data).is_ok();
+        check_api_compatibility(data).is_ok() && analyzed && described
+    })
+}
+
+fn run<F: FnOnce() -> bool>(parse: F) -> FuzzOutcome {
+    match panic::catch_unwind(AssertUnwindSafe(parse)) {
+        Ok(true) => FuzzOutcome::Accepted,
+        Ok(false) => FuzzOutcome::Rejected,
+        Err(payload) => {
+            let msg = match payload.downcast_ref::<&str>() {
+                Some(msg) => msg.to_string(),
+                None => payload
+                    .downcast_ref::<String>()
+                    .cloned()
+                    .unwrap_or_default(),
+            };
+            FuzzOutcome::Panicked(msg)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    static CONTRACT_0_7: &[u8] = include_bytes!("../testdata/contract_0.7.wasm");
+
+    #[test]
+    fn parsers_accept_valid_and_reject_garbage() {
+        assert_eq!(
+            parse_contract_result(br#"{"err":"boom"}"#),
+            FuzzOutcome::Accepted
+        );
+        assert_eq!(
+            parse_query_result(br#"{"ok":"AAA="}"#),
+            FuzzOutcome::Accepted
+        );
+        assert_eq!(check_code(CONTRACT_0_7), FuzzOutcome::Accepted);
+        for garbage in &[&b""[..], b"\x00asm", b"{\"ok\":", b"\xff\xfe"] {
+            assert_eq!(parse_contract_result(garbage), FuzzOutcome::Rejected);
+            assert_eq!(parse_query_result(garbage), FuzzOutcome::Rejected);
+            assert_eq!(parse_env(garbage), FuzzOutcome::Rejected);
+            assert_eq!(check_code(garbage), FuzzOutcome::Rejected);
+        }
+    }
+
+    #[test]
+    fn run_catches_panics() {
+        let outcome = run(|| panic!("bad input"));
+        assert_eq!(outcome, FuzzOutcome::Panicked("bad input".to_string()));
+    }
+}
@@ -0,0 +1,425 @@
+use crate::trace::TraceEvent;
+
+/// Gas charged by the host functions, on top of the gas used to execute the wasm code.
+/// All values are in the same units as the wasm gas. The default charges nothing, so the gas
+/// of a call is what the wasm code uses; chains opt in with Instance::set_gas_config or
+/// Instance::set_gas_version.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct GasConfig {
+    pub read_base: u64,
+    pub read_per_byte: u64,
+    pub write_base: u64,
+    pub write_per_byte: u64,
+    /// cost of canonicalize_address and humanize_address
+    pub api_call: u64,
+    pub secp256k1_verify: u64,
+    pub secp256k1_recover_pubkey: u64,
+    pub ed25519_verify: u64,
+    /// ed25519_batch_verify is charged this for every signature, and once for an empty or
+    /// malformed batch
+    pub ed25519_batch_verify_per_signature: u64,
+    /// cost of debug_print, charged whether or not a LogSink is registered
+    pub debug_print_base: u64,
+    pub debug_print_per_byte: u64,
+    pub random_base: u64,
+    pub random_per_byte: u64,
+    pub check_gas: u64,
+}
+
+impl GasConfig {
+    pub fn read_cost(&self, key: &[u8], value: &Option<Vec<u8>>) -> u64 {
+        let len = key.len() + value.as_ref().map(|v| v.len()).unwrap_or(0);
+        self.read_base + self.read_per_byte * len as u64
+    }
+
+    pub fn write_cost(&self, key: &[u8], value: &[u8]) -> u64 {
+        self.write_base + self.write_per_byte * (key.len() + value.len()) as u64
+    }
+}
+
+/// GasVersion names a fixed table of host function costs. A chain that changes costs adds a
+/// new version and never edits an existing one, so old blocks replay with the costs they
+/// were executed with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum GasVersion {
+    V1,
+}
+
+impl GasVersion {
+    pub fn config(self) -> GasConfig {
+        match self {
+            // mirrors the KVStore gas config and signature verification costs of the cosmos-sdk
+            GasVersion::V1 => GasConfig {
+                read_base: 1000,
+                read_per_byte: 3,
+                write_base: 2000,
+                write_per_byte: 30,
+                api_call: 100,
+                secp256k1_verify: 1000,
+                secp256k1_recover_pubkey: 1000,
+                ed25519_verify: 590,
+                // batches are verified about twice as fast
+                ed25519_batch_verify_per_signature: 295,
+                debug_print_base: 100,
+                debug_print_per_byte: 1,
+                // one sha256 per 32 bytes
+                random_base: 100,
+                random_per_byte: 3,
+                check_gas: 10,
+            },
+        }
+    }
+}
+
+/// GasSchedule knows from which block height each GasVersion applies
+#[derive(Debug, Clone, PartialEq)]
+pub struct GasSchedule {
+    // sorted by height, the first entry is at height 0
+    activations: Vec<(u64, GasVersion)>,
+}
+
+impl GasSchedule {
+    pub fn new(initial: GasVersion) -> Self {
+        GasSchedule {
+            activations: vec![(0, initial)],
+        }
+    }
+
+    /// Makes version apply from height on, replacing later activations
+    pub fn activate(&mut self, height: u64, version: GasVersion) {
+        self.activations.retain(|&(h, _)| h < height);
+        self.activations.push((height, version));
+    }
+
+    pub fn version_at(&self, height: u64) -> GasVersion {
+        self.activations
+            .iter()
+            .rev()
+            .find(|&&(h, _)| h <= height)
+            .expect("schedule starts at height 0")
+            .1
+    }
+}
+
+/// GasReport breaks down the gas used by the last call on an Instance.
+/// With backends that do not meter wasm gas (cranelift), wasm_gas is 0
+/// and remaining does not change.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct GasReport {
+    /// all gas used by the call, ie. wasm_gas + used_externally
+    pub used_internally: u64,
+    /// gas charged by host functions
+    pub used_externally: u64,
+    /// gas left after the call
+    pub remaining: u64,
+    /// part of used_externally charged for storage access
+    pub storage_gas: u64,
+    /// gas used to execute the wasm code
+    pub wasm_gas: u64,
+}
+
+/// Gas used by all calls of a block, as returned by BlockGasTracker::finish_block.
+/// Query gas is not part of the execution totals.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct BlockGasUsage {
+    /// number of init and handle calls
+    pub executions: u64,
+    /// used_internally of all executions
+    pub execution_gas: u64,
+    pub wasm_gas: u64,
+    pub storage_gas: u64,
+    /// used_externally of all executions, including storage_gas
+    pub used_externally: u64,
+    pub queries: u64,
+    /// used_internally of all queries
+    pub query_gas: u64,
+}
+
+/// BlockGasTracker sums up the GasReports of all calls in a block.
+/// With a limit set, it tells when the execution gas of the block reaches the ceiling.
+#[derive(Debug, Default, Clone)]
+pub struct BlockGasTracker {
+    limit: Option<u64>,
+    usage: BlockGasUsage,
+}
+
+impl BlockGasTracker {
+    pub fn new() -> Self {
+        BlockGasTracker::default()
+    }
+
+    /// Creates a tracker enforcing a ceiling on the execution gas of every block
+    pub fn with_limit(limit: u64) -> Self {
+        BlockGasTracker {
+            limit: Some(limit),
+            usage: BlockGasUsage::default(),
+        }
+    }
+
+    /// Adds the report of an init or handle call
+    pub fn record_execution(&mut self, report: &GasReport) {
+        let usage = &mut self.usage;
+        usage.executions += 1;
+        usage.execution_gas = usage.execution_gas.saturating_add(report.used_internally);
+        usage.wasm_gas = usage.wasm_gas.saturating_add(report.wasm_gas);
+        usage.storage_gas = usage.storage_gas.saturating_add(report.storage_gas);
+        usage.used_externally = usage.used_externally.saturating_add(report.used_externally);
+    }
+
+    /// Adds the report of a query made while processing the block
+    pub fn record_query(&mut self, report: &GasReport) {
+        self.usage.queries += 1;
+        self.usage.query_gas = self.usage.query_gas.saturating_add(report.used_internally);
+    }
+
+    /// Execution gas left in this block, None if there is no limit.
+    /// Use it to cap the gas limit of the next call.
+    pub fn remaining(&self) -> Option<u64> {
+        self.limit
+            .map(|limit| limit.saturating_sub(self.usage.execution_gas))
+    }
+
+    /// true once the execution gas reached the limit
+    pub fn is_exhausted(&self) -> bool {
+        self.remaining() == Some(0)
+    }
+
+    /// Usage of the block so far
+    pub fn usage(&self) -> BlockGasUsage {
+        self.usage
+    }
+
+    /// Returns the usage of the finished block and starts counting the next one
+    pub fn finish_block(&mut self) -> BlockGasUsage {
+        let usage = self.usage;
+        self.usage = BlockGasUsage::default();
+        usage
+    }
+}
+
+/// Host side gas accounting of the currently running call
+#[derive(Default)]
+pub(crate) struct GasState {
+    pub config: GasConfig,
+    pub externally_used: u64,
+    pub storage_used: u64,
+    /// in audit mode every charge is reported as a TraceEvent::GasCharge
+    pub audit: bool,
+    /// sequence number of the next GasCharge of this call
+    seq: u64,
+    /// gas left after the last charge, to attribute the difference to wasm
+    checkpoint: u64,
+    /// gas left when the call started
+    start: u64,
+}
+
+impl GasState {
+    pub fn reset(&mut self, gas_left: u64) {
+        self.externally_used = 0;
+        self.storage_used = 0;
+        self.seq = 0;
+        self.checkpoint = gas_left;
+        self.start = gas_left;
+    }
+
+    /// Returns all gas used since the call started. It never decreases during a call
+    /// and, unlike time, is the same on every node.
+    pub fn progress(&self, gas_left: u64) -> u64 {
+        self.report(self.start, gas_left).used_internally
+    }
+
+    /// Returns the events for a host charge of amount, preceded by the wasm gas
+    /// used since the last charge. Empty if not in audit mode.
+    pub fn audit(&mut self, source: &str, amount: u64, gas_left: u64) -> Vec<TraceEvent> {
+        let mut events = self.audit_wasm(gas_left);
+        if self.audit {
+            events.push(self.gas_charge(source, amount));
+            self.checkpoint = gas_left.saturating_sub(amount);
+        }
+        events
+    }
+
+    /// Returns the event for the wasm gas used since the last charge, if any
+    pub fn audit_wasm(&mut self, gas_left: u64) -> Vec<TraceEvent> {
+        let amount = self.checkpoint.saturating_sub(gas_left);
+        if !self.audit || amount == 0 {
+            return vec![];
+        }
+        self.checkpoint = gas_left;
+        vec![self.gas_charge("wasm", amount)]
+    }
+
+    fn gas_charge(&mut self, source: &str, amount: u64) -> TraceEvent {
+        let event = TraceEvent::GasCharge {
+            seq: self.seq,
+            source: source.to_string(),
+            amount,
+        };
+        self.seq += 1;
+        event
+    }
+
+    pub fn report(&self, gas_before: u64, gas_after: u64) -> GasReport {
+        let wasm_gas = gas_before
+            .saturating_sub(gas_after)
+            .saturating_sub(self.externally_used);
+        GasReport {
+            used_internally: wasm_gas + self.externally_used,
+            used_externally: self.externally_used,
+            remaining: gas_after,
+            storage_gas: self.storage_used,
+            wasm_gas,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn schedule_selects_version_by_height() {
+        let mut schedule = GasSchedule::new(GasVersion::V1);
+        assert_eq!(schedule.version_at(0), GasVersion::V1);
+        assert_eq!(schedule.version_at(1_000_000), GasVersion::V1);
+
+        schedule.activate(100, GasVersion::V1);
+        assert_eq!(schedule.version_at(99), GasVersion::V1);
+        assert_eq!(schedule.version_at(100), GasVersion::V1);
+        assert_eq!(schedule.activations.len(), 2);
+
+        // activating earlier drops the later entries
+        schedule.activate(50, GasVersion::V1);
+        assert_eq!(
+            schedule.activations,
+            vec![(0, GasVersion::V1), (50, GasVersion::V1)]
+        );
+        assert_ne!(GasVersion::V1.config(), GasConfig::default());
+    }
+
+    #[test]
+    fn progress_counts_all_gas_of_the_call() {
+        let mut gas = GasState::default();
+        gas.reset(1000);
+        assert_eq!(gas.progress(1000), 0);
+        // wasm gas
+        assert_eq!(gas.progress(900), 100);
+        // host gas, also without wasm metering
+        gas.externally_used = 50;
+        assert_eq!(gas.progress(850), 150);
+        assert_eq!(gas.progress(1000), 50);
+
+        gas.reset(800);
+        assert_eq!(gas.progress(800), 0);
+    }
+
+    #[test]
+    fn storage_costs_depend_on_length() {
+        let config = GasVersion::V1.config();
+        assert_eq!(config.read_cost(b"foo", &None), 1009);
+        assert_eq!(config.read_cost(b"foo", &Some(b"bar".to_vec())), 1018);
+        assert_eq!(config.write_cost(b"foo", b"bar"), 2180);
+
+        // host charges are opt-in
+        let config = GasConfig::default();
+        assert_eq!(config.read_cost(b"foo", &Some(b"bar".to_vec())), 0);
+        assert_eq!(config.write_cost(b"foo", b"bar"), 0);
+    }
+
+    #[test]
+    fn report_splits_wasm_and_host_gas() {
+        let state = GasState {
+            config: GasConfig::default(),
+            externally_used: 300,
+            storage_used: 200,
+            ..GasState::default()
+        };
+        let report = state.report(10_000, 9_000);
+        assert_eq!(
+            report,
+            GasReport {
+                used_internally: 1000,
+                used_externally: 300,
+                remaining: 9000,
+                storage_gas: 200,
+                wasm_gas: 700,
+            }
+        );
+
+        // without metering, gas left does not change
+        let report = state.report(10_000, 10_000);
+        assert_eq!(report.wasm_gas, 0);
+        assert_eq!(report.used_internally, 300);
+    }
+
+    #[test]
+    fn audit_attributes_gas_to_wasm_and_host() {
+        let mut state = GasState::default();
+        state.reset(10_000);
+        // nothing is reported outside of audit mode
+        assert_eq!(state.audit("read_db", 1000, 9_500), vec![]);
+
+        state.audit = true;
+        state.reset(10_000);
+        let charge = |seq, source: &str, amount| TraceEvent::GasCharge {
+            seq,
+            source: source.to_string(),
+            amount,
+        };
+        assert_eq!(
+            state.audit("read_db", 1000, 9_500),
+            vec![charge(0, "wasm", 500), charge(1, "read_db", 1000)]
+        );
+        // no wasm gas used in between
+        assert_eq!(
+            state.audit("write_db", 2000, 8_500),
+            vec![charge(2, "write_db", 2000)]
+        );
+        assert_eq!(state.audit_wasm(6_000), vec![charge(3, "wasm", 500)]);
+    }
+
+    #[test]
+    fn block_gas_tracker_sums_up_calls() {
+        let execution = GasReport {
+            used_internally: 1000,
+            used_externally: 300,
+            remaining: 9000,
+            storage_gas: 200,
+            wasm_gas: 700,
+        };
+        let query = GasReport {
+            used_internally: 50,
+            ..GasReport::default()
+        };
+
+        let mut tracker = BlockGasTracker::with_limit(2500);
+        tracker.record_execution(&execution);
+        tracker.record_query(&query);
+        tracker.record_execution(&execution);
+        assert_eq!(tracker.remaining(), Some(500));
+        assert!(!tracker.is_exhausted());
+
+        tracker.record_execution(&execution);
+        assert_eq!(tracker.remaining(), Some(0));
+        assert!(tracker.is_exhausted());
+
+        let usage = tracker.finish_block();
+        assert_eq!(
+            usage,
+            BlockGasUsage {
+                executions: 3,
+                execution_gas: 3000,
+                wasm_gas: 2100,
+                storage_gas: 600,
+                used_externally: 900,
+                queries: 1,
+                query_gas: 50,
+            }
+        );
+
+        // the next block starts from scratch
+        assert_eq!(tracker.usage(), BlockGasUsage::default());
+        assert_eq!(tracker.remaining(), Some(2500));
+        assert_eq!(BlockGasTracker::new().remaining(), None);
+    }
+}
@@ -0,0 +1,66 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::errors::Result;
+
+/// A host function takes the payload from the contract and returns the response
+/// and the gas to charge for the call. An error aborts the contract call.
+pub type HostFunction = Box<dyn Fn(&[u8]) -> Result<(Vec<u8>, u64)>>;
+
+/// HostFunctions holds the chain specific functions contracts can call through the host_call
+/// import. A function is only callable if it is registered and allowed, so a node can ship
+/// functions that the chain enables later.
+#[derive(Default)]
+pub struct HostFunctions {
+    functions: BTreeMap<String, HostFunction>,
+    allowed: BTreeSet<String>,
+}
+
+impl HostFunctions {
+    pub fn new() -> Self {
+        HostFunctions::default()
+    }
+
+    /// Registers func under name, replacing any function registered before
+    pub fn register(&mut self, name: &str, func: HostFunction) {
+        self.functions.insert(name.to_string(), func);
+    }
+
+    /// Replaces the allowlist. Usually set from the chain's parameters.
+    pub fn set_allowed(&mut self, names: &[&str]) {
+        self.allowed = names.iter().map(|name| name.to_string()).collect();
+    }
+
+    pub fn is_callable(&self, name: &str) -> bool {
+        self.allowed.contains(name) && self.functions.contains_key(name)
+    }
+
+    /// Returns None if name is not callable
+    pub(crate) fn call(&self, name: &str, payload: &[u8]) -> Option<Result<(Vec<u8>, u64)>> {
+        if !self.allowed.contains(name) {
+            return None;
+        }
+        self.functions.get(name).map(|func| func(payload))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn only_allowed_functions_are_called() {
+        let mut functions = HostFunctions::new();
+        functions.register("echo", Box::new(|payload| Ok((payload.to_vec(), 5))));
+        assert!(!functions.is_callable("echo"));
+        assert!(functions.call("echo", b"hi").is_none());
+
+        functions.set_allowed(&["echo", "missing"]);
+        assert!(functions.is_callable("echo"));
+        assert!(!functions.is_callable("missing"));
+        assert_eq!(
+            functions.call("echo", b"hi").unwrap().unwrap(),
+            (b"hi".to_vec(), 5)
+        );
+        assert!(functions.call("missing", b"hi").is_none());
+    }
+}
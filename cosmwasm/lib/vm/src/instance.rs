@@ -1,4 +1,8 @@
+#[cfg(feature = "coverage")]
+use std::collections::BTreeSet;
+use std::collections::{BTreeMap, HashMap};
 use std::marker::PhantomData;
+use std::time::{Duration, Instant};
 
 use snafu::ResultExt;
 pub use wasmer_runtime_core::typed_func::Func;
@@ -10,22 +14,93 @@ use wasmer_runtime_core::{
 };
 
 use cosmwasm::traits::{Api, Extern, Storage};
+use cosmwasm::types::CanonicalAddr;
 
 use crate::backends::{compile, get_gas, set_gas};
+#[cfg(feature = "call-depth")]
+use crate::call_depth::{call_depth_imports, CallDepth};
+#[cfg(feature = "coverage")]
+use crate::context::take_coverage;
 use crate::context::{
-    do_canonical_address, do_human_address, do_read, do_write, leave_storage, setup_context,
-    take_storage, with_storage_from_context,
+    clear_prefetched, do_canonical_address, do_check_gas, do_debug_print, do_ed25519_batch_verify,
+    do_ed25519_verify, do_host_call, do_human_address, do_random, do_read,
+    do_secp256k1_recover_pubkey, do_secp256k1_verify, do_write, finish_gas_audit, gas_report,
+    leave_storage, limits, prefetch, record_trace, reset_debug_messages, reset_gas_state,
+    set_gas_audit, set_gas_config, set_host_functions, set_journal, set_limits, set_log_sink,
+    set_overlay, set_random, set_read_only, set_trace_sink, setup_context, take_import_calls,
+    take_journal, take_log_sink, take_overlay, take_storage, take_storage_usage, take_trace_sink,
+    with_storage_from_context,
 };
-use crate::errors::{ResolveErr, Result, RuntimeErr, WasmerErr};
-use crate::memory::{read_region, write_region};
+#[cfg(feature = "coverage")]
+use crate::coverage::coverage_imports;
+use crate::errors::{PolicyErr, ResolveErr, Result, RuntimeErr, WasmerErr};
+use crate::gas::{GasConfig, GasReport, GasVersion};
+use crate::host_functions::HostFunctions;
+use crate::journal::Journal;
+use crate::limits::Limits;
+use crate::memory::{read_region, read_region_chunks, read_region_limited, write_region};
+use crate::messages::MessageCheck;
+use crate::policy::ExecutionPolicy;
+use crate::prepare::Overlay;
+use crate::random::RandomState;
+use crate::resources::{ResourceReport, StorageUsageReport};
+use crate::stats::ContractStats;
+use crate::trace::{LogSink, TraceEvent, TraceSink};
 
 pub struct Instance<S: Storage + 'static, A: Api + 'static> {
     wasmer_instance: wasmer_runtime_core::instance::Instance,
     pub api: A,
+    // Buffers we allocated in wasm memory (offset -> length) that were neither
+    // deallocated by us nor handed over to the contract
+    allocations: HashMap<u32, usize>,
+    gas_report: GasReport,
+    resource_report: ResourceReport,
+    storage_usage: StorageUsageReport,
+    // None for code compiled without the call depth limit
+    #[cfg(feature = "call-depth")]
+    call_depth: Option<CallDepth>,
+    // calls since the instance was created, merged into the cache's stats when it is stored
+    stats: ContractStats,
+    // when the current call started
+    call_started: Instant,
+    code_id: Option<Vec<u8>>,
+    // the contract of the current init or handle call
+    contract: Option<CanonicalAddr>,
+    policy: Option<Box<dyn ExecutionPolicy>>,
+    message_check: Option<MessageCheck>,
+    slow_call_threshold: Option<Duration>,
+    // position of the next call in its block, separates the random bytes of calls
+    call_index: u64,
     // This does not store data but only fixes type information
     type_storage: PhantomData<S>,
 }
 
+/// Summary of the host allocations freed by `Instance::reclaim_allocations`
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct ReclaimedBuffers {
+    pub count: usize,
+    pub bytes: usize,
+}
+
+/// Result of `Instance::health_check`
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct HealthReport {
+    /// a buffer could be allocated in the contract, read back and freed
+    pub memory_ok: bool,
+    /// the instance holds its storage, which is gone after it was recycled
+    pub storage_attached: bool,
+    /// buffers the host allocated that are neither freed nor handed over
+    pub outstanding_allocations: usize,
+    pub gas_left: u64,
+}
+
+impl HealthReport {
+    /// true if the instance can serve calls
+    pub fn is_healthy(&self) -> bool {
+        self.memory_ok && self.storage_attached
+    }
+}
+
 impl<S, A> Instance<S, A>
 where
     S: Storage + 'static,
@@ -46,31 +121,111 @@ where
                 // A prepared and sufficiently large memory Region is expected at value_ptr that points to pre-allocated memory.
                 // Returns length of the value in bytes on success. Returns negative value on error. An incomplete list of error codes is:
                 //   value region too small: -1000002
+                // Keys over the limit abort the call.
                 // Ownership of both input and output pointer is not transferred to the host.
-                "read_db" => Func::new(move |ctx: &mut Ctx, key_ptr: u32, value_ptr: u32| -> i32 {
+                "read_db" => Func::new(move |ctx: &mut Ctx, key_ptr: u32, value_ptr: u32| -> Result<i32> {
                     do_read::<S>(ctx, key_ptr, value_ptr)
                 }),
                 // Writes the given value into the database entry at the given key.
+                // Keys or values over the limits abort the call.
                 // Ownership of both input and output pointer is not transferred to the host.
-                "write_db" => Func::new(move |ctx: &mut Ctx, key_ptr: u32, value_ptr: u32| {
+                "write_db" => Func::new(move |ctx: &mut Ctx, key_ptr: u32, value_ptr: u32| -> Result<()> {
                     do_write::<S>(ctx, key_ptr, value_ptr)
                 }),
                 // Reads human address from human_ptr and writes canonicalized representation to canonical_ptr.
                 // A prepared and sufficiently large memory Region is expected at canonical_ptr that points to pre-allocated memory.
                 // Returns negative value on error. Returns length of the canoncal address on success.
                 // Ownership of both input and output pointer is not transferred to the host.
-                "canonicalize_address" => Func::new(move |ctx: &mut Ctx, human_ptr: u32, canonical_ptr: u32| -> i32 {
-                    do_canonical_address(api, ctx, human_ptr, canonical_ptr)
+                "canonicalize_address" => Func::new(move |ctx: &mut Ctx, human_ptr: u32, canonical_ptr: u32| -> Result<i32> {
+                    do_canonical_address::<S, A>(api, ctx, human_ptr, canonical_ptr)
                 }),
                 // Reads canonical address from canonical_ptr and writes humanized representation to human_ptr.
                 // A prepared and sufficiently large memory Region is expected at human_ptr that points to pre-allocated memory.
                 // Returns negative value on error. Returns length of the human address on success.
                 // Ownership of both input and output pointer is not transferred to the host.
-                "humanize_address" => Func::new(move |ctx: &mut Ctx, canonical_ptr: u32, human_ptr: u32| -> i32 {
-                    do_human_address(api, ctx, canonical_ptr, human_ptr)
+                "humanize_address" => Func::new(move |ctx: &mut Ctx, canonical_ptr: u32, human_ptr: u32| -> Result<i32> {
+                    do_human_address::<S, A>(api, ctx, canonical_ptr, human_ptr)
+                }),
+                // Verifies a secp256k1 signature (64 bytes r, s) of a 32 byte message hash against a
+                // compressed or uncompressed public key.
+                // Returns 0 if the signature is valid, 1 if it is not, -1 if an input is malformed and -2 if the
+                // host does not support the scheme.
+                // Ownership of all input pointers is not transferred to the host.
+                "secp256k1_verify" => Func::new(move |ctx: &mut Ctx, hash_ptr: u32, signature_ptr: u32, pubkey_ptr: u32| -> Result<i32> {
+                    do_secp256k1_verify::<S, A>(api, ctx, hash_ptr, signature_ptr, pubkey_ptr)
+                }),
+                // Recovers the public key of a secp256k1 signature of a 32 byte message hash and writes it,
+                // uncompressed, to pubkey_ptr. A prepared region of at least 65 bytes is expected there.
+                // Returns the length of the public key on success, -2 if the host does not support the scheme
+                // and another negative value on error.
+                // Ownership of all input and output pointers is not transferred to the host.
+                "secp256k1_recover_pubkey" => Func::new(move |ctx: &mut Ctx, hash_ptr: u32, signature_ptr: u32, recovery_param: u32, pubkey_ptr: u32| -> Result<i32> {
+                    do_secp256k1_recover_pubkey::<S, A>(api, ctx, hash_ptr, signature_ptr, recovery_param, pubkey_ptr)
+                }),
+                // Verifies an ed25519 signature of the message against a 32 byte public key.
+                // Returns 0 if the signature is valid, 1 if it is not, -1 if an input is malformed and -2 if the
+                // host does not support the scheme.
+                // Ownership of all input pointers is not transferred to the host.
+                "ed25519_verify" => Func::new(move |ctx: &mut Ctx, message_ptr: u32, signature_ptr: u32, pubkey_ptr: u32| -> Result<i32> {
+                    do_ed25519_verify::<S, A>(api, ctx, message_ptr, signature_ptr, pubkey_ptr)
+                }),
+                // Verifies a batch of ed25519 signatures. Each region holds a list encoded with
+                // cosmwasm::encoding::encode_sections.
+                // Returns 0 if all signatures are valid, 1 if any is not, -1 if an input is malformed and -2 if
+                // the host does not support the scheme.
+                // Ownership of all input pointers is not transferred to the host.
+                "ed25519_batch_verify" => Func::new(move |ctx: &mut Ctx, messages_ptr: u32, signatures_ptr: u32, pubkeys_ptr: u32| -> Result<i32> {
+                    do_ed25519_batch_verify::<S, A>(api, ctx, messages_ptr, signatures_ptr, pubkeys_ptr)
+                }),
+                // Passes the message to the registered LogSink, if any. Messages are only for debugging
+                // and are dropped on nodes without a sink. Gas is charged in any case.
+                // Ownership of the input pointer is not transferred to the host.
+                "debug_print" => Func::new(move |ctx: &mut Ctx, message_ptr: u32| -> Result<()> {
+                    do_debug_print::<S>(ctx, message_ptr)
+                }),
+                // Fills the region at buffer_ptr (up to its length) with random bytes and returns their number.
+                // The bytes are derived from the block entropy, the contract address and the call index set
+                // by the embedder: every node returns the same bytes for the same call and they are only as
+                // unpredictable as the block entropy.
+                // Repeated calls within one init or handle continue the sequence.
+                // Returns -1 if there is no entropy (queries, or blocks without it) and -2 if more than
+                // 64 KiB are requested.
+                // Ownership of the output pointer is not transferred to the host.
+                "random" => Func::new(move |ctx: &mut Ctx, buffer_ptr: u32| -> Result<i32> {
+                    do_random::<S>(ctx, buffer_ptr)
+                }),
+                // Writes the gas left and the execution progress of the call, both as big endian u64,
+                // to the region at result_ptr and returns 16. Progress is all gas used by the call so far,
+                // it only grows and is the same on every node, so contracts can limit themselves
+                // without reading a clock. Returns a negative value if the region is too small.
+                // Ownership of the output pointer is not transferred to the host.
+                "check_gas" => Func::new(move |ctx: &mut Ctx, result_ptr: u32| -> Result<i32> {
+                    do_check_gas::<S>(ctx, result_ptr)
+                }),
+                // Calls the chain specific host function named by the region at name_ptr with the payload
+                // at payload_ptr and writes its response to result_ptr. Returns the length of the response,
+                // -1 if the chain does not provide the function and a negative value if the region is too small.
+                // Errors of the function abort the call.
+                // Ownership of all pointers is not transferred to the host.
+                "host_call" => Func::new(move |ctx: &mut Ctx, name_ptr: u32, payload_ptr: u32, result_ptr: u32| -> Result<i32> {
+                    do_host_call::<S>(ctx, name_ptr, payload_ptr, result_ptr)
                 }),
             },
         };
+        // instrumented code reports the blocks it enters
+        #[cfg(feature = "coverage")]
+        let import_obj = {
+            let mut import_obj = import_obj;
+            import_obj.extend(coverage_imports::<S>());
+            import_obj
+        };
+        // instrumented code aborts calls nested deeper than max_call_depth
+        #[cfg(feature = "call-depth")]
+        let import_obj = {
+            let mut import_obj = import_obj;
+            import_obj.extend(call_depth_imports::<S>());
+            import_obj
+        };
         let wasmer_instance = module.instantiate(&import_obj).context(WasmerErr {})?;
         Ok(Instance::from_wasmer(wasmer_instance, deps, gas_limit))
     }
@@ -82,9 +237,25 @@ where
     ) -> Self {
         set_gas(&mut wasmer_instance, gas_limit);
         leave_storage(wasmer_instance.context(), Some(deps.storage));
+        #[cfg(feature = "call-depth")]
+        let call_depth = CallDepth::from_instance(&wasmer_instance);
         Instance {
             wasmer_instance: wasmer_instance,
             api: deps.api,
+            allocations: HashMap::new(),
+            gas_report: GasReport::default(),
+            resource_report: ResourceReport::default(),
+            storage_usage: StorageUsageReport::default(),
+            stats: ContractStats::default(),
+            #[cfg(feature = "call-depth")]
+            call_depth,
+            call_started: Instant::now(),
+            code_id: None,
+            contract: None,
+            policy: None,
+            message_check: None,
+            slow_call_threshold: None,
+            call_index: 0,
             type_storage: PhantomData::<S> {},
         }
     }
@@ -92,6 +263,11 @@ where
     /// Takes ownership of instance and decomposes it into its components.
     /// The components we want to preserve are returned, the rest is dropped.
     pub fn recycle(instance: Self) -> (wasmer_runtime_core::Instance, Option<Extern<S, A>>) {
+        // a trace sink belongs to the caller, not to the cached instance
+        let _ = take_trace_sink::<S>(instance.wasmer_instance.context());
+        let _ = take_log_sink::<S>(instance.wasmer_instance.context());
+        let _ = take_overlay::<S>(instance.wasmer_instance.context());
+        let _ = take_journal::<S>(instance.wasmer_instance.context());
         let ext = if let Some(storage) = take_storage(instance.wasmer_instance.context()) {
             Some(Extern {
                 storage: storage,
@@ -107,10 +283,321 @@ where
         get_gas(&self.wasmer_instance)
     }
 
+    // sets the gas left for the following calls
+    pub(crate) fn set_gas(&mut self, gas_limit: u64) {
+        set_gas(&mut self.wasmer_instance, gas_limit);
+    }
+
+    /// Sets the policy that decides whether the following calls may run, replacing any
+    /// set before
+    pub fn set_execution_policy(&mut self, policy: Box<dyn ExecutionPolicy>) {
+        self.policy = Some(policy);
+    }
+
+    /// Makes the following init and handle calls check the messages they return, replacing
+    /// any check set before. Without one messages are left to the chain.
+    pub fn set_message_check(&mut self, check: MessageCheck) {
+        self.message_check = Some(check);
+    }
+
+    pub(crate) fn message_check(&self) -> Option<&MessageCheck> {
+        self.message_check.as_ref()
+    }
+
+    /// Tells the instance which code it runs, so the execution policy can check it.
+    /// CosmCache::get_instance sets this.
+    pub fn set_code_id(&mut self, id: &[u8]) {
+        self.code_id = Some(id.to_vec());
+    }
+
+    // fails with PolicyErr if the execution policy refuses the code or the contract
+    pub(crate) fn check_policy(&self, contract: Option<&CanonicalAddr>) -> Result<()> {
+        let policy = match &self.policy {
+            Some(policy) => policy,
+            None => return Ok(()),
+        };
+        if let Some(id) = &self.code_id {
+            if !policy.allow_code(id) {
+                return PolicyErr {
+                    msg: format!("code {} is blocked", hex::encode(id)),
+                }
+                .fail();
+            }
+        }
+        if let Some(contract) = contract {
+            if !policy.allow_contract(contract) {
+                return PolicyErr {
+                    msg: format!("contract {} is blocked", hex::encode(contract.as_slice())),
+                }
+                .fail();
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets the gas charged by host functions for all following calls
+    pub fn set_gas_config(&mut self, config: GasConfig) {
+        set_gas_config::<S>(self.wasmer_instance.context(), config);
+    }
+
+    /// Sets the gas costs of version for all following calls. Chains pass the version
+    /// their GasSchedule gives for the height of the block, before every call.
+    pub fn set_gas_version(&mut self, version: GasVersion) {
+        self.set_gas_config(version.config());
+    }
+
+    /// Sets the size limits on contract data for all following calls
+    pub fn set_limits(&mut self, limits: Limits) {
+        set_limits::<S>(self.wasmer_instance.context(), limits);
+    }
+
+    pub(crate) fn limits(&self) -> Limits {
+        limits::<S>(self.wasmer_instance.context())
+    }
+
+    /// Sets the position of the next init or handle call in its block. The embedder counts it
+    /// up for every call of the block, so calls to the same contract get different random bytes.
+    /// It must be the same on every node.
+    pub fn set_call_index(&mut self, index: u64) {
+        self.call_index = index;
+    }
+
+    pub(crate) fn call_index(&self) -> u64 {
+        self.call_index
+    }
+
+    // seeds the random import for the next call, None disables it
+    pub(crate) fn set_random(&mut self, random: Option<RandomState>) {
+        set_random::<S>(self.wasmer_instance.context(), random);
+    }
+
+    // reads the result of a call, enforcing the result size limit
+    pub(crate) fn read_result(&self, ptr: u32) -> Result<Vec<u8>> {
+        let ctx = self.wasmer_instance.context();
+        let limit = limits::<S>(ctx).max_result_length;
+        read_region_limited(ctx, ptr, limit, "Result")
+    }
+
+    // passes the result of a call to func in chunks, without a size limit
+    pub(crate) fn read_result_chunks<F: FnMut(&[u8]) -> Result<()>>(
+        &self,
+        ptr: u32,
+        chunk_size: usize,
+        func: F,
+    ) -> Result<usize> {
+        read_region_chunks(self.wasmer_instance.context(), ptr, chunk_size, func)
+    }
+
+    /// Returns where the gas of the last init, handle or query call went
+    pub fn gas_report(&self) -> GasReport {
+        self.gas_report
+    }
+
+    // starts accounting for a new call, returns the gas left at its start
+    pub(crate) fn start_gas_report(&mut self) -> u64 {
+        let gas_left = self.get_gas();
+        reset_gas_state::<S>(self.wasmer_instance.context(), gas_left);
+        reset_debug_messages::<S>(self.wasmer_instance.context());
+        let _ = take_import_calls::<S>(self.wasmer_instance.context());
+        let _ = take_storage_usage::<S>(self.wasmer_instance.context());
+        #[cfg(feature = "call-depth")]
+        if let Some(call_depth) = &self.call_depth {
+            call_depth.reset(self.limits().max_call_depth);
+        }
+        self.call_started = Instant::now();
+        gas_left
+    }
+
+    pub(crate) fn finish_gas_report(&mut self, gas_before: u64) {
+        let gas_after = self.get_gas();
+        finish_gas_audit::<S>(self.wasmer_instance.context(), gas_after);
+        self.gas_report = gas_report::<S>(self.wasmer_instance.context(), gas_before, gas_after);
+        let ctx = self.wasmer_instance.context();
+        self.resource_report = ResourceReport {
+            memory_pages: ctx.memory(0).size().0,
+            import_calls: take_import_calls::<S>(ctx)
+                .into_iter()
+                .map(|(name, count)| (name.to_string(), count))
+                .collect(),
+            duration: self.call_started.elapsed(),
+        };
+        self.storage_usage = take_storage_usage::<S>(ctx);
+    }
+
+    /// Returns the memory, imports and time the last init, handle or query call used
+    pub fn resource_report(&self) -> &ResourceReport {
+        &self.resource_report
+    }
+
+    /// Calls taking longer than threshold are reported to the trace sink as
+    /// TraceEvent::SlowCall, so operators can find the contracts that slow down blocks.
+    /// None, the default, turns the detection off.
+    pub fn set_slow_call_threshold(&mut self, threshold: Option<Duration>) {
+        self.slow_call_threshold = threshold;
+    }
+
+    pub(crate) fn prefetch(&mut self, keys: &[Vec<u8>]) {
+        prefetch::<S>(self.wasmer_instance.context(), keys);
+    }
+
+    pub(crate) fn clear_prefetched(&mut self) {
+        clear_prefetched::<S>(self.wasmer_instance.context());
+    }
+
+    pub(crate) fn set_read_only(&mut self, read_only: bool) {
+        set_read_only::<S>(self.wasmer_instance.context(), read_only);
+    }
+
+    pub(crate) fn set_contract(&mut self, contract: Option<CanonicalAddr>) {
+        self.contract = contract;
+    }
+
+    // reports the last call if it exceeded the slow call threshold, call after finish_gas_report
+    pub(crate) fn trace_slow_call(&self, entry_point: &str, input_len: usize) {
+        let report = &self.resource_report;
+        match self.slow_call_threshold {
+            Some(threshold) if report.duration > threshold => {}
+            _ => return,
+        }
+        self.record_trace(TraceEvent::SlowCall {
+            entry_point: entry_point.to_string(),
+            code_id: self.code_id.clone(),
+            contract: self.contract.clone(),
+            duration: report.duration,
+            gas_used: self.gas_report.used_internally,
+            import_calls: report.import_calls.clone(),
+            memory_pages: report.memory_pages,
+            input_len,
+        });
+    }
+
+    /// Returns the blocks instrumented code entered since the last time, see coverage::instrument
+    #[cfg(feature = "coverage")]
+    pub fn take_coverage(&mut self) -> BTreeSet<u32> {
+        take_coverage::<S>(self.wasmer_instance.context())
+    }
+
+    /// Returns the storage writes of the last init or handle call
+    pub fn storage_usage(&self) -> StorageUsageReport {
+        self.storage_usage
+    }
+
+    // counts the last call, call once the result is known
+    pub(crate) fn record_call(&mut self, failed: bool) {
+        let gas_used = self.gas_report.used_internally;
+        let duration = self.resource_report.duration;
+        self.stats.record(gas_used, duration, failed);
+    }
+
+    pub(crate) fn take_stats(&mut self) -> ContractStats {
+        std::mem::take(&mut self.stats)
+    }
+
+    // the writes of the last call were dropped
+    pub(crate) fn discard_storage_usage(&mut self) {
+        self.storage_usage = StorageUsageReport::default();
+    }
+
+    /// In audit mode, every gas charge of the following calls is passed to the trace sink
+    /// as TraceEvent::GasCharge, so two nodes' traces can be compared with diff_gas_traces
+    pub fn set_gas_audit(&mut self, enabled: bool) {
+        set_gas_audit::<S>(self.wasmer_instance.context(), enabled);
+    }
+
+    /// Registers a sink that receives trace events of all following calls, replacing
+    /// any previously registered one
+    pub fn set_trace_sink(&mut self, sink: Box<dyn TraceSink>) {
+        set_trace_sink::<S>(self.wasmer_instance.context(), Some(sink));
+    }
+
+    /// Removes the registered trace sink and returns it
+    pub fn take_trace_sink(&mut self) -> Option<Box<dyn TraceSink>> {
+        take_trace_sink::<S>(self.wasmer_instance.context())
+    }
+
+    /// Registers a sink for the debug_print messages of all following calls, replacing
+    /// any previously registered one
+    pub fn set_log_sink(&mut self, sink: Box<dyn LogSink>) {
+        set_log_sink::<S>(self.wasmer_instance.context(), Some(sink));
+    }
+
+    /// Removes the registered log sink and returns it
+    pub fn take_log_sink(&mut self) -> Option<Box<dyn LogSink>> {
+        take_log_sink::<S>(self.wasmer_instance.context())
+    }
+
+    pub(crate) fn record_trace(&self, event: TraceEvent) {
+        record_trace::<S>(self.wasmer_instance.context(), event);
+    }
+
+    // while an overlay is set, contract writes are collected there instead of storage
+    pub(crate) fn set_overlay(&mut self, overlay: Option<Overlay>) {
+        set_overlay::<S>(self.wasmer_instance.context(), overlay);
+    }
+
+    pub(crate) fn take_overlay(&mut self) -> Option<Overlay> {
+        take_overlay::<S>(self.wasmer_instance.context())
+    }
+
+    /// Sets the functions contracts can call through host_call, replacing any set before
+    pub fn set_host_functions(&mut self, functions: HostFunctions) {
+        set_host_functions::<S>(self.wasmer_instance.context(), Some(functions));
+    }
+
+    /// Registers a journal that protects the storage writes of all following calls against
+    /// crashes, replacing any previously registered one. Run recover_journal on its path
+    /// before registering it.
+    pub fn set_journal(&mut self, journal: Journal) {
+        set_journal::<S>(self.wasmer_instance.context(), Some(journal));
+    }
+
+    /// Removes the registered journal and returns it
+    pub fn take_journal(&mut self) -> Option<Journal> {
+        take_journal::<S>(self.wasmer_instance.context())
+    }
+
+    // applies the writes of a successful call to storage, journaling them if enabled
+    pub(crate) fn apply_writes(&mut self, writes: &BTreeMap<Vec<u8>, Vec<u8>>) -> Result<()> {
+        if writes.is_empty() {
+            return Ok(());
+        }
+        let journal = take_journal::<S>(self.wasmer_instance.context());
+        let res = self.write_journaled(journal.as_ref(), writes);
+        set_journal::<S>(self.wasmer_instance.context(), journal);
+        res
+    }
+
+    fn write_journaled(
+        &self,
+        journal: Option<&Journal>,
+        writes: &BTreeMap<Vec<u8>, Vec<u8>>,
+    ) -> Result<()> {
+        if let Some(journal) = journal {
+            journal.begin(writes)?;
+        }
+        self.with_storage(|store| {
+            for (key, value) in writes.iter() {
+                store.set(key, value);
+            }
+        });
+        if let Some(journal) = journal {
+            journal.finish()?;
+        }
+        Ok(())
+    }
+
     pub fn with_storage<F: FnMut(&mut S)>(&self, func: F) {
         with_storage_from_context(self.wasmer_instance.context(), func)
     }
 
+    // replaces the storage used by the following calls and returns the previous one
+    pub(crate) fn swap_storage(&mut self, storage: Option<S>) -> Option<S> {
+        let ctx = self.wasmer_instance.context();
+        let previous = take_storage(ctx);
+        leave_storage(ctx, storage);
+        previous
+    }
+
     pub fn memory(&self, ptr: u32) -> Vec<u8> {
         read_region(self.wasmer_instance.context(), ptr)
     }
@@ -121,6 +608,7 @@ where
         let alloc: Func<u32, u32> = self.func("allocate")?;
         let ptr = alloc.call(data.len() as u32).context(RuntimeErr {})?;
         write_region(self.wasmer_instance.context(), ptr, data)?;
+        self.allocations.insert(ptr, data.len());
         Ok(ptr)
     }
 
@@ -130,9 +618,54 @@ where
     pub fn deallocate(&mut self, ptr: u32) -> Result<()> {
         let dealloc: Func<u32, ()> = self.func("deallocate")?;
         dealloc.call(ptr).context(RuntimeErr {})?;
+        self.allocations.remove(&ptr);
         Ok(())
     }
 
+    // hand_over marks a buffer we allocated as owned by the contract, which frees it
+    // when consuming an argument. We must never deallocate it again afterwards.
+    pub(crate) fn hand_over(&mut self, ptr: u32) {
+        self.allocations.remove(&ptr);
+    }
+
+    /// Frees all buffers we allocated in this instance that are still outstanding.
+    /// This must be done before an instance is reused, otherwise the memory leaks
+    /// inside the long-lived wasm instance.
+    pub fn reclaim_allocations(&mut self) -> Result<ReclaimedBuffers> {
+        let mut reclaimed = ReclaimedBuffers::default();
+        let outstanding: Vec<(u32, usize)> = self.allocations.drain().collect();
+        for (ptr, len) in outstanding {
+            self.deallocate(ptr)?;
+            reclaimed.count += 1;
+            reclaimed.bytes += len;
+        }
+        Ok(reclaimed)
+    }
+
+    /// Checks without calling an entry point that the instance can serve calls: makes a
+    /// round trip through the contract's allocator and looks for the storage.
+    /// Cheap enough to back a readiness probe. Does not change contract state.
+    pub fn health_check(&mut self) -> HealthReport {
+        let outstanding_allocations = self.allocations.len();
+        let probe = b"health check";
+        let memory_ok = match self.allocate(probe) {
+            Ok(ptr) => {
+                let read_back = self.memory(ptr);
+                self.deallocate(ptr).is_ok() && read_back == probe
+            }
+            Err(_) => false,
+        };
+        let storage = take_storage::<S>(self.wasmer_instance.context());
+        let storage_attached = storage.is_some();
+        leave_storage(self.wasmer_instance.context(), storage);
+        HealthReport {
+            memory_ok,
+            storage_attached,
+            outstanding_allocations,
+            gas_left: self.get_gas(),
+        }
+    }
+
     pub fn func<Args, Rets>(&self, name: &str) -> Result<Func<Args, Rets, Wasm>>
     where
         Args: WasmTypeList,
@@ -144,9 +677,13 @@ where
 
 #[cfg(test)]
 mod test {
+    use super::{Func, HealthReport, Instance, ReclaimedBuffers};
     use crate::calls::{call_handle, call_init, call_query};
+    use crate::context::MAX_DEBUG_MESSAGES;
+    use crate::errors::Error;
     use crate::testing::{mock_instance, mock_instance_with_gas_limit};
-    use cosmwasm::mock::mock_env;
+    use cosmwasm::mock::{mock_env, MockApi, MockStorage};
+    use cosmwasm::traits::Extern;
     use cosmwasm::types::coin;
 
     static CONTRACT_0_7: &[u8] = include_bytes!("../testdata/contract_0.7.wasm");
@@ -167,6 +704,164 @@ mod test {
         assert_eq!(orig_gas, 123321);
     }
 
+    #[test]
+    fn reclaim_allocations_frees_outstanding_buffers() {
+        let mut instance = mock_instance(&CONTRACT_0_7);
+        let kept = instance.allocate(b"outstanding").unwrap();
+        let freed = instance.allocate(b"freed").unwrap();
+        instance.deallocate(freed).unwrap();
+        assert_ne!(kept, freed);
+
+        let reclaimed = instance.reclaim_allocations().unwrap();
+        assert_eq!(
+            reclaimed,
+            ReclaimedBuffers {
+                count: 1,
+                bytes: 11
+            }
+        );
+
+        // nothing left on a second run
+        let reclaimed = instance.reclaim_allocations().unwrap();
+        assert_eq!(reclaimed, ReclaimedBuffers::default());
+    }
+
+    #[test]
+    fn health_check_reports_state() {
+        let mut instance = mock_instance(&CONTRACT_0_7);
+        instance.allocate(b"outstanding").unwrap();
+        let report = instance.health_check();
+        assert_eq!(
+            report,
+            HealthReport {
+                memory_ok: true,
+                storage_attached: true,
+                outstanding_allocations: 1,
+                gas_left: instance.get_gas(),
+            }
+        );
+        assert!(report.is_healthy());
+        // the probe buffer was freed again
+        assert_eq!(instance.reclaim_allocations().unwrap().count, 1);
+    }
+
+    #[test]
+    fn ed25519_verify_import_works() {
+        use crate::testing::with_allocator;
+        use cosmwasm::mock::dependencies;
+        use cosmwasm::traits::Api;
+        use cosmwasm::types::{CanonicalAddr, HumanAddr};
+        use wabt::wat2wasm;
+
+        // a wrapper around the import
+        static WAT: &'static str = r#"
+            (import "env" "ed25519_verify" (func $ed25519_verify (param i32 i32 i32) (result i32)))
+            (memory (export "memory") 1)
+            (func (export "verify") (param i32 i32 i32) (result i32)
+              local.get 0
+              local.get 1
+              local.get 2
+              call $ed25519_verify)
+        "#;
+
+        let wasm = wat2wasm(with_allocator(WAT)).unwrap();
+        let mut instance = Instance::from_code(&wasm, dependencies(20), 1_000_000).unwrap();
+        let public_key =
+            hex::decode("8a88e3dd7409f195fd52db2d3cba5d72ca6709bf1d94121bf3748801b40f6f5c")
+                .unwrap();
+        let signature = hex::decode("1b11b54888ee61743d072f36ce1ffdcde233b39ace8754db41cd299af9d8a63f5ccda750aa6316da57e8f2617811a21e612a6deb6d4408894a89f1a4ec3fc004").unwrap();
+
+        fn verify<A: Api + 'static>(
+            instance: &mut Instance<MockStorage, A>,
+            message: &[u8],
+            signature: &[u8],
+            public_key: &[u8],
+        ) -> i32 {
+            let message = instance.allocate(message).unwrap();
+            let signature = instance.allocate(signature).unwrap();
+            let public_key = instance.allocate(public_key).unwrap();
+            let func: Func<(u32, u32, u32), i32> = instance.func("verify").unwrap();
+            func.call(message, signature, public_key).unwrap()
+        }
+        let mut check = |message: &[u8], signature: &[u8]| {
+            verify(&mut instance, message, signature, &public_key)
+        };
+        assert_eq!(check(b"hello world", &signature), 0);
+        assert_eq!(check(b"goodbye world", &signature), 1);
+        assert_eq!(check(b"hello world", &signature[1..]), -1);
+
+        // hosts that only implement the address conversions
+        #[derive(Copy, Clone)]
+        struct AddressApi(MockApi);
+        impl Api for AddressApi {
+            fn canonical_address(
+                &self,
+                human: &HumanAddr,
+            ) -> cosmwasm::errors::Result<CanonicalAddr> {
+                self.0.canonical_address(human)
+            }
+            fn human_address(
+                &self,
+                canonical: &CanonicalAddr,
+            ) -> cosmwasm::errors::Result<HumanAddr> {
+                self.0.human_address(canonical)
+            }
+        }
+        let deps = Extern {
+            storage: MockStorage::new(),
+            api: AddressApi(MockApi::new(20)),
+        };
+        let mut instance = Instance::from_code(&wasm, deps, 1_000_000).unwrap();
+        assert_eq!(
+            verify(&mut instance, b"hello world", &signature, &public_key),
+            -2
+        );
+    }
+
+    #[test]
+    fn debug_print_reaches_log_sink() {
+        use crate::trace::LogRecorder;
+        use cosmwasm::mock::dependencies;
+        use wabt::wat2wasm;
+
+        // a region at 8 pointing to "hello world" at 64
+        static WAT: &'static str = r#"
+            (module
+              (import "env" "debug_print" (func $debug_print (param i32)))
+              (memory (export "memory") 1)
+              (data (i32.const 8) "\40\00\00\00\0b\00\00\00")
+              (data (i32.const 64) "hello world")
+              (func (export "print")
+                i32.const 8
+                call $debug_print))
+        "#;
+
+        let wasm = wat2wasm(WAT).unwrap();
+        let mut instance = Instance::from_code(&wasm, dependencies(20), 1_000_000).unwrap();
+        let print = |instance: &mut Instance<_, _>| {
+            let func: Func<(), ()> = instance.func("print").unwrap();
+            func.call().unwrap();
+        };
+
+        // without a sink the message is dropped
+        print(&mut instance);
+
+        let recorder = LogRecorder::new();
+        instance.set_log_sink(Box::new(recorder.clone()));
+        print(&mut instance);
+        print(&mut instance);
+        assert_eq!(recorder.messages(), vec!["hello world", "hello world"]);
+
+        // the limit holds until the next call starts
+        for _ in 0..MAX_DEBUG_MESSAGES {
+            print(&mut instance);
+        }
+        assert_eq!(recorder.messages().len() as u32, MAX_DEBUG_MESSAGES);
+        instance.start_gas_report();
+        print(&mut instance);
+        assert_eq!(recorder.messages().len() as u32, MAX_DEBUG_MESSAGES + 1);
+    }
+
     #[test]
     #[should_panic]
     fn with_context_safe_for_panic() {
@@ -175,6 +870,32 @@ mod test {
         instance.with_storage(|_store| assert_eq!(1, 2));
     }
 
+    #[test]
+    fn storage_panic_keeps_storage() {
+        let deps = Extern {
+            storage: MockStorage::new().panic_on_read(1),
+            api: MockApi::new(20),
+        };
+        let mut instance = Instance::from_code(&CONTRACT_0_7, deps, 500_000).unwrap();
+
+        // init only writes
+        let env = mock_env(&instance.api, "creator", &coin("1000", "earth"), &[]);
+        let msg = r#"{"verifier": "verifies", "beneficiary": "benefits"}"#.as_bytes();
+        call_init(&mut instance, &env, msg).unwrap().unwrap();
+        match call_query(&mut instance, br#"{"verifier":{}}"#) {
+            Err(Error::RuntimeErr { source, .. }) => {
+                assert!(source.to_string().contains("injected failure"))
+            }
+            Err(e) => panic!("Unexpected error {:?}", e),
+            Ok(_) => panic!("Storage panic was not reported"),
+        }
+
+        // the storage survived the panic
+        let mut has_storage = false;
+        instance.with_storage(|_store| has_storage = true);
+        assert!(has_storage);
+    }
+
     #[test]
     #[cfg(feature = "default-singlepass")]
     fn contract_deducts_gas_init() {
@@ -217,6 +938,37 @@ mod test {
         assert_eq!(handle_used, 91_487);
     }
 
+    #[test]
+    #[cfg(feature = "default-singlepass")]
+    fn contract_deducts_host_gas_with_config() {
+        let mut instance = mock_instance(&CONTRACT_0_7);
+        instance.set_gas_version(crate::gas::GasVersion::V1);
+        let orig_gas = instance.get_gas();
+
+        // the wasm gas of contract_deducts_gas_init, plus looking up and writing
+        // the config and canonicalizing two addresses
+        let env = mock_env(&instance.api, "creator", &coin("1000", "earth"), &[]);
+        let msg = r#"{"verifier": "verifies", "beneficiary": "benefits"}"#.as_bytes();
+        call_init(&mut instance, &env, msg).unwrap();
+        assert_eq!(orig_gas - instance.get_gas(), 59_781);
+
+        // plus reading the config and humanizing two addresses
+        let gas_before_handle = instance.get_gas();
+        let env = mock_env(
+            &instance.api,
+            "verifies",
+            &coin("15", "earth"),
+            &coin("1015", "earth"),
+        );
+        call_handle(&mut instance, &env, br#"{"release":{}}"#).unwrap();
+        assert_eq!(gas_before_handle - instance.get_gas(), 93_089);
+
+        // plus reading the config and humanizing the verifier
+        let gas_before_query = instance.get_gas();
+        call_query(&mut instance, br#"{"verifier":{}}"#).unwrap();
+        assert_eq!(gas_before_query - instance.get_gas(), 46_423);
+    }
+
     #[test]
     #[cfg(feature = "default-singlepass")]
     fn contract_enforces_gas_limit() {
@@ -0,0 +1,176 @@
+//! Helpers for rewriting contract code before it is compiled, shared by the call depth limit
+//! and the coverage instrumentation.
+use parity_wasm::elements::{
+    deserialize_buffer, serialize, External, FunctionType, ImportCountType, ImportEntry,
+    ImportSection, IndexMap, Instruction, Internal, Module, Section, Type, ValueType,
+};
+
+use crate::errors::{Result, ValidationErr};
+
+pub(crate) fn deserialize_module(wasm_code: &[u8]) -> Result<Module> {
+    match deserialize_buffer::<Module>(wasm_code) {
+        // a name section that cannot be parsed stays a custom section
+        Ok(module) => Ok(module.parse_names().unwrap_or_else(|(_, module)| module)),
+        Err(err) => ValidationErr {
+            msg: format!("Wasm bytecode could not be deserialized: {}", err),
+        }
+        .fail(),
+    }
+}
+
+pub(crate) fn serialize_module(module: Module) -> Result<Vec<u8>> {
+    match serialize(module) {
+        Ok(code) => Ok(code),
+        Err(err) => ValidationErr {
+            msg: format!("Instrumented code could not be serialized: {}", err),
+        }
+        .fail(),
+    }
+}
+
+/// Imports env.<field> with the given params and no result after the other imported
+/// functions and returns its index. All functions defined in the module move one index up.
+pub(crate) fn add_function_import(
+    module: &mut Module,
+    field: &str,
+    params: Vec<ValueType>,
+) -> Result<u32> {
+    let index = module.import_count(ImportCountType::Function) as u32;
+    let types = match module.type_section_mut() {
+        Some(type_section) => type_section.types_mut(),
+        None => {
+            return ValidationErr {
+                msg: "Wasm code has no type section".to_string(),
+            }
+            .fail()
+        }
+    };
+    types.push(Type::Function(FunctionType::new(params, None)));
+    let type_index = types.len() as u32 - 1;
+
+    if module.import_section().is_none() {
+        module
+            .insert_section(Section::Import(ImportSection::with_entries(Vec::new())))
+            .expect("no import section yet");
+    }
+    let imports = module
+        .import_section_mut()
+        .expect("import section exists")
+        .entries_mut();
+    // function imports come first in the index space, so insert after the last one
+    let position = imports
+        .iter()
+        .enumerate()
+        .rev()
+        .find_map(|(i, entry)| match entry.external() {
+            External::Function(_) => Some(i + 1),
+            _ => None,
+        })
+        .unwrap_or(0);
+    imports.insert(
+        position,
+        ImportEntry::new(
+            "env".to_string(),
+            field.to_string(),
+            External::Function(type_index),
+        ),
+    );
+    shift_functions(module, index);
+    Ok(index)
+}
+
+// moves all functions from index on one index up, making room for an import
+fn shift_functions(module: &mut Module, from: u32) {
+    let shift = |index: &mut u32| {
+        if *index >= from {
+            *index += 1;
+        }
+    };
+    if let Some(code_section) = module.code_section_mut() {
+        for body in code_section.bodies_mut() {
+            for instruction in body.code_mut().elements_mut() {
+                if let Instruction::Call(index) = instruction {
+                    shift(index);
+                }
+            }
+        }
+    }
+    if let Some(export_section) = module.export_section_mut() {
+        for entry in export_section.entries_mut() {
+            if let Internal::Function(index) = entry.internal_mut() {
+                shift(index);
+            }
+        }
+    }
+    if let Some(elements_section) = module.elements_section_mut() {
+        for segment in elements_section.entries_mut() {
+            segment.members_mut().iter_mut().for_each(shift);
+        }
+    }
+    if let Some(mut start) = module.start_section() {
+        shift(&mut start);
+        module.set_start_section(start);
+    }
+    // names move with their functions. An unparsed name section cannot be shifted and
+    // would point to the wrong functions.
+    module.sections_mut().retain(|section| match section {
+        Section::Custom(custom) => custom.name() != "name",
+        _ => true,
+    });
+    if let Some(names) = module.names_section_mut() {
+        if let Some(functions) = names.functions_mut() {
+            shift_keys(functions.names_mut(), shift);
+        }
+        if let Some(locals) = names.locals_mut() {
+            shift_keys(locals.local_names_mut(), shift);
+        }
+    }
+}
+
+fn shift_keys<T>(map: &mut IndexMap<T>, shift: impl Fn(&mut u32)) {
+    *map = std::mem::replace(map, IndexMap::with_capacity(0))
+        .into_iter()
+        .map(|(mut index, value)| {
+            shift(&mut index);
+            (index, value)
+        })
+        .collect();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use wabt::Wat2Wasm;
+
+    #[test]
+    fn add_function_import_keeps_names() {
+        let wasm = Wat2Wasm::new()
+            .write_debug_names(true)
+            .convert(
+                r#"(module
+                  (import "env" "one" (func $one))
+                  (func $two call $one)
+                  (func $three (param $x i32) call $two))"#,
+            )
+            .unwrap();
+        let mut module = deserialize_module(wasm.as_ref()).unwrap();
+        assert_eq!(
+            add_function_import(&mut module, "added", Vec::new()).unwrap(),
+            1
+        );
+
+        let names = module.names_section().unwrap();
+        let functions = names.functions().unwrap().names();
+        assert_eq!(functions.get(0).unwrap(), "one");
+        assert_eq!(functions.get(1), None);
+        assert_eq!(functions.get(2).unwrap(), "two");
+        assert_eq!(functions.get(3).unwrap(), "three");
+        let locals = names.locals().unwrap().local_names();
+        assert_eq!(locals.get(3).unwrap().get(0).unwrap(), "x");
+
+        // still valid after serializing
+        let code = serialize_module(module).unwrap();
+        let module = deserialize_module(&code).unwrap();
+        assert!(module.names_section().is_some());
+    }
+}
@@ -0,0 +1,161 @@
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::fs::{remove_file, File};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+use snafu::ResultExt;
+
+use cosmwasm::traits::Storage;
+
+use crate::errors::{Error, IoErr};
+
+/// Journal records the writes of a call in a file before they are applied to storage and
+/// removes the file once they are. If the process dies in between, the file is still there
+/// and recover_journal applies the writes again at the next start.
+/// Register one with `Instance::set_journal`; every instance needs its own path.
+pub struct Journal {
+    path: PathBuf,
+}
+
+impl Journal {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Journal { path: path.into() }
+    }
+
+    /// Persists the writes, must be called before the first of them is applied
+    pub(crate) fn begin(&self, writes: &BTreeMap<Vec<u8>, Vec<u8>>) -> Result<(), Error> {
+        let mut file = File::create(&self.path).context(IoErr {})?;
+        file.write_all(&encode(writes)).context(IoErr {})?;
+        file.sync_all().context(IoErr {})
+    }
+
+    /// Marks the writes as applied
+    pub(crate) fn finish(&self) -> Result<(), Error> {
+        remove_file(&self.path).context(IoErr {})
+    }
+}
+
+/// recover_journal completes the writes of a call that was interrupted while they were
+/// applied. Call it with the journal path and storage of an instance before using them.
+/// A journal that was not completely written is dropped, as none of its writes were applied.
+/// Returns the number of writes applied again.
+pub fn recover_journal<P: AsRef<Path>, S: Storage>(
+    path: P,
+    storage: &mut S,
+) -> Result<usize, Error> {
+    let path = path.as_ref();
+    let data = match std::fs::read(path) {
+        Ok(data) => data,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(0),
+        Err(err) => return Err(err).context(IoErr {}),
+    };
+    let recovered = match decode(&data) {
+        Some(writes) => {
+            for (key, value) in writes.iter() {
+                storage.set(key, value);
+            }
+            writes.len()
+        }
+        None => 0,
+    };
+    remove_file(path).context(IoErr {})?;
+    Ok(recovered)
+}
+
+// every write as length prefixed key and value, followed by the sha256 of all of them
+fn encode(writes: &BTreeMap<Vec<u8>, Vec<u8>>) -> Vec<u8> {
+    let mut data = Vec::new();
+    for (key, value) in writes.iter() {
+        data.extend_from_slice(&(key.len() as u32).to_be_bytes());
+        data.extend_from_slice(key);
+        data.extend_from_slice(&(value.len() as u32).to_be_bytes());
+        data.extend_from_slice(value);
+    }
+    let hash = Sha256::digest(&data);
+    data.extend_from_slice(&hash);
+    data
+}
+
+// None if data is truncated or otherwise not written by encode
+fn decode(data: &[u8]) -> Option<Vec<(Vec<u8>, Vec<u8>)>> {
+    if data.len() < 32 {
+        return None;
+    }
+    let (mut entries, hash) = data.split_at(data.len() - 32);
+    if Sha256::digest(entries).as_slice() != hash {
+        return None;
+    }
+    let mut writes = Vec::new();
+    while !entries.is_empty() {
+        let (key, rest) = take_section(entries)?;
+        let (value, rest) = take_section(rest)?;
+        writes.push((key.to_vec(), value.to_vec()));
+        entries = rest;
+    }
+    Some(writes)
+}
+
+fn take_section(data: &[u8]) -> Option<(&[u8], &[u8])> {
+    if data.len() < 4 {
+        return None;
+    }
+    let len = u32::from_be_bytes(data[..4].try_into().ok()?) as usize;
+    let rest = &data[4..];
+    if rest.len() < len {
+        return None;
+    }
+    Some(rest.split_at(len))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cosmwasm::mock::MockStorage;
+    use cosmwasm::traits::ReadonlyStorage;
+    use tempfile::TempDir;
+
+    fn writes() -> BTreeMap<Vec<u8>, Vec<u8>> {
+        let mut writes = BTreeMap::new();
+        writes.insert(b"foo".to_vec(), b"bar".to_vec());
+        writes.insert(b"empty".to_vec(), vec![]);
+        writes
+    }
+
+    #[test]
+    fn recover_applies_unfinished_journal() {
+        let tmp_dir = TempDir::new().unwrap();
+        let path = tmp_dir.path().join("journal");
+        let journal = Journal::new(&path);
+        let mut storage = MockStorage::new();
+
+        // nothing to do without a journal
+        assert_eq!(recover_journal(&path, &mut storage).unwrap(), 0);
+
+        journal.begin(&writes()).unwrap();
+        assert_eq!(recover_journal(&path, &mut storage).unwrap(), 2);
+        assert_eq!(storage.get(b"foo"), Some(b"bar".to_vec()));
+        assert_eq!(storage.get(b"empty"), Some(vec![]));
+        assert!(!path.exists());
+
+        // finished journals are gone
+        journal.begin(&writes()).unwrap();
+        journal.finish().unwrap();
+        assert_eq!(recover_journal(&path, &mut storage).unwrap(), 0);
+    }
+
+    #[test]
+    fn recover_drops_incomplete_journal() {
+        let tmp_dir = TempDir::new().unwrap();
+        let path = tmp_dir.path().join("journal");
+        let mut data = encode(&writes());
+        data.truncate(data.len() - 1);
+        std::fs::write(&path, &data).unwrap();
+
+        let mut storage = MockStorage::new();
+        assert_eq!(recover_journal(&path, &mut storage).unwrap(), 0);
+        assert_eq!(storage.get(b"foo"), None);
+        assert!(!path.exists());
+    }
+}
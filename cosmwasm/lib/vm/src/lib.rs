@@ -1,19 +1,67 @@
 mod backends;
+#[cfg(feature = "bench")]
+pub mod bench;
 mod cache;
+#[cfg(feature = "call-depth")]
+mod call_depth;
 mod calls;
 mod compatability;
+mod config;
 mod context;
+#[cfg(feature = "coverage")]
+pub mod coverage;
 pub mod errors;
+#[cfg(feature = "fuzzing")]
+pub mod fuzzing;
+mod gas;
+mod host_functions;
 mod instance;
+#[cfg(any(feature = "call-depth", feature = "coverage"))]
+mod instrumentation;
+mod journal;
+mod limits;
 mod memory;
+mod messages;
 mod middleware;
 mod modules;
+mod policy;
+mod prepare;
+mod query_cache;
+mod query_quota;
+mod random;
+pub mod requests;
+mod resources;
+mod stats;
 pub mod testing;
+pub mod timeline;
+pub mod trace;
 mod wasm_store;
 
-pub use crate::cache::CosmCache;
+pub use crate::cache::{CacheObserver, CacheOptions, CosmCache, LoadSource};
 pub use crate::calls::{
-    call_handle, call_handle_raw, call_init, call_init_raw, call_query, call_query_raw,
+    call_handle, call_handle_batch, call_handle_raw, call_handle_readonly, call_handle_simulate,
+    call_handle_with_hints, call_init, call_init_raw, call_query, call_query_at_height,
+    call_query_chunked, call_query_raw, call_query_with_gas_limit, call_sudo, call_sudo_raw,
+    commit, prepare_execute,
 };
-pub use crate::instance::Instance;
+pub use crate::compatability::{
+    analyze_code, check_code_limits, describe_code, vm_info, CodeAnalysis, ContractDescription,
+    FloatPolicy, VmInfo, SCHEMA_SECTION,
+};
+pub use crate::config::{VmConfig, ENV_PREFIX};
+pub use crate::gas::{
+    BlockGasTracker, BlockGasUsage, GasConfig, GasReport, GasSchedule, GasVersion,
+};
+pub use crate::host_functions::{HostFunction, HostFunctions};
+pub use crate::instance::{HealthReport, Instance, ReclaimedBuffers};
+pub use crate::journal::{recover_journal, Journal};
+pub use crate::limits::{Limits, LimitsVersion};
+pub use crate::messages::MessageCheck;
 pub use crate::modules::FileSystemCache;
+pub use crate::policy::{BlockList, ExecutionPolicy};
+pub use crate::prepare::{AccessSet, PreparedExecution, Simulation};
+pub use crate::query_cache::{QueryCache, QueryKey};
+pub use crate::query_quota::QueryQuota;
+pub use crate::resources::{ResourceReport, StorageUsageReport};
+pub use crate::stats::ContractStats;
+pub use crate::wasm_store::{FileSystemStore, MemoryStore, WasmStore};
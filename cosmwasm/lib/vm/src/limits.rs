@@ -0,0 +1,166 @@
+use cosmwasm::types::LogAttribute;
+
+use crate::errors::{Result, ResultTooLargeErr};
+
+/// Limits on the size of data a contract hands to the host and on how often it calls it.
+/// Exceeding one aborts the call with ValueTooLargeErr before the data is copied out
+/// of wasm memory, so a contract cannot make the host allocate arbitrary amounts.
+///
+/// The default sets no limits, so contracts that worked before keep working. A chain turns
+/// them on with a LimitsVersion.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Limits {
+    /// storage keys passed to read_db and write_db
+    pub max_key_length: usize,
+    /// storage values passed to write_db
+    pub max_value_length: usize,
+    /// results returned by init, handle and query
+    pub max_result_length: usize,
+    /// number of log attributes in the result of init and handle
+    pub max_log_attributes: usize,
+    pub max_log_key_length: usize,
+    pub max_log_value_length: usize,
+    /// number of import calls in one init, handle or query. Gas prices most imports far
+    /// below the host work of a call, so this bounds what cheap calls in a loop can cost.
+    /// Exceeding it aborts the call with ImportLimitExceededErr.
+    pub max_import_calls: u32,
+    /// depth of nested calls of contract functions. Exceeding it aborts the call with
+    /// CallDepthExceededErr before the native stack can overflow. Like gas, this decides
+    /// whether a call succeeds and must be the same on all nodes.
+    /// Only enforced when built with the call-depth feature.
+    pub max_call_depth: u32,
+    /// names of the host functions passed to host_call
+    pub max_host_call_name: usize,
+    /// payloads passed to host_call
+    pub max_host_call_payload: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_key_length: usize::MAX,
+            max_value_length: usize::MAX,
+            max_result_length: usize::MAX,
+            max_log_attributes: usize::MAX,
+            max_log_key_length: usize::MAX,
+            max_log_value_length: usize::MAX,
+            max_import_calls: u32::MAX,
+            max_call_depth: u32::MAX,
+            max_host_call_name: usize::MAX,
+            max_host_call_payload: usize::MAX,
+        }
+    }
+}
+
+/// LimitsVersion names a fixed set of limits, like GasVersion does for costs. A chain that
+/// changes limits adds a new version and never edits an existing one, so old blocks replay
+/// with the limits they were executed with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LimitsVersion {
+    V1,
+}
+
+impl LimitsVersion {
+    pub fn limits(self) -> Limits {
+        match self {
+            LimitsVersion::V1 => Limits {
+                max_key_length: 64 * 1024,
+                max_value_length: 128 * 1024,
+                max_result_length: 1024 * 1024,
+                max_log_attributes: 64,
+                max_log_key_length: 128,
+                max_log_value_length: 4 * 1024,
+                max_import_calls: 100_000,
+                max_call_depth: 1024,
+                max_host_call_name: 256,
+                max_host_call_payload: 128 * 1024,
+            },
+        }
+    }
+}
+
+impl Limits {
+    /// Fails with ResultTooLargeErr naming the first limit the log exceeds.
+    /// The log is only checked once parsed, a call stays within max_result_length in any case.
+    pub(crate) fn check_log(&self, log: &[LogAttribute]) -> Result<()> {
+        if log.len() > self.max_log_attributes {
+            return ResultTooLargeErr {
+                limit: "max_log_attributes",
+                size: log.len(),
+                max: self.max_log_attributes,
+            }
+            .fail();
+        }
+        for attr in log {
+            if attr.key.len() > self.max_log_key_length {
+                return ResultTooLargeErr {
+                    limit: "max_log_key_length",
+                    size: attr.key.len(),
+                    max: self.max_log_key_length,
+                }
+                .fail();
+            }
+            if attr.value.len() > self.max_log_value_length {
+                return ResultTooLargeErr {
+                    limit: "max_log_value_length",
+                    size: attr.value.len(),
+                    max: self.max_log_value_length,
+                }
+                .fail();
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::errors::Error;
+
+    fn attr(key: &str, value: &str) -> LogAttribute {
+        LogAttribute {
+            key: key.to_string(),
+            value: value.to_string(),
+        }
+    }
+
+    #[test]
+    fn default_sets_no_limits() {
+        let log: Vec<_> = (0..1000).map(|_| attr(&"k".repeat(200), "")).collect();
+        Limits::default().check_log(&log).unwrap();
+        assert!(LimitsVersion::V1.limits().check_log(&log).is_err());
+    }
+
+    #[test]
+    fn check_log_names_exceeded_limit() {
+        let limits = Limits {
+            max_log_attributes: 2,
+            max_log_key_length: 3,
+            max_log_value_length: 5,
+            ..Limits::default()
+        };
+        limits
+            .check_log(&[attr("foo", "12345"), attr("bar", "")])
+            .unwrap();
+
+        let cases = vec![
+            (
+                vec![attr("a", ""), attr("b", ""), attr("c", "")],
+                "max_log_attributes",
+                3,
+            ),
+            (vec![attr("food", "")], "max_log_key_length", 4),
+            (vec![attr("foo", "123456")], "max_log_value_length", 6),
+        ];
+        for (log, expected, expected_size) in cases {
+            match limits.check_log(&log) {
+                Err(Error::ResultTooLargeErr { limit, size, .. }) => {
+                    assert_eq!(limit, expected);
+                    assert_eq!(size, expected_size);
+                }
+                res => panic!("Unexpected result {:?}", res),
+            }
+        }
+    }
+}
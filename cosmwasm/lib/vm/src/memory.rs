@@ -1,4 +1,4 @@
-use crate::errors::{Error, RegionTooSmallErr};
+use crate::errors::{Error, RegionTooSmallErr, ValueTooLargeErr};
 use wasmer_runtime_core::{
     memory::ptr::{Array, WasmPtr},
     types::ValueType,
@@ -46,6 +46,59 @@ pub fn read_region(ctx: &Ctx, ptr: u32) -> Vec<u8> {
     }
 }
 
+/// Like read_region, but fails with ValueTooLargeErr without copying if the region
+/// is longer than limit. kind names the data in the error.
+pub fn read_region_limited(
+    ctx: &Ctx,
+    ptr: u32,
+    limit: usize,
+    kind: &'static str,
+) -> Result<Vec<u8>, Error> {
+    let size = region_len(ctx, ptr);
+    if size > limit {
+        return ValueTooLargeErr { kind, size, limit }.fail();
+    }
+    Ok(read_region(ctx, ptr))
+}
+
+/// Passes the data of the region at ptr to func in chunks of at most chunk_size bytes,
+/// so it is never copied out of wasm memory at once. Stops at the first error of func.
+/// Returns the length of the data.
+pub fn read_region_chunks<F: FnMut(&[u8]) -> Result<(), Error>>(
+    ctx: &Ctx,
+    ptr: u32,
+    chunk_size: usize,
+    mut func: F,
+) -> Result<usize, Error> {
+    let region = to_region(ctx, ptr);
+    let memory = ctx.memory(0);
+    let len = region.len as usize;
+    let chunk_size = chunk_size.max(1);
+
+    match WasmPtr::<u8, Array>::new(region.offset).deref(memory, 0, region.len) {
+        Some(cells) => {
+            let mut chunk = Vec::with_capacity(chunk_size.min(len));
+            for start in (0..len).step_by(chunk_size) {
+                let end = (start + chunk_size).min(len);
+                chunk.clear();
+                chunk.extend(cells[start..end].iter().map(|cell| cell.get()));
+                func(&chunk)?;
+            }
+            Ok(len)
+        }
+        None => panic!(
+            "Error dereferencing region {:?} in wasm memory of size {}. This typically happens when the given pointer does not point to a Region struct.",
+            region,
+            memory.size().bytes().0
+        ),
+    }
+}
+
+/// Returns the length of the memory region the Region struct at ptr points to
+pub fn region_len(ctx: &Ctx, ptr: u32) -> usize {
+    to_region(ctx, ptr).len as usize
+}
+
 /// A prepared and sufficiently large memory Region is expected at ptr that points to pre-allocated memory.
 ///
 /// Returns number of bytes written on success.
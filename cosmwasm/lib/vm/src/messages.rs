@@ -0,0 +1,185 @@
+use cosmwasm::traits::Api;
+use cosmwasm::types::{Coin, CosmosMsg, HumanAddr};
+
+use crate::errors::{InvalidMessageErr, Result};
+
+/// MessageCheck makes init and handle fail with InvalidMessageErr when they return a message
+/// the chain could not dispatch, so its writes are dropped. Instances only check messages
+/// once it is set with Instance::set_message_check, otherwise they are left to the chain.
+pub struct MessageCheck {
+    valid_denom: Box<dyn Fn(&str) -> bool>,
+}
+
+impl MessageCheck {
+    /// valid_denom is the rule of the chain for coin denoms. Like gas, it decides whether
+    /// a call succeeds and must be the same on all nodes.
+    pub fn new(valid_denom: Box<dyn Fn(&str) -> bool>) -> Self {
+        MessageCheck { valid_denom }
+    }
+
+    /// Fails with InvalidMessageErr for the first message the chain could not dispatch:
+    /// a coin with a denom the rule rejects or an amount that is not a positive integer,
+    /// or an address that does not convert to a canonical address and back unchanged.
+    pub(crate) fn check<A: Api>(&self, api: &A, messages: &[CosmosMsg]) -> Result<()> {
+        for (index, msg) in messages.iter().enumerate() {
+            self.check_message(api, msg)
+                .or_else(|msg| InvalidMessageErr { index, msg }.fail())?;
+        }
+        Ok(())
+    }
+
+    fn check_message<A: Api>(&self, api: &A, msg: &CosmosMsg) -> Result<(), String> {
+        match msg {
+            CosmosMsg::Send {
+                from_address,
+                to_address,
+                amount,
+            } => {
+                check_address(api, from_address)?;
+                check_address(api, to_address)?;
+                self.check_coins(amount)
+            }
+            CosmosMsg::Contract {
+                contract_addr,
+                send,
+                ..
+            } => {
+                check_address(api, contract_addr)?;
+                self.check_coins(send.as_ref().map_or(&[], Vec::as_slice))
+            }
+            // passed through from the user, the chain checks it when decoding
+            CosmosMsg::Opaque { .. } => Ok(()),
+        }
+    }
+
+    fn check_coins(&self, coins: &[Coin]) -> Result<(), String> {
+        for coin in coins {
+            if !(self.valid_denom)(&coin.denom) {
+                return Err(format!("invalid denom {:?}", coin.denom));
+            }
+            if !is_positive_amount(&coin.amount) {
+                return Err(format!(
+                    "invalid amount {:?} of {}",
+                    coin.amount, coin.denom
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn check_address<A: Api>(api: &A, human: &HumanAddr) -> Result<(), String> {
+    let canonical = api
+        .canonical_address(human)
+        .map_err(|_| format!("invalid address {:?}", human.as_str()))?;
+    match api.human_address(&canonical) {
+        Ok(ref back) if back == human => Ok(()),
+        _ => Err(format!("address {:?} is not canonical", human.as_str())),
+    }
+}
+
+fn is_positive_amount(amount: &str) -> bool {
+    !amount.is_empty()
+        && amount.bytes().all(|b| b.is_ascii_digit())
+        && amount.bytes().any(|b| b != b'0')
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::errors::Error;
+    use cosmwasm::encoding::Binary;
+    use cosmwasm::mock::MockApi;
+    use cosmwasm::types::coin;
+
+    // the format the sdk accepts for denoms: [a-z][a-z0-9]{2,15}
+    fn sdk_denoms() -> MessageCheck {
+        MessageCheck::new(Box::new(|denom: &str| {
+            let bytes = denom.as_bytes();
+            bytes.len() >= 3
+                && bytes.len() <= 16
+                && bytes[0].is_ascii_lowercase()
+                && bytes[1..]
+                    .iter()
+                    .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit())
+        }))
+    }
+
+    fn send(to: &str, amount: Vec<Coin>) -> CosmosMsg {
+        CosmosMsg::Send {
+            from_address: HumanAddr("cosmos2contract".to_string()),
+            to_address: HumanAddr(to.to_string()),
+            amount,
+        }
+    }
+
+    #[test]
+    fn check_messages_accepts_well_formed_messages() {
+        let api = MockApi::new(20);
+        let check = sdk_denoms();
+        let messages = vec![
+            send("benefits", coin("1000", "earth")),
+            send("benefits", vec![]),
+            CosmosMsg::Contract {
+                contract_addr: HumanAddr("other".to_string()),
+                msg: Binary(b"{}".to_vec()),
+                send: Some(coin("7", "uscrt")),
+            },
+            CosmosMsg::Opaque {
+                data: Binary(b"anything".to_vec()),
+            },
+        ];
+        check.check(&api, &messages).unwrap();
+
+        // the denoms are up to the chain
+        let ibc = MessageCheck::new(Box::new(|denom: &str| denom.starts_with("ibc/")));
+        let messages = vec![send("benefits", coin("1", "ibc/27394FB092"))];
+        ibc.check(&api, &messages).unwrap();
+    }
+
+    #[test]
+    fn check_messages_names_first_invalid_message() {
+        let api = MockApi::new(20);
+        let check = sdk_denoms();
+        let cases = vec![
+            (coin("0", "earth"), r#"invalid amount "0" of earth"#),
+            (coin("-5", "earth"), r#"invalid amount "-5" of earth"#),
+            (coin("1.5", "earth"), r#"invalid amount "1.5" of earth"#),
+            (coin("5", "Earth"), r#"invalid denom "Earth""#),
+            (coin("5", "ab"), r#"invalid denom "ab""#),
+            (coin("5", "1earth"), r#"invalid denom "1earth""#),
+        ];
+        for (amount, expected) in cases {
+            let messages = vec![
+                send("benefits", coin("1", "earth")),
+                send("benefits", amount),
+            ];
+            match check.check(&api, &messages) {
+                Err(Error::InvalidMessageErr { index, msg, .. }) => {
+                    assert_eq!(index, 1);
+                    assert_eq!(msg, expected);
+                }
+                res => panic!("Unexpected result {:?}", res),
+            }
+        }
+
+        // too long for the mock's canonical length
+        let messages = vec![send("a_much_too_long_human_address", coin("1", "earth"))];
+        match check.check(&api, &messages) {
+            Err(Error::InvalidMessageErr { index, msg, .. }) => {
+                assert_eq!(index, 0);
+                assert_eq!(msg, r#"invalid address "a_much_too_long_human_address""#);
+            }
+            res => panic!("Unexpected result {:?}", res),
+        }
+
+        // the mock strips the zero byte when converting back
+        let messages = vec![send("bene\0fits", coin("1", "earth"))];
+        match check.check(&api, &messages) {
+            Err(Error::InvalidMessageErr { msg, .. }) => {
+                assert_eq!(msg, r#"address "bene\u{0}fits" is not canonical"#)
+            }
+            res => panic!("Unexpected result {:?}", res),
+        }
+    }
+}
@@ -122,6 +122,17 @@ impl FileSystemCache {
 
         Ok(())
     }
+
+    /// Removes the module stored for key with the current backend, if any
+    pub fn remove(&mut self, key: WasmHash) -> io::Result<()> {
+        let mut path = self.path.clone();
+        path.push(backend());
+        path.push(key.encode());
+        match std::fs::remove_file(path) {
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+            res => res,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -132,6 +143,8 @@ mod tests {
     use std::env;
 
     #[test]
+    // instrumented code needs the call_depth_exceeded import
+    #[cfg(not(feature = "call-depth"))]
     fn test_file_system_cache_run() {
         use wabt::wat2wasm;
         use wasmer_runtime_core::{imports, typed_func::Func};
@@ -0,0 +1,49 @@
+use std::collections::BTreeSet;
+
+use cosmwasm::types::CanonicalAddr;
+
+/// ExecutionPolicy is asked before every call whether the code and contract may run,
+/// eg. to enforce contracts frozen by governance. Checks happen before the contract is
+/// entered, so a refused call uses no gas.
+pub trait ExecutionPolicy {
+    /// id is the code id returned by CosmCache::save_wasm
+    fn allow_code(&self, _id: &[u8]) -> bool {
+        true
+    }
+
+    /// Only asked for init and handle, queries do not know the contract address
+    fn allow_contract(&self, _contract: &CanonicalAddr) -> bool {
+        true
+    }
+}
+
+/// BlockList refuses the listed code ids and contracts and allows everything else
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct BlockList {
+    pub codes: BTreeSet<Vec<u8>>,
+    pub contracts: BTreeSet<Vec<u8>>,
+}
+
+impl BlockList {
+    pub fn new() -> Self {
+        BlockList::default()
+    }
+
+    pub fn block_code(&mut self, id: &[u8]) {
+        self.codes.insert(id.to_vec());
+    }
+
+    pub fn block_contract(&mut self, contract: &CanonicalAddr) {
+        self.contracts.insert(contract.as_slice().to_vec());
+    }
+}
+
+impl ExecutionPolicy for BlockList {
+    fn allow_code(&self, id: &[u8]) -> bool {
+        !self.codes.contains(id)
+    }
+
+    fn allow_contract(&self, contract: &CanonicalAddr) -> bool {
+        !self.contracts.contains(contract.as_slice())
+    }
+}
@@ -0,0 +1,241 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use cosmwasm::traits::Storage;
+use cosmwasm::types::ContractResult;
+
+use crate::errors::{Result, StateChangedErr};
+use crate::gas::GasReport;
+use crate::trace::hash_key;
+
+/// Overlay collects the storage accesses of a prepared execution.
+/// Writes never reach the backing storage, reads see the pending writes.
+#[derive(Default)]
+pub(crate) struct Overlay {
+    /// the backing storage value of every key read before it was written
+    reads: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+    writes: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+impl Overlay {
+    /// Returns the pending write for key, if any.
+    pub fn pending(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.writes.get(key).cloned()
+    }
+
+    /// Remembers a value read from the backing storage. Only the first read is kept,
+    /// this is the value commit expects to find.
+    pub fn record_read(&mut self, key: &[u8], value: &Option<Vec<u8>>) {
+        if !self.reads.contains_key(key) {
+            self.reads.insert(key.to_vec(), value.clone());
+        }
+    }
+
+    /// Returns the length of the value key has for this call, from the pending write or
+    /// the recorded read. None if the call has neither read nor written key.
+    pub fn known_len(&self, key: &[u8]) -> Option<Option<usize>> {
+        match self.writes.get(key) {
+            Some(value) => Some(Some(value.len())),
+            None => self
+                .reads
+                .get(key)
+                .map(|value| value.as_ref().map(Vec::len)),
+        }
+    }
+
+    pub fn write(&mut self, key: &[u8], value: &[u8]) {
+        self.writes.insert(key.to_vec(), value.to_vec());
+    }
+
+    /// All pending writes, ordered by key
+    pub fn writes(&self) -> &BTreeMap<Vec<u8>, Vec<u8>> {
+        &self.writes
+    }
+}
+
+/// AccessSet lists the storage keys (as sha256 hashes) a prepared execution read and wrote.
+/// Executions whose sets do not conflict can be prepared in parallel and committed in any order.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AccessSet {
+    pub reads: BTreeSet<Vec<u8>>,
+    pub writes: BTreeSet<Vec<u8>>,
+}
+
+impl AccessSet {
+    /// true if either execution writes a key the other one reads or writes
+    pub fn conflicts_with(&self, other: &AccessSet) -> bool {
+        let touches =
+            |set: &AccessSet, key: &Vec<u8>| set.reads.contains(key) || set.writes.contains(key);
+        self.writes.iter().any(|key| touches(other, key))
+            || other.writes.iter().any(|key| self.reads.contains(key))
+    }
+}
+
+/// Simulation is the outcome of `call_handle_simulate`: everything a handle call would
+/// produce, without any of its writes
+#[derive(Debug, Clone, PartialEq)]
+pub struct Simulation {
+    /// messages, log and data the contract returned
+    pub result: ContractResult,
+    /// the gas of the call, as if it was executed
+    pub gas_report: GasReport,
+    /// number of storage entries the call would have written
+    pub writes: usize,
+}
+
+/// PreparedExecution is the outcome of `prepare_execute`: the contract result and
+/// the write set of a call that has not been applied to storage yet.
+/// Pass it to `commit` to apply it.
+pub struct PreparedExecution {
+    result: ContractResult,
+    gas_used: u64,
+    reads: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+    writes: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+impl PreparedExecution {
+    pub(crate) fn new(result: ContractResult, gas_used: u64, overlay: Overlay) -> Self {
+        // a failed call must not write anything, like a direct call
+        let writes = if result.is_err() {
+            BTreeMap::new()
+        } else {
+            overlay.writes
+        };
+        PreparedExecution {
+            result,
+            gas_used,
+            reads: overlay.reads,
+            writes,
+        }
+    }
+
+    pub fn result(&self) -> &ContractResult {
+        &self.result
+    }
+
+    /// Gas used by the contract while preparing
+    pub fn gas_used(&self) -> u64 {
+        self.gas_used
+    }
+
+    /// All key value pairs commit will write, ordered by key
+    pub fn writes(&self) -> Vec<(&[u8], &[u8])> {
+        self.writes
+            .iter()
+            .map(|(k, v)| (k.as_slice(), v.as_slice()))
+            .collect()
+    }
+
+    /// The keys read and written while preparing, to detect conflicts with other executions.
+    /// Keys that were only read after being written count as written.
+    pub fn access_set(&self) -> AccessSet {
+        AccessSet {
+            reads: self.reads.keys().map(|key| hash_key(key)).collect(),
+            writes: self.writes.keys().map(|key| hash_key(key)).collect(),
+        }
+    }
+
+    /// Fails with StateChangedErr if any value read while preparing has changed in storage
+    pub(crate) fn verify<S: Storage>(&self, storage: &S) -> Result<()> {
+        for (key, value) in self.reads.iter() {
+            if storage.get(key).ne(value) {
+                return StateChangedErr {}.fail();
+            }
+        }
+        Ok(())
+    }
+
+    /// Splits into the result and the writes to apply
+    pub(crate) fn into_parts(self) -> (ContractResult, BTreeMap<Vec<u8>, Vec<u8>>) {
+        (self.result, self.writes)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cosmwasm::mock::MockStorage;
+    use cosmwasm::traits::ReadonlyStorage;
+    use cosmwasm::types::Response;
+
+    fn prepared(overlay: Overlay) -> PreparedExecution {
+        PreparedExecution::new(ContractResult::Ok(Response::default()), 0, overlay)
+    }
+
+    #[test]
+    fn overlay_keeps_first_read() {
+        let mut overlay = Overlay::default();
+        overlay.record_read(b"foo", &Some(b"bar".to_vec()));
+        overlay.record_read(b"foo", &None);
+        assert_eq!(
+            overlay.reads.get(&b"foo".to_vec()),
+            Some(&Some(b"bar".to_vec()))
+        );
+
+        assert_eq!(overlay.pending(b"foo"), None);
+        overlay.write(b"foo", b"baz");
+        assert_eq!(overlay.pending(b"foo"), Some(b"baz".to_vec()));
+    }
+
+    #[test]
+    fn verify_accepts_unchanged_state() {
+        let mut storage = MockStorage::new();
+        storage.set(b"foo", b"bar");
+
+        let mut overlay = Overlay::default();
+        overlay.record_read(b"foo", &Some(b"bar".to_vec()));
+        overlay.record_read(b"missing", &None);
+        overlay.write(b"foo", b"baz");
+        overlay.write(b"new", b"value");
+        let prepared = prepared(overlay);
+        prepared.verify(&storage).unwrap();
+
+        let (result, writes) = prepared.into_parts();
+        assert!(!result.is_err());
+        assert_eq!(writes.get(b"foo".as_ref()), Some(&b"baz".to_vec()));
+        assert_eq!(writes.get(b"new".as_ref()), Some(&b"value".to_vec()));
+    }
+
+    #[test]
+    fn access_sets_detect_conflicts() {
+        let mut overlay = Overlay::default();
+        overlay.record_read(b"shared", &None);
+        overlay.write(b"mine", b"1");
+        let a = prepared(overlay).access_set();
+        assert_eq!(a.reads.len(), 1);
+        assert!(a.writes.contains(&hash_key(b"mine")));
+
+        // reading the same key is fine
+        let mut overlay = Overlay::default();
+        overlay.record_read(b"shared", &None);
+        overlay.write(b"yours", b"2");
+        let b = prepared(overlay).access_set();
+        assert!(!a.conflicts_with(&b));
+        assert!(!b.conflicts_with(&a));
+
+        // writing a key the other reads is not
+        let mut overlay = Overlay::default();
+        overlay.write(b"shared", b"3");
+        let c = prepared(overlay).access_set();
+        assert!(a.conflicts_with(&c));
+        assert!(c.conflicts_with(&a));
+    }
+
+    #[test]
+    fn verify_rejects_changed_state() {
+        let mut storage = MockStorage::new();
+        storage.set(b"foo", b"bar");
+
+        let mut overlay = Overlay::default();
+        overlay.record_read(b"missing", &None);
+        overlay.write(b"foo", b"baz");
+        let handle = prepared(overlay);
+
+        // someone else wrote a key we read as empty
+        storage.set(b"missing", b"now set");
+        match handle.verify(&storage) {
+            Err(crate::errors::Error::StateChangedErr { .. }) => {}
+            Err(e) => panic!("Unexpected error {:?}", e),
+            Ok(_) => panic!("Didn't detect the state change"),
+        }
+    }
+}
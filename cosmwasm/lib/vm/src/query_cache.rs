@@ -0,0 +1,186 @@
+use std::time::{Duration, Instant};
+
+use lru::LruCache;
+use sha2::{Digest, Sha256};
+
+use cosmwasm::traits::{Api, Storage};
+
+use crate::calls::call_query_raw;
+use crate::errors::Error;
+use crate::instance::Instance;
+
+/// QueryKey identifies a query result. The same code, contract, message and
+/// block height always give the same answer, so the result can be reused.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct QueryKey {
+    code_id: Vec<u8>,
+    contract: Vec<u8>,
+    msg_hash: Vec<u8>,
+    height: i64,
+}
+
+impl QueryKey {
+    /// contract is the (canonical) address of the contract instance, msg the raw query
+    pub fn new(code_id: &[u8], contract: &[u8], msg: &[u8], height: i64) -> Self {
+        QueryKey {
+            code_id: code_id.to_vec(),
+            contract: contract.to_vec(),
+            msg_hash: Sha256::digest(msg).to_vec(),
+            height,
+        }
+    }
+}
+
+struct Entry {
+    result: Vec<u8>,
+    created: Instant,
+}
+
+/// QueryCache keeps the raw results of recent queries, so a node serving the same
+/// query repeatedly (eg. dashboards polling) does not run the contract every time.
+/// Entries are dropped when the cache is full (least recently used first)
+/// and when they are older than the ttl.
+pub struct QueryCache {
+    entries: LruCache<QueryKey, Entry>,
+    ttl: Duration,
+    hits: u64,
+    misses: u64,
+}
+
+impl QueryCache {
+    pub fn new(size: usize, ttl: Duration) -> Self {
+        QueryCache {
+            entries: LruCache::new(size),
+            ttl,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    /// Returns the cached result, if there is one that did not expire yet
+    pub fn get(&mut self, key: &QueryKey) -> Option<Vec<u8>> {
+        let fresh = match self.entries.get(key) {
+            Some(entry) => entry.created.elapsed() < self.ttl,
+            None => {
+                self.misses += 1;
+                return None;
+            }
+        };
+        if !fresh {
+            self.entries.pop(key);
+            self.misses += 1;
+            return None;
+        }
+        self.hits += 1;
+        self.entries.get(key).map(|entry| entry.result.clone())
+    }
+
+    pub fn put(&mut self, key: QueryKey, result: Vec<u8>) {
+        let entry = Entry {
+            result,
+            created: Instant::now(),
+        };
+        self.entries.put(key, entry);
+    }
+
+    /// Returns the cached result or runs the query on instance and caches it.
+    /// Failed calls (eg. out of gas) are not cached.
+    pub fn query<S: Storage + 'static, A: Api + 'static>(
+        &mut self,
+        key: QueryKey,
+        instance: &mut Instance<S, A>,
+        msg: &[u8],
+    ) -> Result<Vec<u8>, Error> {
+        if let Some(result) = self.get(&key) {
+            return Ok(result);
+        }
+        let result = call_query_raw(instance, msg)?;
+        self.put(key, result.clone());
+        Ok(result)
+    }
+
+    /// Drops all entries, eg. after a chain reorg
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.misses
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::calls::call_init;
+    use crate::testing::mock_instance;
+    use cosmwasm::mock::mock_env;
+    use cosmwasm::types::coin;
+
+    static CONTRACT_0_7: &[u8] = include_bytes!("../testdata/contract_0.7.wasm");
+    static HOUR: Duration = Duration::from_secs(3600);
+
+    #[test]
+    fn get_returns_fresh_entries() {
+        let mut cache = QueryCache::new(2, HOUR);
+        let key = QueryKey::new(b"code", b"contract", b"{}", 10);
+        assert_eq!(cache.get(&key), None);
+
+        cache.put(key.clone(), b"result".to_vec());
+        assert_eq!(cache.get(&key), Some(b"result".to_vec()));
+        // any other height is a different query
+        assert_eq!(
+            cache.get(&QueryKey::new(b"code", b"contract", b"{}", 11)),
+            None
+        );
+        assert_eq!(cache.hits(), 1);
+        assert_eq!(cache.misses(), 2);
+
+        let mut expiring = QueryCache::new(2, Duration::from_secs(0));
+        expiring.put(key.clone(), b"result".to_vec());
+        assert_eq!(expiring.get(&key), None);
+        assert!(expiring.is_empty());
+    }
+
+    #[test]
+    fn size_is_bounded() {
+        let mut cache = QueryCache::new(2, HOUR);
+        for height in 0..3 {
+            cache.put(QueryKey::new(b"code", b"contract", b"{}", height), vec![]);
+        }
+        assert_eq!(cache.len(), 2);
+        assert_eq!(
+            cache.get(&QueryKey::new(b"code", b"contract", b"{}", 0)),
+            None
+        );
+    }
+
+    #[test]
+    fn query_runs_contract_once() {
+        let mut instance = mock_instance(&CONTRACT_0_7);
+        let env = mock_env(&instance.api, "creator", &coin("1000", "earth"), &[]);
+        let msg = r#"{"verifier": "verifies", "beneficiary": "benefits"}"#.as_bytes();
+        call_init(&mut instance, &env, msg).unwrap().unwrap();
+
+        let mut cache = QueryCache::new(10, HOUR);
+        let query = br#"{"verifier":{}}"#;
+        let key = QueryKey::new(b"code", b"contract", query, env.block.height);
+        let first = cache.query(key.clone(), &mut instance, query).unwrap();
+        let second = cache.query(key, &mut instance, query).unwrap();
+        assert_eq!(first, second);
+        assert_eq!(cache.misses(), 1);
+        assert_eq!(cache.hits(), 1);
+    }
+}
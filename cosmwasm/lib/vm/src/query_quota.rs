@@ -0,0 +1,158 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use cosmwasm::traits::{Api, Storage};
+
+use crate::calls::call_query_raw;
+use crate::errors::{Error, QuotaExceededErr};
+use crate::instance::Instance;
+
+/// QueryQuota limits the gas queries of any one source may use within a sliding window,
+/// so a single client cannot keep a public node busy with queries.
+/// The source is a tag chosen by the caller, eg. the client IP or api key.
+/// A query that starts within the quota runs to completion, so a source can exceed its
+/// limit by the gas of one query; all following queries are rejected until the window
+/// moved past enough of its usage.
+pub struct QueryQuota {
+    limit: u64,
+    window: Duration,
+    usage: HashMap<String, VecDeque<(Instant, u64)>>,
+}
+
+impl QueryQuota {
+    /// Allows limit gas per source within every period of length window
+    pub fn new(limit: u64, window: Duration) -> Self {
+        QueryQuota {
+            limit,
+            window,
+            usage: HashMap::new(),
+        }
+    }
+
+    /// Gas used by tag within the current window
+    pub fn used(&mut self, tag: &str) -> u64 {
+        let window = self.window;
+        match self.usage.get_mut(tag) {
+            Some(entries) => {
+                while let Some((time, _)) = entries.front() {
+                    if time.elapsed() < window {
+                        break;
+                    }
+                    entries.pop_front();
+                }
+                entries.iter().map(|(_, gas)| gas).sum()
+            }
+            None => 0,
+        }
+    }
+
+    /// Fails with QuotaExceededErr if tag has used up its quota
+    pub fn check(&mut self, tag: &str) -> Result<(), Error> {
+        let used = self.used(tag);
+        if used >= self.limit {
+            return QuotaExceededErr {
+                tag,
+                used,
+                limit: self.limit,
+            }
+            .fail();
+        }
+        Ok(())
+    }
+
+    /// Adds the gas of a query made by tag
+    pub fn record(&mut self, tag: &str, gas: u64) {
+        self.usage
+            .entry(tag.to_string())
+            .or_default()
+            .push_back((Instant::now(), gas));
+    }
+
+    /// Runs the query on instance if tag is within its quota and records the gas used
+    pub fn query<S: Storage + 'static, A: Api + 'static>(
+        &mut self,
+        tag: &str,
+        instance: &mut Instance<S, A>,
+        msg: &[u8],
+    ) -> Result<Vec<u8>, Error> {
+        self.check(tag)?;
+        let res = call_query_raw(instance, msg);
+        // failed queries cost gas as well
+        self.record(tag, instance.gas_report().used_internally);
+        res
+    }
+
+    /// Forgets the usage of sources without queries in the current window
+    pub fn prune(&mut self) {
+        let tags: Vec<String> = self.usage.keys().cloned().collect();
+        for tag in tags {
+            if self.used(&tag) == 0 {
+                self.usage.remove(&tag);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::calls::call_init;
+    use crate::gas::GasVersion;
+    use crate::testing::mock_instance;
+    use cosmwasm::mock::mock_env;
+    use cosmwasm::types::coin;
+
+    static CONTRACT_0_7: &[u8] = include_bytes!("../testdata/contract_0.7.wasm");
+    static HOUR: Duration = Duration::from_secs(3600);
+
+    #[test]
+    fn check_rejects_exhausted_sources() {
+        let mut quota = QueryQuota::new(100, HOUR);
+        quota.check("alice").unwrap();
+        quota.record("alice", 60);
+        quota.check("alice").unwrap();
+        quota.record("alice", 60);
+        match quota.check("alice") {
+            Err(Error::QuotaExceededErr {
+                tag, used, limit, ..
+            }) => {
+                assert_eq!(tag, "alice");
+                assert_eq!(used, 120);
+                assert_eq!(limit, 100);
+            }
+            Err(e) => panic!("Unexpected error {:?}", e),
+            Ok(_) => panic!("Quota was not enforced"),
+        }
+        // other sources are not affected
+        quota.check("bob").unwrap();
+    }
+
+    #[test]
+    fn usage_expires_with_window() {
+        let mut quota = QueryQuota::new(100, Duration::from_secs(0));
+        quota.record("alice", 200);
+        assert_eq!(quota.used("alice"), 0);
+        quota.check("alice").unwrap();
+        quota.prune();
+        assert!(quota.usage.is_empty());
+    }
+
+    #[test]
+    fn query_records_gas() {
+        let mut instance = mock_instance(&CONTRACT_0_7);
+        // charges host gas also on backends without wasm metering
+        instance.set_gas_version(GasVersion::V1);
+        let env = mock_env(&instance.api, "creator", &coin("1000", "earth"), &[]);
+        let msg = r#"{"verifier": "verifies", "beneficiary": "benefits"}"#.as_bytes();
+        call_init(&mut instance, &env, msg).unwrap().unwrap();
+
+        let mut quota = QueryQuota::new(1, HOUR);
+        quota
+            .query("alice", &mut instance, br#"{"verifier":{}}"#)
+            .unwrap();
+        assert_eq!(quota.used("alice"), instance.gas_report().used_internally);
+        assert!(quota
+            .query("alice", &mut instance, br#"{"verifier":{}}"#)
+            .is_err());
+    }
+}
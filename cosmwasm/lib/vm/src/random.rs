@@ -0,0 +1,66 @@
+use sha2::{Digest, Sha256};
+
+/// RandomState generates the bytes returned by the random import.
+///
+/// The output is deterministic: it only depends on the block entropy, the contract address,
+/// the index of the call in its block and how many bytes the call requested before. All nodes
+/// return the same bytes, which is required for consensus, and the bytes are as unpredictable
+/// as the block entropy. Every call of a block gets its own sequence, as long as the embedder
+/// gives each one another index.
+pub(crate) struct RandomState {
+    seed: Vec<u8>,
+    counter: u64,
+}
+
+impl RandomState {
+    pub fn new(entropy: &[u8], contract: &[u8], call_index: u64) -> Self {
+        let mut hasher = Sha256::new();
+        hasher.input(entropy);
+        hasher.input(contract);
+        hasher.input(call_index.to_be_bytes());
+        RandomState {
+            seed: hasher.result().to_vec(),
+            counter: 0,
+        }
+    }
+
+    /// Returns the next len bytes of the sequence
+    pub fn fill(&mut self, len: usize) -> Vec<u8> {
+        let mut out = Vec::with_capacity(len + 32);
+        while out.len() < len {
+            let mut hasher = Sha256::new();
+            hasher.input(&self.seed);
+            hasher.input(self.counter.to_be_bytes());
+            out.extend_from_slice(&hasher.result());
+            self.counter += 1;
+        }
+        out.truncate(len);
+        out
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn fill_is_deterministic() {
+        let mut a = RandomState::new(&[1u8; 32], b"contract", 0);
+        let mut b = RandomState::new(&[1u8; 32], b"contract", 0);
+        let first = a.fill(40);
+        assert_eq!(first.len(), 40);
+        assert_eq!(first, b.fill(40));
+
+        // the sequence continues
+        let next = a.fill(10);
+        assert_ne!(next, first[..10].to_vec());
+
+        // other contracts get other bytes
+        let mut other = RandomState::new(&[1u8; 32], b"other", 0);
+        assert_ne!(other.fill(40), first);
+
+        // and so do other calls of the same contract
+        let mut later = RandomState::new(&[1u8; 32], b"contract", 1);
+        assert_ne!(later.fill(40), first);
+    }
+}
@@ -0,0 +1,169 @@
+//! Typed requests for the call functions, for embedders that pass calls over an FFI
+//! boundary as one JSON document instead of separate env and msg buffers.
+
+use serde::{Deserialize, Serialize};
+use snafu::ResultExt;
+
+use cosmwasm::encoding::Binary;
+use cosmwasm::serde::{from_slice, to_vec};
+use cosmwasm::traits::{Api, Storage};
+use cosmwasm::types::{ContractResult, Env, QueryResult};
+
+use crate::calls::{call_handle, call_init, call_query};
+use crate::errors::{Error, ParseErr, RequestErr, SerializeErr};
+use crate::instance::Instance;
+
+/// The request format this version understands. Bumped on incompatible changes.
+pub static REQUEST_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct InitRequest {
+    pub version: u32,
+    pub env: Env,
+    /// the json message for the contract
+    pub msg: Binary,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ExecRequest {
+    pub version: u32,
+    pub env: Env,
+    /// the json message for the contract
+    pub msg: Binary,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct QueryRequest {
+    pub version: u32,
+    /// the json message for the contract
+    pub msg: Binary,
+}
+
+impl InitRequest {
+    pub fn new<T: Serialize>(env: Env, msg: &T) -> Result<Self, Error> {
+        Ok(InitRequest {
+            version: REQUEST_VERSION,
+            env,
+            msg: Binary(to_vec(msg).context(SerializeErr {})?),
+        })
+    }
+}
+
+impl ExecRequest {
+    pub fn new<T: Serialize>(env: Env, msg: &T) -> Result<Self, Error> {
+        Ok(ExecRequest {
+            version: REQUEST_VERSION,
+            env,
+            msg: Binary(to_vec(msg).context(SerializeErr {})?),
+        })
+    }
+}
+
+impl QueryRequest {
+    pub fn new<T: Serialize>(msg: &T) -> Result<Self, Error> {
+        Ok(QueryRequest {
+            version: REQUEST_VERSION,
+            msg: Binary(to_vec(msg).context(SerializeErr {})?),
+        })
+    }
+}
+
+/// Parses a serialized InitRequest, ExecRequest or QueryRequest
+pub fn parse_request<'a, T: Deserialize<'a>>(data: &'a [u8]) -> Result<T, Error> {
+    from_slice(data).context(ParseErr {})
+}
+
+pub fn init_request<S: Storage + 'static, A: Api + 'static>(
+    instance: &mut Instance<S, A>,
+    request: &InitRequest,
+) -> Result<ContractResult, Error> {
+    check_version(request.version)?;
+    call_init(instance, &request.env, request.msg.as_slice())
+}
+
+pub fn exec_request<S: Storage + 'static, A: Api + 'static>(
+    instance: &mut Instance<S, A>,
+    request: &ExecRequest,
+) -> Result<ContractResult, Error> {
+    check_version(request.version)?;
+    call_handle(instance, &request.env, request.msg.as_slice())
+}
+
+pub fn query_request<S: Storage + 'static, A: Api + 'static>(
+    instance: &mut Instance<S, A>,
+    request: &QueryRequest,
+) -> Result<QueryResult, Error> {
+    check_version(request.version)?;
+    call_query(instance, request.msg.as_slice())
+}
+
+fn check_version(version: u32) -> Result<(), Error> {
+    if version != REQUEST_VERSION {
+        return RequestErr {
+            msg: format!(
+                "unsupported request version {}, expected {}",
+                version, REQUEST_VERSION
+            ),
+        }
+        .fail();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::mock_instance;
+    use cosmwasm::mock::mock_env;
+    use cosmwasm::types::coin;
+
+    static CONTRACT_0_7: &[u8] = include_bytes!("../testdata/contract_0.7.wasm");
+
+    #[derive(Serialize)]
+    struct InitMsg {
+        verifier: String,
+        beneficiary: String,
+    }
+
+    #[derive(Serialize)]
+    #[serde(rename_all = "lowercase")]
+    enum QueryMsg {
+        Verifier {},
+    }
+
+    #[test]
+    fn requests_call_contract() {
+        let mut instance = mock_instance(&CONTRACT_0_7);
+        let env = mock_env(&instance.api, "creator", &coin("1000", "earth"), &[]);
+
+        let msg = InitMsg {
+            verifier: "verifies".to_string(),
+            beneficiary: "benefits".to_string(),
+        };
+        let init = InitRequest::new(env, &msg).unwrap();
+        // as received from the other side of the boundary
+        let init: InitRequest = parse_request(&to_vec(&init).unwrap()).unwrap();
+        init_request(&mut instance, &init).unwrap().unwrap();
+
+        let query = QueryRequest::new(&QueryMsg::Verifier {}).unwrap();
+        let res = query_request(&mut instance, &query).unwrap().unwrap();
+        let expected = call_query(&mut instance, br#"{"verifier":{}}"#)
+            .unwrap()
+            .unwrap();
+        assert_eq!(res, expected);
+    }
+
+    #[test]
+    fn unknown_version_is_rejected() {
+        let mut instance = mock_instance(&CONTRACT_0_7);
+        let mut query = QueryRequest::new(&QueryMsg::Verifier {}).unwrap();
+        query.version = REQUEST_VERSION + 1;
+        match query_request(&mut instance, &query) {
+            Err(Error::RequestErr { msg, .. }) => {
+                assert_eq!(msg, "unsupported request version 2, expected 1")
+            }
+            Err(e) => panic!("Unexpected error {:?}", e),
+            Ok(_) => panic!("Accepted unknown version"),
+        }
+    }
+}
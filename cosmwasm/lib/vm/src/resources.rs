@@ -0,0 +1,67 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// ResourceReport describes what the last init, handle or query call on an Instance used
+/// besides gas, to find pathological contracts and choose limits.
+/// Unlike the gas, duration differs between nodes and must not affect the call.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ResourceReport {
+    /// size of the wasm memory after the call, in pages of 64 KiB.
+    /// Wasm memory never shrinks, so this is the peak.
+    pub memory_pages: u32,
+    /// number of calls of every import used, by name
+    pub import_calls: BTreeMap<String, u32>,
+    /// wall-clock time spent in the call
+    pub duration: Duration,
+}
+
+/// StorageUsageReport sums up the storage writes of the last init or handle call on an
+/// Instance, so chains can charge rent or deposits for contract state. Writes of calls
+/// that failed or returned an error are dropped and not counted.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct StorageUsageReport {
+    pub writes: u32,
+    /// length of all keys and values written, counting overwrites again
+    pub bytes_written: u64,
+    /// change in the size of the contract's stored keys and values. Negative if values were
+    /// replaced by shorter ones. For prepared executions, this is relative to the state
+    /// when the execution was prepared.
+    pub net_bytes: i64,
+}
+
+impl StorageUsageReport {
+    pub(crate) fn record_write(
+        &mut self,
+        key_len: usize,
+        value_len: usize,
+        previous: Option<usize>,
+    ) {
+        self.writes += 1;
+        self.bytes_written += (key_len + value_len) as u64;
+        self.net_bytes += match previous {
+            Some(previous) => value_len as i64 - previous as i64,
+            None => (key_len + value_len) as i64,
+        };
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn record_write_counts_overwrites() {
+        let mut report = StorageUsageReport::default();
+        report.record_write(3, 10, None);
+        assert_eq!(report.net_bytes, 13);
+        report.record_write(3, 4, Some(10));
+        assert_eq!(
+            report,
+            StorageUsageReport {
+                writes: 2,
+                bytes_written: 20,
+                net_bytes: 7,
+            }
+        );
+    }
+}
@@ -0,0 +1,180 @@
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fs::{rename, File};
+use std::io::{self, ErrorKind, Read, Write};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use snafu::ResultExt;
+
+use crate::errors::{Error, IoErr};
+
+/// ContractStats sums up the init, handle and query calls of one code.
+/// Calls failing with an Error and calls returning a contract error both count as errors.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct ContractStats {
+    pub calls: u64,
+    pub errors: u64,
+    /// used_internally of all calls
+    pub gas_used: u64,
+    pub duration: Duration,
+}
+
+impl ContractStats {
+    pub fn average_gas(&self) -> u64 {
+        if self.calls == 0 {
+            return 0;
+        }
+        self.gas_used / self.calls
+    }
+
+    /// Divides by at most u32::MAX calls, 0 without calls
+    pub fn average_duration(&self) -> Duration {
+        let calls = u32::try_from(self.calls).unwrap_or(u32::MAX);
+        self.duration.checked_div(calls).unwrap_or_default()
+    }
+
+    /// errors divided by calls, 0 without calls
+    pub fn error_rate(&self) -> f64 {
+        if self.calls == 0 {
+            return 0.0;
+        }
+        self.errors as f64 / self.calls as f64
+    }
+
+    pub(crate) fn record(&mut self, gas_used: u64, duration: Duration, failed: bool) {
+        self.calls += 1;
+        self.gas_used = self.gas_used.saturating_add(gas_used);
+        self.duration += duration;
+        if failed {
+            self.errors += 1;
+        }
+    }
+
+    pub(crate) fn add(&mut self, other: &ContractStats) {
+        self.calls += other.calls;
+        self.errors += other.errors;
+        self.gas_used = self.gas_used.saturating_add(other.gas_used);
+        self.duration += other.duration;
+    }
+}
+
+/// StatsStore keeps the ContractStats of every code id in a text file, one line per code:
+/// "<hex id> <calls> <errors> <gas used> <duration in nanoseconds>".
+/// Nodes differ in what they record, so the file is local state and never part of consensus.
+pub(crate) struct StatsStore {
+    path: PathBuf,
+    stats: BTreeMap<Vec<u8>, ContractStats>,
+}
+
+impl StatsStore {
+    /// Loads the stats stored at path, if any. Lines that cannot be parsed are dropped.
+    pub fn open(path: PathBuf) -> Result<Self, Error> {
+        let mut stats = BTreeMap::new();
+        let mut data = String::new();
+        match File::open(&path) {
+            Ok(mut file) => {
+                file.read_to_string(&mut data).context(IoErr {})?;
+            }
+            Err(ref err) if err.kind() == ErrorKind::NotFound => {}
+            Err(err) => return Err(err).context(IoErr {}),
+        }
+        for line in data.lines() {
+            if let Some((id, entry)) = parse_line(line) {
+                stats.insert(id, entry);
+            }
+        }
+        Ok(StatsStore { path, stats })
+    }
+
+    pub fn get(&self, id: &[u8]) -> Option<ContractStats> {
+        self.stats.get(id).cloned()
+    }
+
+    pub fn add(&mut self, id: &[u8], stats: &ContractStats) {
+        if stats.calls > 0 {
+            self.stats.entry(id.to_vec()).or_default().add(stats);
+        }
+    }
+
+    pub fn remove(&mut self, id: &[u8]) {
+        self.stats.remove(id);
+    }
+
+    /// Writes all stats to the file, replacing it atomically
+    pub fn flush(&self) -> io::Result<()> {
+        let mut data = String::new();
+        for (id, stats) in self.stats.iter() {
+            data.push_str(&format!(
+                "{} {} {} {} {}\n",
+                hex::encode(id),
+                stats.calls,
+                stats.errors,
+                stats.gas_used,
+                stats.duration.as_nanos()
+            ));
+        }
+        let tmp_path = self.path.with_extension("tmp");
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(data.as_bytes())?;
+        file.sync_all()?;
+        rename(&tmp_path, &self.path)
+    }
+}
+
+fn parse_line(line: &str) -> Option<(Vec<u8>, ContractStats)> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    if fields.len() != 5 {
+        return None;
+    }
+    let id = hex::decode(fields[0]).ok()?;
+    let nanos: u64 = fields[4].parse().ok()?;
+    let stats = ContractStats {
+        calls: fields[1].parse().ok()?,
+        errors: fields[2].parse().ok()?,
+        gas_used: fields[3].parse().ok()?,
+        duration: Duration::from_nanos(nanos),
+    };
+    Some((id, stats))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn stats_survive_reopening() {
+        let tmp_dir = TempDir::new().unwrap();
+        let path = tmp_dir.path().join("stats");
+        let mut store = StatsStore::open(path.clone()).unwrap();
+        assert_eq!(store.get(b"code"), None);
+
+        let mut stats = ContractStats::default();
+        stats.record(100, Duration::from_millis(4), false);
+        stats.record(300, Duration::from_millis(2), true);
+        store.add(b"code", &stats);
+        store.add(b"code", &stats);
+        store.add(b"idle", &ContractStats::default());
+        store.flush().unwrap();
+
+        let mut store = StatsStore::open(path.clone()).unwrap();
+        let stats = store.get(b"code").unwrap();
+        assert_eq!(stats.calls, 4);
+        assert_eq!(stats.average_gas(), 200);
+        assert_eq!(stats.average_duration(), Duration::from_millis(3));
+        assert_eq!(
+            ContractStats::default().average_duration(),
+            Duration::default()
+        );
+        assert_eq!(stats.error_rate(), 0.5);
+        assert_eq!(store.get(b"idle"), None);
+
+        store.remove(b"code");
+        store.flush().unwrap();
+        // broken lines are dropped
+        std::fs::write(&path, "zz 1 2 3 4\n636f6465 1 0 5 6\n").unwrap();
+        let store = StatsStore::open(path).unwrap();
+        assert_eq!(store.get(b"code").unwrap().gas_used, 5);
+    }
+}
@@ -68,6 +68,42 @@ pub fn query<S: Storage + 'static, A: Api + 'static, T: Serialize + JsonSchema>(
     }
 }
 
+// with_allocator wraps the given module fields (imports, memory, data, funcs)
+// in a module that also exports a bump allocator, so hand written wat fixtures
+// can receive regions from the vm. The fields may not use a global named $next.
+#[cfg(test)]
+pub(crate) fn with_allocator(fields: &str) -> String {
+    format!("(module\n{}\n{}\n)", fields, ALLOCATOR_WAT)
+}
+
+// each region header is followed directly by its data, regions are 8 byte aligned
+#[cfg(test)]
+static ALLOCATOR_WAT: &str = r#"
+    (global $next (mut i32) (i32.const 1024))
+    (func (export "allocate") (param $size i32) (result i32)
+      (local $region i32)
+      global.get $next
+      local.set $region
+      local.get $region
+      local.get $region
+      i32.const 8
+      i32.add
+      i32.store
+      local.get $region
+      local.get $size
+      i32.store offset=4
+      local.get $region
+      local.get $size
+      i32.add
+      i32.const 15
+      i32.add
+      i32.const -8
+      i32.and
+      global.set $next
+      local.get $region)
+    (func (export "deallocate") (param i32))
+"#;
+
 /// Runs a series of IO tests, hammering especially on allocate and deallocate.
 /// This could be especially useful when run with some kind of leak detector.
 pub fn test_io<S: Storage + 'static, A: Api + 'static>(instance: &mut Instance<S, A>) {
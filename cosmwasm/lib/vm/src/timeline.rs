@@ -0,0 +1,191 @@
+//! Step by step views of recorded calls, to find out what a failed execution did before it
+//! failed. Record a trace with a TraceRecorder and `Instance::set_gas_audit(true)`, then
+//! build the timeline from its events. Like the trace, the timeline only holds key hashes
+//! and value lengths, so it can be shared without leaking contract state.
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+use snafu::ResultExt;
+
+use cosmwasm::serde::to_vec;
+
+use crate::errors::{Result, SerializeErr};
+use crate::trace::TraceEvent;
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum StepKind {
+    Read,
+    Write,
+}
+
+/// A value written by the call so far
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct PendingWrite {
+    /// hex encoded sha256 of the key
+    pub key_hash: String,
+    pub value_len: usize,
+}
+
+/// One storage access of a call, with the state of the call right after it
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct TimelineStep {
+    /// the entry point the access happened in
+    pub entry_point: String,
+    pub kind: StepKind,
+    /// hex encoded sha256 of the key
+    pub key_hash: String,
+    /// None for reads of keys that are not set
+    pub value_len: Option<usize>,
+    /// gas charged by the call up to and including this access. 0 without gas audit.
+    pub gas_used: u64,
+    /// all writes of the call so far, latest value per key, ordered by key hash
+    pub writes: Vec<PendingWrite>,
+}
+
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct Timeline {
+    pub steps: Vec<TimelineStep>,
+}
+
+impl Timeline {
+    /// Builds the timeline of all calls in the events, eg. from `TraceRecorder::events`.
+    /// Each entry point starts over with no writes and no gas used.
+    pub fn from_events(events: &[TraceEvent]) -> Self {
+        let mut steps = Vec::new();
+        let mut entry_point = String::new();
+        let mut gas_used = 0u64;
+        let mut writes = BTreeMap::new();
+        for event in events {
+            let (kind, key_hash, value_len) = match event {
+                TraceEvent::EntryPoint { name } => {
+                    entry_point = name.clone();
+                    gas_used = 0;
+                    writes.clear();
+                    continue;
+                }
+                TraceEvent::GasCharge { amount, .. } => {
+                    gas_used = gas_used.saturating_add(*amount);
+                    continue;
+                }
+                TraceEvent::ReadDb {
+                    key_hash,
+                    value_len,
+                } => (StepKind::Read, key_hash, *value_len),
+                TraceEvent::WriteDb {
+                    key_hash,
+                    value_len,
+                } => {
+                    writes.insert(hex::encode(key_hash), *value_len);
+                    (StepKind::Write, key_hash, Some(*value_len))
+                }
+                _ => continue,
+            };
+            steps.push(TimelineStep {
+                entry_point: entry_point.clone(),
+                kind,
+                key_hash: hex::encode(key_hash),
+                value_len,
+                gas_used,
+                writes: writes
+                    .iter()
+                    .map(|(key_hash, value_len)| PendingWrite {
+                        key_hash: key_hash.clone(),
+                        value_len: *value_len,
+                    })
+                    .collect(),
+            });
+        }
+        Timeline { steps }
+    }
+
+    /// The timeline as JSON, for tools that display it
+    pub fn to_json(&self) -> Result<Vec<u8>> {
+        to_vec(self).context(SerializeErr {})
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::calls::call_init;
+    use crate::testing::mock_instance;
+    use crate::trace::{hash_key, TraceRecorder};
+    use cosmwasm::mock::mock_env;
+    use cosmwasm::types::coin;
+
+    static CONTRACT_0_7: &[u8] = include_bytes!("../testdata/contract_0.7.wasm");
+
+    #[test]
+    fn timeline_follows_storage_accesses() {
+        let write = |key: &[u8], value_len| TraceEvent::WriteDb {
+            key_hash: hash_key(key),
+            value_len,
+        };
+        let charge = |amount| TraceEvent::GasCharge {
+            seq: 0,
+            source: "wasm".to_string(),
+            amount,
+        };
+        let events = vec![
+            TraceEvent::EntryPoint {
+                name: "handle".to_string(),
+            },
+            charge(10),
+            write(b"a", 3),
+            charge(5),
+            TraceEvent::ReadDb {
+                key_hash: hash_key(b"b"),
+                value_len: None,
+            },
+            write(b"a", 1),
+            TraceEvent::EntryPoint {
+                name: "query".to_string(),
+            },
+            TraceEvent::ReadDb {
+                key_hash: hash_key(b"a"),
+                value_len: Some(1),
+            },
+        ];
+        let timeline = Timeline::from_events(&events);
+        assert_eq!(timeline.steps.len(), 4);
+        let steps = &timeline.steps;
+        assert_eq!(steps[0].kind, StepKind::Write);
+        assert_eq!(steps[0].gas_used, 10);
+        assert_eq!(steps[1].kind, StepKind::Read);
+        assert_eq!(steps[1].value_len, None);
+        assert_eq!(steps[1].gas_used, 15);
+        assert_eq!(steps[1].writes, steps[0].writes);
+        // the second write replaces the first
+        assert_eq!(
+            steps[2].writes,
+            vec![PendingWrite {
+                key_hash: hex::encode(hash_key(b"a")),
+                value_len: 1,
+            }]
+        );
+        assert_eq!(steps[3].entry_point, "query");
+        assert_eq!(steps[3].gas_used, 0);
+        assert!(steps[3].writes.is_empty());
+    }
+
+    #[test]
+    fn timeline_of_recorded_call() {
+        let mut instance = mock_instance(&CONTRACT_0_7);
+        let recorder = TraceRecorder::new();
+        instance.set_trace_sink(Box::new(recorder.clone()));
+        instance.set_gas_audit(true);
+        let env = mock_env(&instance.api, "creator", &coin("1000", "earth"), &[]);
+        let msg = br#"{"verifier": "verifies", "beneficiary": "benefits"}"#;
+        call_init(&mut instance, &env, msg).unwrap().unwrap();
+
+        let timeline = Timeline::from_events(&recorder.events());
+        let last = timeline.steps.last().unwrap();
+        assert_eq!(last.entry_point, "init");
+        assert_eq!(last.kind, StepKind::Write);
+        assert_eq!(last.writes.len(), 1);
+        assert_eq!(last.writes[0].key_hash, hex::encode(hash_key(b"config")));
+        let json = String::from_utf8(timeline.to_json().unwrap()).unwrap();
+        assert!(json.contains(r#""kind":"write""#));
+    }
+}
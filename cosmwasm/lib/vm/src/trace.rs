@@ -0,0 +1,196 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use sha2::{Digest, Sha256};
+
+use cosmwasm::types::CanonicalAddr;
+
+/// Structured events emitted while a contract is executed.
+/// Storage keys are only reported as hashes, values only by their length,
+/// so traces can be shared without leaking contract state.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TraceEvent {
+    /// An entry point (init, handle or query) is about to be called
+    EntryPoint { name: String },
+    /// The contract read from storage. value_len is None if the key was not set.
+    ReadDb {
+        key_hash: Vec<u8>,
+        value_len: Option<usize>,
+    },
+    /// The contract wrote to storage
+    WriteDb { key_hash: Vec<u8>, value_len: usize },
+    /// Gas left at the start and end of a call
+    GasCheckpoint { remaining: u64 },
+    /// A log attribute returned by the contract
+    Log { key: String, value: String },
+    /// Gas charged in audit mode, see `Instance::set_gas_audit`. source is "wasm" for the
+    /// instructions executed since the previous charge, otherwise the import that charged.
+    /// seq counts the charges of a call, starting at 0.
+    GasCharge {
+        seq: u64,
+        source: String,
+        amount: u64,
+    },
+    /// A call took longer than the threshold set with `Instance::set_slow_call_threshold`.
+    /// Sent after the call returned, with what it used. contract is None for queries and
+    /// raw calls, input_len is the size of the env and msg passed to the contract.
+    SlowCall {
+        entry_point: String,
+        code_id: Option<Vec<u8>>,
+        contract: Option<CanonicalAddr>,
+        duration: Duration,
+        gas_used: u64,
+        import_calls: BTreeMap<String, u32>,
+        memory_pages: u32,
+        input_len: usize,
+    },
+}
+
+/// TraceSink receives all events of the calls on the Instance it is registered on.
+/// Register one with `Instance::set_trace_sink`.
+pub trait TraceSink {
+    fn record(&mut self, event: TraceEvent);
+}
+
+/// TraceRecorder keeps all events in memory. It can be cloned before handing it to the
+/// instance; all clones share the same event list.
+#[derive(Clone, Default)]
+pub struct TraceRecorder {
+    events: Arc<Mutex<Vec<TraceEvent>>>,
+}
+
+impl TraceRecorder {
+    pub fn new() -> Self {
+        TraceRecorder::default()
+    }
+
+    /// Returns a copy of all events recorded so far
+    pub fn events(&self) -> Vec<TraceEvent> {
+        self.events.lock().unwrap().clone()
+    }
+}
+
+impl TraceSink for TraceRecorder {
+    fn record(&mut self, event: TraceEvent) {
+        self.events.lock().unwrap().push(event);
+    }
+}
+
+/// LogSink receives the debug messages contracts emit with debug_print.
+/// Without a registered sink the messages are dropped, which is what validators should do.
+/// Register one with `Instance::set_log_sink`.
+pub trait LogSink {
+    fn log(&mut self, message: &str);
+}
+
+/// LogRecorder keeps all messages in memory, shared between its clones like TraceRecorder.
+#[derive(Clone, Default)]
+pub struct LogRecorder {
+    messages: Arc<Mutex<Vec<String>>>,
+}
+
+impl LogRecorder {
+    pub fn new() -> Self {
+        LogRecorder::default()
+    }
+
+    /// Returns a copy of all messages logged so far
+    pub fn messages(&self) -> Vec<String> {
+        self.messages.lock().unwrap().clone()
+    }
+}
+
+impl LogSink for LogRecorder {
+    fn log(&mut self, message: &str) {
+        self.messages.lock().unwrap().push(message.to_string());
+    }
+}
+
+/// The first point where the gas charges of two traces differ, as returned by diff_gas_traces.
+/// A side is None if its trace has fewer charges.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GasDivergence {
+    /// position among the GasCharge events of both traces
+    pub index: usize,
+    pub left: Option<TraceEvent>,
+    pub right: Option<TraceEvent>,
+}
+
+/// Compares the GasCharge events of two audit traces, eg. recorded by two nodes for the
+/// same call, and returns where they diverge first. Other events are ignored.
+/// None means both charged exactly the same gas in the same order.
+pub fn diff_gas_traces(left: &[TraceEvent], right: &[TraceEvent]) -> Option<GasDivergence> {
+    let charges = |events: &[TraceEvent]| -> Vec<TraceEvent> {
+        events
+            .iter()
+            .filter_map(|e| match e {
+                TraceEvent::GasCharge { .. } => Some(e.clone()),
+                _ => None,
+            })
+            .collect()
+    };
+    let left = charges(left);
+    let right = charges(right);
+    let len = left.len().max(right.len());
+    (0..len)
+        .find(|&i| left.get(i) != right.get(i))
+        .map(|index| GasDivergence {
+            index,
+            left: left.get(index).cloned(),
+            right: right.get(index).cloned(),
+        })
+}
+
+pub fn hash_key(key: &[u8]) -> Vec<u8> {
+    Sha256::digest(key).to_vec()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn charge(seq: u64, source: &str, amount: u64) -> TraceEvent {
+        TraceEvent::GasCharge {
+            seq,
+            source: source.to_string(),
+            amount,
+        }
+    }
+
+    #[test]
+    fn diff_gas_traces_finds_first_divergence() {
+        let left = vec![
+            TraceEvent::EntryPoint {
+                name: "handle".to_string(),
+            },
+            charge(0, "wasm", 500),
+            charge(1, "read_db", 1009),
+            charge(2, "wasm", 20),
+        ];
+        let mut right = left.clone();
+        // other events do not matter
+        right.remove(0);
+        assert_eq!(diff_gas_traces(&left, &right), None);
+
+        right[1] = charge(1, "read_db", 1018);
+        assert_eq!(
+            diff_gas_traces(&left, &right),
+            Some(GasDivergence {
+                index: 1,
+                left: Some(charge(1, "read_db", 1009)),
+                right: Some(charge(1, "read_db", 1018)),
+            })
+        );
+
+        let shorter = &left[..3];
+        assert_eq!(
+            diff_gas_traces(&left, shorter),
+            Some(GasDivergence {
+                index: 2,
+                left: Some(charge(2, "wasm", 20)),
+                right: None,
+            })
+        );
+    }
+}
@@ -1,46 +1,298 @@
-use std::fs::{File, OpenOptions};
-use std::io::{Read, Write};
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::fs::{hard_link, read_dir, remove_file, rename, File, OpenOptions};
+use std::io::{self, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 
+use fs2::FileExt;
 use sha2::{Digest, Sha256};
 use snafu::ResultExt;
 
-use crate::errors::{Error, IoErr};
+use crate::errors::{Error, IntegrityErr, IoErr};
+
+/// zstd frames start with this magic number. Files without it are raw wasm
+/// stored by older versions, which can be migrated with rewrite_store.
+static ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+static COMPRESSION_LEVEL: i32 = 3;
+static TMP_SUFFIX: &str = ".tmp";
+static LOCK_FILE: &str = ".lock";
 
 pub fn wasm_hash(wasm: &[u8]) -> Vec<u8> {
     Sha256::digest(wasm).to_vec()
 }
 
-/// save stores the wasm code in the given directory and returns an ID for lookup.
-/// It will create the directory if it doesn't exist.
+/// WasmStore keeps the original code of all contracts, which CosmCache compiles from.
+/// Implement it to keep the code in the application database instead of the filesystem.
+pub trait WasmStore {
+    /// Stores the code and returns its sha256 hash as the id.
+    /// Fails if code with this id is already stored.
+    fn save(&mut self, wasm: &[u8]) -> Result<Vec<u8>, Error>;
+
+    /// Returns the code stored under id, failing with IntegrityErr if it does not hash to id
+    fn load(&self, id: &[u8]) -> Result<Vec<u8>, Error>;
+
+    /// Migrates code stored in an outdated format, returns the number of migrated entries
+    fn rewrite(&self) -> Result<usize, Error> {
+        Ok(0)
+    }
+
+    /// Lists the ids of all stored code with the time it was saved, for CosmCache::gc.
+    /// Stores that do not implement it list nothing, so their code is never collected.
+    fn list(&self) -> Result<Vec<(Vec<u8>, SystemTime)>, Error> {
+        Ok(Vec::new())
+    }
+
+    /// Removes the code stored under id
+    fn remove(&mut self, _id: &[u8]) -> Result<(), Error> {
+        let err = io::Error::new(
+            ErrorKind::InvalidInput,
+            "store does not support removing code",
+        );
+        Err(err).context(IoErr {})
+    }
+}
+
+/// FileSystemStore stores every contract compressed in a file of the given directory,
+/// see save and load
+pub struct FileSystemStore {
+    dir: PathBuf,
+}
+
+impl FileSystemStore {
+    /// The directory must exist
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        FileSystemStore { dir: dir.into() }
+    }
+}
+
+impl WasmStore for FileSystemStore {
+    fn save(&mut self, wasm: &[u8]) -> Result<Vec<u8>, Error> {
+        save(&self.dir, wasm)
+    }
+
+    fn load(&self, id: &[u8]) -> Result<Vec<u8>, Error> {
+        load(&self.dir, id)
+    }
+
+    fn rewrite(&self) -> Result<usize, Error> {
+        rewrite_store(&self.dir)
+    }
+
+    fn list(&self) -> Result<Vec<(Vec<u8>, SystemTime)>, Error> {
+        let _lock = StoreLock::shared(&self.dir)?;
+        let mut entries = Vec::new();
+        for entry in read_dir(&self.dir).context(IoErr {})? {
+            let entry = entry.context(IoErr {})?;
+            let id = match entry.file_name().to_str().map(hex::decode) {
+                Some(Ok(id)) => id,
+                // not written by save, eg. the lock file
+                _ => continue,
+            };
+            let modified = entry
+                .metadata()
+                .and_then(|meta| meta.modified())
+                .context(IoErr {})?;
+            entries.push((id, modified));
+        }
+        Ok(entries)
+    }
+
+    fn remove(&mut self, id: &[u8]) -> Result<(), Error> {
+        let _lock = StoreLock::exclusive(&self.dir)?;
+        remove_file(self.dir.join(hex::encode(id))).context(IoErr {})
+    }
+}
+
+/// MemoryStore keeps all code in memory, eg. for tests and short-lived nodes
+#[derive(Default)]
+pub struct MemoryStore {
+    // code and the time it was saved
+    codes: HashMap<Vec<u8>, (Vec<u8>, SystemTime)>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        MemoryStore::default()
+    }
+}
+
+impl WasmStore for MemoryStore {
+    fn save(&mut self, wasm: &[u8]) -> Result<Vec<u8>, Error> {
+        let id = wasm_hash(wasm);
+        if self.codes.contains_key(&id) {
+            let err = io::Error::new(ErrorKind::AlreadyExists, "code already stored");
+            return Err(err).context(IoErr {});
+        }
+        self.codes
+            .insert(id.clone(), (wasm.to_vec(), SystemTime::now()));
+        Ok(id)
+    }
+
+    fn load(&self, id: &[u8]) -> Result<Vec<u8>, Error> {
+        match self.codes.get(id) {
+            Some((wasm, _)) => Ok(wasm.clone()),
+            None => not_found(),
+        }
+    }
+
+    fn list(&self) -> Result<Vec<(Vec<u8>, SystemTime)>, Error> {
+        Ok(self
+            .codes
+            .iter()
+            .map(|(id, (_, saved))| (id.clone(), *saved))
+            .collect())
+    }
+
+    fn remove(&mut self, id: &[u8]) -> Result<(), Error> {
+        match self.codes.remove(id) {
+            Some(_) => Ok(()),
+            None => not_found(),
+        }
+    }
+}
+
+fn not_found<T>() -> Result<T, Error> {
+    let err = io::Error::new(ErrorKind::NotFound, "code not found");
+    Err(err).context(IoErr {})
+}
+
+/// save stores the wasm code compressed in the given directory and returns an ID for lookup.
+/// The ID is the sha256 hash of the uncompressed code, which load uses to verify integrity.
+/// The directory must exist.
 /// If the file already exists, it will return an error.
+///
+/// Several processes can share a directory: the code is written to a temporary file and
+/// linked in place once complete, so no process ever sees a partially written file.
 pub fn save<P: Into<PathBuf>>(dir: P, wasm: &[u8]) -> Result<Vec<u8>, Error> {
     // calculate filename
+    let dir = dir.into();
     let id = wasm_hash(wasm);
-    let filename = hex::encode(&id);
-    let filepath = dir.into().join(&filename);
+    let filepath = dir.join(hex::encode(&id));
 
-    // write data to file
-    let mut file = OpenOptions::new()
-        .write(true)
-        .create_new(true)
-        .open(filepath)
-        .context(IoErr {})?;
-    file.write_all(wasm).context(IoErr {})?;
+    let compressed = zstd::stream::encode_all(wasm, COMPRESSION_LEVEL).context(IoErr {})?;
+
+    let _lock = StoreLock::exclusive(&dir)?;
+    let tmp_path = write_tmp_file(&dir, &id, &compressed)?;
+    // unlike rename, this fails if the file exists
+    let linked = hard_link(&tmp_path, &filepath).context(IoErr {});
+    remove_file(&tmp_path).context(IoErr {})?;
+    linked?;
 
     Ok(id)
 }
 
+/// load returns the code stored under id.
+/// Fails with IntegrityErr if the stored data cannot be decompressed or does not hash to id.
 pub fn load<P: Into<PathBuf>>(dir: P, id: &[u8]) -> Result<Vec<u8>, Error> {
     // this requires the directory and file to exist
-    let path = dir.into().join(hex::encode(id));
-    let mut file = File::open(path).context(IoErr {})?;
-
-    let mut wasm = Vec::<u8>::new();
-    let _ = file.read_to_end(&mut wasm).context(IoErr {})?;
+    let dir = dir.into();
+    let path = dir.join(hex::encode(id));
+    let data = {
+        let _lock = StoreLock::shared(&dir)?;
+        read_file(&path)?
+    };
+    let wasm = if data.starts_with(ZSTD_MAGIC) {
+        match zstd::stream::decode_all(data.as_slice()) {
+            Ok(wasm) => wasm,
+            Err(_) => return IntegrityErr {}.fail(),
+        }
+    } else {
+        data
+    };
+    if wasm_hash(&wasm).ne(&id) {
+        return IntegrityErr {}.fail();
+    }
     Ok(wasm)
 }
 
+/// rewrite_store compresses all files in dir that were stored uncompressed by older versions.
+/// Every file is verified before it is rewritten, so corrupted code is reported as
+/// IntegrityErr rather than being carried over into the new format.
+/// Returns the number of rewritten files.
+pub fn rewrite_store<P: Into<PathBuf>>(dir: P) -> Result<usize, Error> {
+    let dir = dir.into();
+    let _lock = StoreLock::exclusive(&dir)?;
+    let mut rewritten = 0;
+    for entry in read_dir(&dir).context(IoErr {})? {
+        let path = entry.context(IoErr {})?.path();
+        let id = match path.file_name().and_then(|name| name.to_str()) {
+            Some(name) => match hex::decode(name) {
+                Ok(id) => id,
+                // not written by save, eg. a leftover temporary file
+                Err(_) => continue,
+            },
+            None => continue,
+        };
+        let data = read_file(&path)?;
+        if data.starts_with(ZSTD_MAGIC) {
+            continue;
+        }
+        if wasm_hash(&data).ne(&id) {
+            return IntegrityErr {}.fail();
+        }
+
+        // write to a temporary file first and move it in place,
+        // so a crash never leaves a truncated file behind
+        let compressed =
+            zstd::stream::encode_all(data.as_slice(), COMPRESSION_LEVEL).context(IoErr {})?;
+        let tmp_path = write_tmp_file(&dir, &id, &compressed)?;
+        rename(&tmp_path, &path).context(IoErr {})?;
+        rewritten += 1;
+    }
+    Ok(rewritten)
+}
+
+/// Writes data to a temporary file for id in dir and syncs it to disk.
+/// The name contains the process id, so processes sharing dir never write the same file.
+fn write_tmp_file(dir: &Path, id: &[u8], data: &[u8]) -> Result<PathBuf, Error> {
+    let name = format!("{}.{}{}", hex::encode(id), std::process::id(), TMP_SUFFIX);
+    let path = dir.join(name);
+    let mut file = File::create(&path).context(IoErr {})?;
+    file.write_all(data).context(IoErr {})?;
+    // the id is stored by the caller, so the code must survive a crash or shutdown
+    file.sync_all().context(IoErr {})?;
+    Ok(path)
+}
+
+/// An advisory lock on a store directory, held until dropped.
+/// Reads take it shared, writes exclusive, so processes sharing the directory
+/// never read while another one replaces files.
+struct StoreLock {
+    _file: File,
+}
+
+impl StoreLock {
+    fn shared(dir: &Path) -> Result<Self, Error> {
+        let file = open_lock_file(dir)?;
+        file.lock_shared().context(IoErr {})?;
+        Ok(StoreLock { _file: file })
+    }
+
+    fn exclusive(dir: &Path) -> Result<Self, Error> {
+        let file = open_lock_file(dir)?;
+        file.lock_exclusive().context(IoErr {})?;
+        Ok(StoreLock { _file: file })
+    }
+}
+
+// the lock is released when the file is closed
+fn open_lock_file(dir: &Path) -> Result<File, Error> {
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(dir.join(LOCK_FILE))
+        .context(IoErr {})
+}
+
+fn read_file(path: &PathBuf) -> Result<Vec<u8>, Error> {
+    let mut file = File::open(path).context(IoErr {})?;
+    let mut data = Vec::<u8>::new();
+    let _ = file.read_to_end(&mut data).context(IoErr {})?;
+    Ok(data)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -81,6 +333,100 @@ mod test {
         assert_eq!(code, loaded);
     }
 
+    #[test]
+    fn save_compresses_code() {
+        let tmp_dir = TempDir::new().unwrap();
+        let path = tmp_dir.path();
+        let code = vec![12u8; 2000];
+        let id = save(path, &code).unwrap();
+
+        let stored = std::fs::read(path.join(hex::encode(&id))).unwrap();
+        assert!(stored.starts_with(ZSTD_MAGIC));
+        assert!(stored.len() < code.len());
+    }
+
+    #[test]
+    fn load_detects_tampering() {
+        let tmp_dir = TempDir::new().unwrap();
+        let path = tmp_dir.path();
+        let id = save(path, &vec![12u8; 17]).unwrap();
+        let filepath = path.join(hex::encode(&id));
+
+        // valid compressed data with the wrong content
+        let other = zstd::stream::encode_all(&[13u8; 17][..], COMPRESSION_LEVEL).unwrap();
+        std::fs::write(&filepath, &other).unwrap();
+        match load(path, &id) {
+            Err(Error::IntegrityErr { .. }) => {}
+            Err(e) => panic!("Unexpected error {:?}", e),
+            Ok(_) => panic!("Didn't detect tampered code"),
+        }
+
+        // broken compressed data
+        let mut broken = std::fs::read(&filepath).unwrap();
+        broken.truncate(8);
+        std::fs::write(&filepath, &broken).unwrap();
+        match load(path, &id) {
+            Err(Error::IntegrityErr { .. }) => {}
+            Err(e) => panic!("Unexpected error {:?}", e),
+            Ok(_) => panic!("Didn't detect corrupted code"),
+        }
+    }
+
+    #[test]
+    fn rewrite_store_migrates_raw_files() {
+        let tmp_dir = TempDir::new().unwrap();
+        let path = tmp_dir.path();
+        let legacy = vec![7u8; 300];
+        let legacy_id = wasm_hash(&legacy);
+        std::fs::write(path.join(hex::encode(&legacy_id)), &legacy).unwrap();
+        let current_id = save(path, &vec![12u8; 17]).unwrap();
+
+        // raw files can still be loaded
+        assert_eq!(load(path, &legacy_id).unwrap(), legacy);
+
+        assert_eq!(rewrite_store(path).unwrap(), 1);
+        let stored = std::fs::read(path.join(hex::encode(&legacy_id))).unwrap();
+        assert!(stored.starts_with(ZSTD_MAGIC));
+        assert_eq!(load(path, &legacy_id).unwrap(), legacy);
+        assert_eq!(load(path, &current_id).unwrap(), vec![12u8; 17]);
+
+        // nothing left to do
+        assert_eq!(rewrite_store(path).unwrap(), 0);
+
+        // corrupted raw files are reported
+        std::fs::write(path.join(hex::encode(wasm_hash(b"foo"))), b"bar").unwrap();
+        match rewrite_store(path) {
+            Err(Error::IntegrityErr { .. }) => {}
+            Err(e) => panic!("Unexpected error {:?}", e),
+            Ok(_) => panic!("Didn't detect corrupted code"),
+        }
+    }
+
+    #[test]
+    fn stores_implement_wasm_store() {
+        let tmp_dir = TempDir::new().unwrap();
+        let stores: Vec<Box<dyn WasmStore>> = vec![
+            Box::new(FileSystemStore::new(tmp_dir.path())),
+            Box::new(MemoryStore::new()),
+        ];
+        let code = vec![12u8; 17];
+        for mut store in stores {
+            let id = store.save(&code).unwrap();
+            assert_eq!(id, wasm_hash(&code));
+            assert_eq!(store.load(&id).unwrap(), code);
+            assert!(store.save(&code).is_err());
+            assert!(store.load(&wasm_hash(b"other")).is_err());
+            assert_eq!(store.rewrite().unwrap(), 0);
+
+            let listed: Vec<Vec<u8>> = store.list().unwrap().into_iter().map(|e| e.0).collect();
+            assert_eq!(listed, vec![id.clone()]);
+            store.remove(&id).unwrap();
+            assert!(store.load(&id).is_err());
+            assert!(store.list().unwrap().is_empty());
+            assert!(store.remove(&id).is_err());
+        }
+    }
+
     #[test]
     fn file_already_exists() {
         let tmp_dir = TempDir::new().unwrap();
@@ -91,5 +437,13 @@ mod test {
 
         let dup = save(path, &code);
         assert!(dup.is_err());
+
+        // the temporary file of the failed save was removed
+        let mut names: Vec<String> = read_dir(path)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(names, vec![LOCK_FILE.to_string(), hex::encode(&id)]);
     }
 }
@@ -0,0 +1,258 @@
+#![cfg(not(target_arch = "wasm32"))]
+//! Native implementations of the signature schemes offered to contracts through `Api`.
+//! Only built with the crypto feature. MockApi uses them, and hosts can use them to
+//! implement the Api methods, so the same code runs in unit tests and on the chain.
+
+use std::convert::TryFrom;
+
+use ed25519_zebra::batch;
+use k256::ecdsa::signature::hazmat::PrehashVerifier;
+use k256::ecdsa::{RecoveryId, Signature as Secp256k1Signature, VerifyingKey};
+use rand_core::OsRng;
+
+use crate::errors::{ContractErr, Result};
+
+pub static MESSAGE_HASH_LENGTH: usize = 32;
+pub static SECP256K1_SIGNATURE_LENGTH: usize = 64;
+pub static ED25519_SIGNATURE_LENGTH: usize = 64;
+pub static ED25519_PUBKEY_LENGTH: usize = 32;
+
+/// Verifies a secp256k1 signature of a sha256 message hash.
+/// Signatures must be in the 64 byte (r, s) format with a low s value, as required by the
+/// cosmos-sdk. Public keys can be compressed (33 bytes) or uncompressed (65 bytes).
+/// Returns an error if any argument is malformed and Ok(false) if the signature does not match.
+pub fn secp256k1_verify(message_hash: &[u8], signature: &[u8], public_key: &[u8]) -> Result<bool> {
+    check_message_hash(message_hash)?;
+    let signature = parse_secp256k1_signature(signature)?;
+    let key = match VerifyingKey::from_sec1_bytes(public_key) {
+        Ok(key) => key,
+        Err(_) => {
+            return ContractErr {
+                msg: "invalid secp256k1 public key",
+            }
+            .fail()
+        }
+    };
+    Ok(key.verify_prehash(message_hash, &signature).is_ok())
+}
+
+/// Recovers the public key that created a secp256k1 signature of message_hash.
+/// recovery_param is the recovery id (0-3) produced along with the signature.
+/// Returns the public key in uncompressed format (65 bytes).
+pub fn secp256k1_recover_pubkey(
+    message_hash: &[u8],
+    signature: &[u8],
+    recovery_param: u8,
+) -> Result<Vec<u8>> {
+    check_message_hash(message_hash)?;
+    let signature = parse_secp256k1_signature(signature)?;
+    let recovery_id = match RecoveryId::try_from(recovery_param) {
+        Ok(id) => id,
+        Err(_) => {
+            return ContractErr {
+                msg: "invalid secp256k1 recovery param",
+            }
+            .fail()
+        }
+    };
+    match VerifyingKey::recover_from_prehash(message_hash, &signature, recovery_id) {
+        Ok(key) => Ok(key.to_encoded_point(false).as_bytes().to_vec()),
+        Err(_) => ContractErr {
+            msg: "secp256k1 public key could not be recovered",
+        }
+        .fail(),
+    }
+}
+
+/// Verifies an ed25519 signature of message.
+/// Validation follows ZIP 215, so all nodes agree on the result for any input.
+pub fn ed25519_verify(message: &[u8], signature: &[u8], public_key: &[u8]) -> Result<bool> {
+    let signature = parse_ed25519_signature(signature)?;
+    let key = match ed25519_zebra::VerificationKey::try_from(public_key) {
+        Ok(key) => key,
+        // correctly sized, but not a valid point
+        Err(_) if public_key.len() == ED25519_PUBKEY_LENGTH => return Ok(false),
+        Err(_) => {
+            return ContractErr {
+                msg: "invalid ed25519 public key",
+            }
+            .fail()
+        }
+    };
+    Ok(key.verify(&signature, message).is_ok())
+}
+
+/// Verifies many ed25519 signatures at once, which is faster than verifying them one by one.
+/// Returns Ok(true) only if all signatures are valid.
+///
+/// All lists must have the same length. Two shortcuts are supported: a single message
+/// signed by many keys, and many messages signed by a single key.
+pub fn ed25519_batch_verify(
+    messages: &[&[u8]],
+    signatures: &[&[u8]],
+    public_keys: &[&[u8]],
+) -> Result<bool> {
+    let count = signatures.len();
+    let messages_ok = messages.len() == count || messages.len() == 1;
+    let keys_ok = public_keys.len() == count || public_keys.len() == 1;
+    if !messages_ok || !keys_ok || (messages.len() == 1 && public_keys.len() == 1 && count != 1) {
+        return ContractErr {
+            msg: "ed25519 batch lists have mismatching lengths",
+        }
+        .fail();
+    }
+
+    let mut verifier = batch::Verifier::new();
+    for (i, signature) in signatures.iter().enumerate() {
+        let message = if messages.len() == 1 {
+            messages[0]
+        } else {
+            messages[i]
+        };
+        let public_key = if public_keys.len() == 1 {
+            public_keys[0]
+        } else {
+            public_keys[i]
+        };
+        let signature = parse_ed25519_signature(signature)?;
+        let key = match ed25519_zebra::VerificationKeyBytes::try_from(public_key) {
+            Ok(key) => key,
+            Err(_) => {
+                return ContractErr {
+                    msg: "invalid ed25519 public key",
+                }
+                .fail()
+            }
+        };
+        verifier.queue((key, signature, &message));
+    }
+    // the rng only randomizes the combined check, the result is deterministic
+    Ok(verifier.verify(OsRng).is_ok())
+}
+
+fn check_message_hash(message_hash: &[u8]) -> Result<()> {
+    if message_hash.len() != MESSAGE_HASH_LENGTH {
+        return ContractErr {
+            msg: "message hash must be 32 bytes",
+        }
+        .fail();
+    }
+    Ok(())
+}
+
+fn parse_secp256k1_signature(signature: &[u8]) -> Result<Secp256k1Signature> {
+    if signature.len() != SECP256K1_SIGNATURE_LENGTH {
+        return ContractErr {
+            msg: "secp256k1 signature must be 64 bytes",
+        }
+        .fail();
+    }
+    match Secp256k1Signature::from_slice(signature) {
+        Ok(signature) => Ok(signature),
+        Err(_) => ContractErr {
+            msg: "invalid secp256k1 signature",
+        }
+        .fail(),
+    }
+}
+
+fn parse_ed25519_signature(signature: &[u8]) -> Result<ed25519_zebra::Signature> {
+    match ed25519_zebra::Signature::from_slice(signature) {
+        Ok(signature) => Ok(signature),
+        Err(_) => ContractErr {
+            msg: "ed25519 signature must be 64 bytes",
+        }
+        .fail(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ed25519_zebra::SigningKey;
+    use k256::ecdsa::SigningKey as Secp256k1SigningKey;
+    use k256::sha2::{Digest, Sha256};
+
+    fn secp256k1_fixture() -> (Vec<u8>, Vec<u8>, Vec<u8>, u8) {
+        let key = Secp256k1SigningKey::from_slice(&[7u8; 32]).unwrap();
+        let hash = Sha256::digest(b"hello world").to_vec();
+        let (signature, recovery_id) = key.sign_prehash_recoverable(&hash).unwrap();
+        let public_key = key
+            .verifying_key()
+            .to_encoded_point(false)
+            .as_bytes()
+            .to_vec();
+        (
+            hash,
+            signature.to_bytes().to_vec(),
+            public_key,
+            recovery_id.to_byte(),
+        )
+    }
+
+    #[test]
+    fn secp256k1_verify_works() {
+        let (hash, signature, public_key, _) = secp256k1_fixture();
+        assert!(secp256k1_verify(&hash, &signature, &public_key).unwrap());
+
+        let other = Sha256::digest(b"goodbye").to_vec();
+        assert!(!secp256k1_verify(&other, &signature, &public_key).unwrap());
+
+        // malformed input is an error
+        assert!(secp256k1_verify(&hash[1..], &signature, &public_key).is_err());
+        assert!(secp256k1_verify(&hash, &signature[1..], &public_key).is_err());
+        assert!(secp256k1_verify(&hash, &signature, &public_key[1..]).is_err());
+    }
+
+    #[test]
+    fn secp256k1_recover_pubkey_works() {
+        let (hash, signature, public_key, recovery_param) = secp256k1_fixture();
+        let recovered = secp256k1_recover_pubkey(&hash, &signature, recovery_param).unwrap();
+        assert_eq!(recovered, public_key);
+
+        assert!(secp256k1_recover_pubkey(&hash, &signature, 4).is_err());
+    }
+
+    fn as_refs(list: &[Vec<u8>]) -> Vec<&[u8]> {
+        list.iter().map(|v| v.as_slice()).collect()
+    }
+
+    #[test]
+    fn ed25519_verify_and_batch_verify_work() {
+        let keys: Vec<SigningKey> = (1u8..4).map(|i| SigningKey::from([i; 32])).collect();
+        let public_keys: Vec<Vec<u8>> = keys
+            .iter()
+            .map(|k| <[u8; 32]>::from(ed25519_zebra::VerificationKey::from(k)).to_vec())
+            .collect();
+        let messages: Vec<Vec<u8>> = (0u8..3).map(|i| vec![i; 10]).collect();
+        let signatures: Vec<Vec<u8>> = keys
+            .iter()
+            .zip(messages.iter())
+            .map(|(k, m)| k.sign(m).to_bytes().to_vec())
+            .collect();
+
+        assert!(ed25519_verify(&messages[0], &signatures[0], &public_keys[0]).unwrap());
+        assert!(!ed25519_verify(&messages[1], &signatures[0], &public_keys[0]).unwrap());
+        assert!(ed25519_verify(&messages[0], &signatures[0][1..], &public_keys[0]).is_err());
+
+        let (m, s, p) = (
+            as_refs(&messages),
+            as_refs(&signatures),
+            as_refs(&public_keys),
+        );
+        assert!(ed25519_batch_verify(&m, &s, &p).unwrap());
+
+        let mut swapped = s.clone();
+        swapped.swap(0, 1);
+        assert!(!ed25519_batch_verify(&m, &swapped, &p).unwrap());
+
+        assert!(ed25519_batch_verify(&m[..2], &s, &p).is_err());
+
+        // one message signed by all keys
+        let shared: Vec<Vec<u8>> = keys
+            .iter()
+            .map(|k| k.sign(&messages[0]).to_bytes().to_vec())
+            .collect();
+        assert!(ed25519_batch_verify(&m[..1], &as_refs(&shared), &p).unwrap());
+    }
+}
@@ -4,7 +4,7 @@ use schemars::JsonSchema;
 use serde::{de, ser, Deserialize, Deserializer, Serialize};
 use snafu::ResultExt;
 
-use crate::errors::{Base64Err, Result};
+use crate::errors::{Base64Err, ContractErr, Result};
 
 /// Binary is a wrapper around Vec<u8> to add base64 de/serialization
 /// with serde. It also adds some helper methods to help encode inline.
@@ -88,6 +88,45 @@ impl<'de> de::Visitor<'de> for Base64Visitor {
     }
 }
 
+/// Encodes a list of byte slices into one buffer, each prefixed with its length
+/// as 4 byte big endian integer. This is used to pass lists across the wasm boundary.
+pub fn encode_sections(sections: &[&[u8]]) -> Vec<u8> {
+    let total: usize = sections.iter().map(|s| s.len() + 4).sum();
+    let mut out = Vec::with_capacity(total);
+    for section in sections {
+        out.extend_from_slice(&(section.len() as u32).to_be_bytes());
+        out.extend_from_slice(section);
+    }
+    out
+}
+
+/// Reverses encode_sections
+pub fn decode_sections(data: &[u8]) -> Result<Vec<&[u8]>> {
+    let mut sections = Vec::new();
+    let mut rest = data;
+    while !rest.is_empty() {
+        if rest.len() < 4 {
+            return ContractErr {
+                msg: "truncated section length",
+            }
+            .fail();
+        }
+        let mut len = [0u8; 4];
+        len.copy_from_slice(&rest[..4]);
+        let len = u32::from_be_bytes(len) as usize;
+        rest = &rest[4..];
+        if rest.len() < len {
+            return ContractErr {
+                msg: "truncated section",
+            }
+            .fail();
+        }
+        sections.push(&rest[..len]);
+        rest = &rest[len..];
+    }
+    Ok(sections)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -163,4 +202,16 @@ mod test {
         let res = from_slice::<Binary>(&serialized);
         assert!(res.is_err());
     }
+
+    #[test]
+    fn encode_decode_sections() {
+        let sections: Vec<&[u8]> = vec![b"foo", b"", &[0u8; 300]];
+        let encoded = encode_sections(&sections);
+        assert_eq!(&encoded[..7], &[0, 0, 0, 3, 102, 111, 111]);
+        assert_eq!(decode_sections(&encoded).unwrap(), sections);
+
+        assert_eq!(decode_sections(&[]).unwrap(), Vec::<&[u8]>::new());
+        assert!(decode_sections(&encoded[..encoded.len() - 1]).is_err());
+        assert!(decode_sections(&[0, 0, 1]).is_err());
+    }
 }
@@ -65,6 +65,12 @@ pub enum Error {
         #[cfg(feature = "backtraces")]
         backtrace: snafu::Backtrace,
     },
+    #[snafu(display("{} is not supported", kind))]
+    Unsupported {
+        kind: &'static str,
+        #[cfg(feature = "backtraces")]
+        backtrace: snafu::Backtrace,
+    },
     #[snafu(display("Invalid {}: {}", field, msg))]
     ValidationErr {
         field: &'static str,
@@ -92,6 +98,10 @@ pub fn unauthorized<T>() -> Result<T> {
     Unauthorized {}.fail()
 }
 
+pub fn unsupported<T>(kind: &'static str) -> Result<T> {
+    Unsupported { kind }.fail()
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -135,4 +145,14 @@ mod test {
             Ok(_) => panic!("dyn_contract_err must return error"),
         }
     }
+
+    #[test]
+    fn unsupported_helper() {
+        let e: Result<()> = unsupported("random");
+        match e {
+            Err(Error::Unsupported { kind, .. }) => assert_eq!(kind, "random"),
+            Err(e) => panic!("unexpected error, {:?}", e),
+            Ok(_) => panic!("unsupported must return error"),
+        }
+    }
 }
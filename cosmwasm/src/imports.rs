@@ -3,11 +3,11 @@
 use std::ffi::c_void;
 use std::vec::Vec;
 
-use crate::encoding::Binary;
-use crate::errors::{ContractErr, Result};
+use crate::encoding::{encode_sections, Binary};
+use crate::errors::{unsupported, ContractErr, Result};
 use crate::memory::{alloc, build_region, consume_region, Region};
 use crate::traits::{Api, Extern, ReadonlyStorage, Storage};
-use crate::types::{CanonicalAddr, HumanAddr};
+use crate::types::{CanonicalAddr, GasInfo, HumanAddr};
 
 // this is the buffer we pre-allocate in get - we should configure this somehow later
 static MAX_READ: usize = 2000;
@@ -15,6 +15,9 @@ static MAX_READ: usize = 2000;
 // this should be plenty for any address representation
 static ADDR_BUFFER: usize = 72;
 
+// an uncompressed secp256k1 public key
+static PUBKEY_BUFFER: usize = 65;
+
 // This interface will compile into required Wasm imports.
 // A complete documentation those functions is available in the VM that provides them:
 // https://github.com/confio/cosmwasm/blob/0.7/lib/vm/src/instance.rs#L43
@@ -26,6 +29,32 @@ extern "C" {
     fn write_db(key: *const c_void, value: *mut c_void);
     fn canonicalize_address(human: *const c_void, canonical: *mut c_void) -> i32;
     fn humanize_address(canonical: *const c_void, human: *mut c_void) -> i32;
+
+    fn secp256k1_verify(
+        message_hash: *const c_void,
+        signature: *const c_void,
+        public_key: *const c_void,
+    ) -> i32;
+    fn secp256k1_recover_pubkey(
+        message_hash: *const c_void,
+        signature: *const c_void,
+        recovery_param: u32,
+        public_key: *mut c_void,
+    ) -> i32;
+    fn ed25519_verify(
+        message: *const c_void,
+        signature: *const c_void,
+        public_key: *const c_void,
+    ) -> i32;
+    fn ed25519_batch_verify(
+        messages: *const c_void,
+        signatures: *const c_void,
+        public_keys: *const c_void,
+    ) -> i32;
+
+    fn debug_print(message: *const c_void);
+    fn random(buffer: *mut c_void) -> i32;
+    fn check_gas(result: *mut c_void) -> i32;
 }
 
 // dependencies are all external requirements that can be injected in a real-wasm contract
@@ -128,4 +157,138 @@ impl Api for ExternalApi {
         let result = unsafe { String::from_utf8_unchecked(out) };
         Ok(HumanAddr(result))
     }
+
+    fn secp256k1_verify(
+        &self,
+        message_hash: &[u8],
+        signature: &[u8],
+        public_key: &[u8],
+    ) -> Result<bool> {
+        let hash = build_region(message_hash);
+        let hash_ptr = &*hash as *const Region as *const c_void;
+        let sig = build_region(signature);
+        let sig_ptr = &*sig as *const Region as *const c_void;
+        let key = build_region(public_key);
+        let key_ptr = &*key as *const Region as *const c_void;
+
+        let result = unsafe { secp256k1_verify(hash_ptr, sig_ptr, key_ptr) };
+        verification_result(
+            result,
+            "secp256k1_verify",
+            "secp256k1_verify returned error",
+        )
+    }
+
+    fn secp256k1_recover_pubkey(
+        &self,
+        message_hash: &[u8],
+        signature: &[u8],
+        recovery_param: u8,
+    ) -> Result<Vec<u8>> {
+        let hash = build_region(message_hash);
+        let hash_ptr = &*hash as *const Region as *const c_void;
+        let sig = build_region(signature);
+        let sig_ptr = &*sig as *const Region as *const c_void;
+        let key = alloc(PUBKEY_BUFFER);
+
+        let read =
+            unsafe { secp256k1_recover_pubkey(hash_ptr, sig_ptr, recovery_param.into(), key) };
+        if read == -2 {
+            return unsupported("secp256k1_recover_pubkey");
+        }
+        if read < 0 {
+            return ContractErr {
+                msg: "secp256k1_recover_pubkey returned error",
+            }
+            .fail();
+        }
+
+        let mut out = unsafe { consume_region(key)? };
+        out.truncate(read as usize);
+        Ok(out)
+    }
+
+    fn ed25519_verify(&self, message: &[u8], signature: &[u8], public_key: &[u8]) -> Result<bool> {
+        let msg = build_region(message);
+        let msg_ptr = &*msg as *const Region as *const c_void;
+        let sig = build_region(signature);
+        let sig_ptr = &*sig as *const Region as *const c_void;
+        let key = build_region(public_key);
+        let key_ptr = &*key as *const Region as *const c_void;
+
+        let result = unsafe { ed25519_verify(msg_ptr, sig_ptr, key_ptr) };
+        verification_result(result, "ed25519_verify", "ed25519_verify returned error")
+    }
+
+    fn ed25519_batch_verify(
+        &self,
+        messages: &[&[u8]],
+        signatures: &[&[u8]],
+        public_keys: &[&[u8]],
+    ) -> Result<bool> {
+        let msgs = build_region(&encode_sections(messages));
+        let msgs_ptr = &*msgs as *const Region as *const c_void;
+        let sigs = build_region(&encode_sections(signatures));
+        let sigs_ptr = &*sigs as *const Region as *const c_void;
+        let keys = build_region(&encode_sections(public_keys));
+        let keys_ptr = &*keys as *const Region as *const c_void;
+
+        let result = unsafe { ed25519_batch_verify(msgs_ptr, sigs_ptr, keys_ptr) };
+        verification_result(
+            result,
+            "ed25519_batch_verify",
+            "ed25519_batch_verify returned error",
+        )
+    }
+
+    fn debug(&self, message: &str) {
+        let msg = build_region(message.as_bytes());
+        let msg_ptr = &*msg as *const Region as *const c_void;
+        unsafe { debug_print(msg_ptr) };
+    }
+
+    fn random(&self, len: usize) -> Result<Vec<u8>> {
+        let buffer = alloc(len);
+        let written = unsafe { random(buffer) };
+        if written < 0 {
+            return ContractErr {
+                msg: "random returned error",
+            }
+            .fail();
+        }
+        let mut out = unsafe { consume_region(buffer)? };
+        out.truncate(written as usize);
+        Ok(out)
+    }
+
+    fn check_gas(&self) -> Result<GasInfo> {
+        let result = alloc(16);
+        let written = unsafe { check_gas(result) };
+        let data = unsafe { consume_region(result)? };
+        if written != 16 {
+            return ContractErr {
+                msg: "check_gas returned error",
+            }
+            .fail();
+        }
+        let mut gas_left = [0u8; 8];
+        let mut progress = [0u8; 8];
+        gas_left.copy_from_slice(&data[..8]);
+        progress.copy_from_slice(&data[8..16]);
+        Ok(GasInfo {
+            gas_left: u64::from_be_bytes(gas_left),
+            progress: u64::from_be_bytes(progress),
+        })
+    }
+}
+
+// the verify imports return 0 for a valid signature, 1 for an invalid one and negative values on error
+// -2 is returned by hosts that do not implement the scheme
+fn verification_result(result: i32, kind: &'static str, error: &'static str) -> Result<bool> {
+    match result {
+        0 => Ok(true),
+        1 => Ok(false),
+        -2 => unsupported(kind),
+        _ => ContractErr { msg: error }.fail(),
+    }
 }
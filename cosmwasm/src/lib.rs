@@ -2,6 +2,8 @@
 pub mod exports;
 pub mod imports;
 
+#[cfg(feature = "crypto")]
+pub mod crypto;
 pub mod encoding;
 pub mod errors;
 pub mod memory;
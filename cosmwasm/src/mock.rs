@@ -1,11 +1,18 @@
-use std::collections::HashMap;
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::time::Duration;
 
 use snafu::ResultExt;
 
+#[cfg(all(feature = "crypto", not(target_arch = "wasm32")))]
+use crate::crypto;
 use crate::encoding::Binary;
 use crate::errors::{ContractErr, Result, Utf8StringErr};
 use crate::traits::{Api, Extern, ReadonlyStorage, Storage};
-use crate::types::{BlockInfo, CanonicalAddr, Coin, ContractInfo, Env, HumanAddr, MessageInfo};
+use crate::types::{
+    BlockInfo, CanonicalAddr, Coin, ContractInfo, Env, GasInfo, HumanAddr, MessageInfo,
+    BLOCK_ENTROPY_LENGTH,
+};
 
 // dependencies are all external requirements that can be injected for unit tests
 pub fn dependencies(canonical_length: usize) -> Extern<MockStorage, MockApi> {
@@ -15,17 +22,154 @@ pub fn dependencies(canonical_length: usize) -> Extern<MockStorage, MockApi> {
     }
 }
 
+/// Gas MockStorage counts for every operation, to estimate storage costs in unit tests
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MockStorageCosts {
+    pub read_base: u64,
+    pub read_per_byte: u64,
+    pub write_base: u64,
+    pub write_per_byte: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Order {
+    Ascending,
+    Descending,
+}
+
+/// A key that differs between two states of MockStorage, see MockStorage::diff
+#[derive(Clone, Debug, PartialEq)]
+pub struct StorageChange {
+    pub key: Vec<u8>,
+    pub old: Option<Vec<u8>>,
+    pub new: Option<Vec<u8>>,
+}
+
 #[derive(Clone)]
 pub struct MockStorage {
-    data: HashMap<Vec<u8>, Vec<u8>>,
+    // ordered by key like the chain's IAVL store, so range is deterministic
+    data: BTreeMap<Vec<u8>, Vec<u8>>,
+    costs: MockStorageCosts,
+    gas_used: Cell<u64>,
+    reads: Cell<u64>,
+    latency: Option<Duration>,
+    // failure injection. Storage has no way to report errors, so failures are panics,
+    // which is what a broken backend does to a contract.
+    panic_on_read: Option<u64>,
+    panic_on_key: Option<Vec<u8>>,
 }
 
 impl MockStorage {
     pub fn new() -> Self {
         MockStorage {
-            data: HashMap::new(),
+            data: BTreeMap::new(),
+            costs: MockStorageCosts::default(),
+            gas_used: Cell::new(0),
+            reads: Cell::new(0),
+            latency: None,
+            panic_on_read: None,
+            panic_on_key: None,
         }
     }
+
+    /// Counts gas for all following operations, see gas_used
+    pub fn with_costs(mut self, costs: MockStorageCosts) -> Self {
+        self.costs = costs;
+        self
+    }
+
+    /// Sleeps for the given time on every operation, to simulate a slow backend
+    pub fn with_latency(mut self, latency: Duration) -> Self {
+        self.latency = Some(latency);
+        self
+    }
+
+    /// Panics on the nth read (counting from 1)
+    pub fn panic_on_read(mut self, n: u64) -> Self {
+        self.panic_on_read = Some(n);
+        self
+    }
+
+    /// Panics on any read or write of key
+    pub fn panic_on_key(mut self, key: &[u8]) -> Self {
+        self.panic_on_key = Some(key.to_vec());
+        self
+    }
+
+    pub fn gas_used(&self) -> u64 {
+        self.gas_used.get()
+    }
+
+    /// Number of reads so far
+    pub fn reads(&self) -> u64 {
+        self.reads.get()
+    }
+
+    /// Returns all pairs with start <= key < end in key order, like iterating
+    /// the chain store. None means unbounded.
+    pub fn range(
+        &self,
+        start: Option<&[u8]>,
+        end: Option<&[u8]>,
+        order: Order,
+    ) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let in_range = |key: &Vec<u8>| {
+            start.map(|s| key.as_slice() >= s).unwrap_or(true)
+                && end.map(|e| key.as_slice() < e).unwrap_or(true)
+        };
+        let pairs = self
+            .data
+            .iter()
+            .filter(|(k, _)| in_range(k))
+            .map(|(k, v)| (k.clone(), v.clone()));
+        match order {
+            Order::Ascending => pairs.collect(),
+            Order::Descending => pairs.rev().collect(),
+        }
+    }
+
+    /// Lists the keys that differ between self and an earlier snapshot (a clone),
+    /// in key order with the old and the new value
+    pub fn diff(&self, snapshot: &MockStorage) -> Vec<StorageChange> {
+        let mut changes = Vec::new();
+        for (key, old) in snapshot.data.iter() {
+            let new = self.data.get(key);
+            if new != Some(old) {
+                changes.push(StorageChange {
+                    key: key.clone(),
+                    old: Some(old.clone()),
+                    new: new.cloned(),
+                });
+            }
+        }
+        for (key, new) in self.data.iter() {
+            if !snapshot.data.contains_key(key) {
+                changes.push(StorageChange {
+                    key: key.clone(),
+                    old: None,
+                    new: Some(new.clone()),
+                });
+            }
+        }
+        changes.sort_by(|a, b| a.key.cmp(&b.key));
+        changes
+    }
+
+    fn before_access(&self, key: &[u8]) {
+        if let Some(latency) = self.latency {
+            std::thread::sleep(latency);
+        }
+        if let Some(panic_key) = &self.panic_on_key {
+            if panic_key.as_slice() == key {
+                panic!("MockStorage: injected failure accessing key {:?}", key);
+            }
+        }
+    }
+
+    fn charge(&self, amount: u64) {
+        self.gas_used
+            .set(self.gas_used.get().saturating_add(amount));
+    }
 }
 
 impl Default for MockStorage {
@@ -36,12 +180,24 @@ impl Default for MockStorage {
 
 impl ReadonlyStorage for MockStorage {
     fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
-        self.data.get(key).cloned()
+        self.before_access(key);
+        let reads = self.reads.get() + 1;
+        self.reads.set(reads);
+        if self.panic_on_read == Some(reads) {
+            panic!("MockStorage: injected failure on read {}", reads);
+        }
+        let value = self.data.get(key).cloned();
+        let len = key.len() + value.as_ref().map(|v| v.len()).unwrap_or(0);
+        self.charge(self.costs.read_base + self.costs.read_per_byte * len as u64);
+        value
     }
 }
 
 impl Storage for MockStorage {
     fn set(&mut self, key: &[u8], value: &[u8]) {
+        self.before_access(key);
+        let len = key.len() + value.len();
+        self.charge(self.costs.write_base + self.costs.write_per_byte * len as u64);
         self.data.insert(key.to_vec(), value.to_vec());
     }
 }
@@ -94,6 +250,60 @@ impl Api for MockApi {
         let human = String::from_utf8(trimmed).context(Utf8StringErr {})?;
         Ok(HumanAddr(human))
     }
+
+    // without the crypto feature MockApi fails with Unsupported, like any host that
+    // does not offer signature verification
+    #[cfg(all(feature = "crypto", not(target_arch = "wasm32")))]
+    fn secp256k1_verify(
+        &self,
+        message_hash: &[u8],
+        signature: &[u8],
+        public_key: &[u8],
+    ) -> Result<bool> {
+        crypto::secp256k1_verify(message_hash, signature, public_key)
+    }
+
+    #[cfg(all(feature = "crypto", not(target_arch = "wasm32")))]
+    fn secp256k1_recover_pubkey(
+        &self,
+        message_hash: &[u8],
+        signature: &[u8],
+        recovery_param: u8,
+    ) -> Result<Vec<u8>> {
+        crypto::secp256k1_recover_pubkey(message_hash, signature, recovery_param)
+    }
+
+    #[cfg(all(feature = "crypto", not(target_arch = "wasm32")))]
+    fn ed25519_verify(&self, message: &[u8], signature: &[u8], public_key: &[u8]) -> Result<bool> {
+        crypto::ed25519_verify(message, signature, public_key)
+    }
+
+    #[cfg(all(feature = "crypto", not(target_arch = "wasm32")))]
+    fn ed25519_batch_verify(
+        &self,
+        messages: &[&[u8]],
+        signatures: &[&[u8]],
+        public_keys: &[&[u8]],
+    ) -> Result<bool> {
+        crypto::ed25519_batch_verify(messages, signatures, public_keys)
+    }
+
+    fn debug(&self, message: &str) {
+        println!("{}", message);
+    }
+
+    // a fixed pattern, so tests can predict it
+    fn random(&self, len: usize) -> Result<Vec<u8>> {
+        Ok((0..len).map(|i| i as u8).collect())
+    }
+
+    // the mocks do not meter gas, so this is a fixed budget
+    fn check_gas(&self) -> Result<GasInfo> {
+        Ok(GasInfo {
+            gas_left: 1_000_000_000,
+            progress: 0,
+        })
+    }
 }
 
 // just set signer, sent funds, and balance - rest given defaults
@@ -110,6 +320,7 @@ pub fn mock_env<T: Api, U: Into<HumanAddr>>(
             height: 12_345,
             time: 1_571_797_419,
             chain_id: "cosmos-testnet-14002".to_string(),
+            entropy: None,
         },
         message: MessageInfo {
             signer: api.canonical_address(&signer).unwrap(),
@@ -132,6 +343,105 @@ pub fn mock_env<T: Api, U: Into<HumanAddr>>(
     }
 }
 
+// mock_entropy deterministically expands a seed into block entropy of the valid length,
+// so tests can set env.block.entropy without hardcoding 32 byte values
+pub fn mock_entropy(seed: &[u8]) -> Binary {
+    let mut out = Vec::with_capacity(BLOCK_ENTROPY_LENGTH);
+    for i in 0..BLOCK_ENTROPY_LENGTH {
+        let byte = if seed.is_empty() {
+            0
+        } else {
+            seed[i % seed.len()]
+        };
+        out.push(byte.wrapping_add(i as u8));
+    }
+    Binary(out)
+}
+
+/// Environment variable that fixes the seed of TestRng::from_env
+pub static TEST_SEED_VAR: &str = "COSMWASM_TEST_SEED";
+
+/// TestRng is a small deterministic pseudo random generator (splitmix64) for randomized tests.
+/// Create it with from_env at the start of a test: it prints the seed, and running the test
+/// again with COSMWASM_TEST_SEED set to that value reproduces the exact same inputs.
+/// This is not suitable for anything but tests.
+#[derive(Debug, Clone)]
+pub struct TestRng {
+    seed: u64,
+    state: u64,
+}
+
+impl TestRng {
+    pub fn new(seed: u64) -> Self {
+        TestRng { seed, state: seed }
+    }
+
+    /// Uses the seed from COSMWASM_TEST_SEED or a fresh one, and prints it
+    pub fn from_env() -> Self {
+        let seed = match std::env::var(TEST_SEED_VAR) {
+            Ok(value) => value
+                .parse()
+                .unwrap_or_else(|_| panic!("{} must be a u64, got {}", TEST_SEED_VAR, value)),
+            Err(_) => std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_nanos() as u64)
+                .unwrap_or(0),
+        };
+        eprintln!(
+            "TestRng seed: {} (set {} to reproduce)",
+            seed, TEST_SEED_VAR
+        );
+        TestRng::new(seed)
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a value in low..high (high exclusive)
+    pub fn range(&mut self, low: u64, high: u64) -> u64 {
+        assert!(low < high, "empty range");
+        low + self.next_u64() % (high - low)
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Vec<u8> {
+        (0..len).map(|_| self.next_u64() as u8).collect()
+    }
+
+    /// Returns a lowercase alphanumeric address of len characters, which MockApi accepts
+    /// as long as len does not exceed its canonical length
+    pub fn human_address(&mut self, len: usize) -> HumanAddr {
+        static CHARS: &[u8] = b"abcdefghijklmnopqrstuvwxyz0123456789";
+        let addr = (0..len)
+            .map(|_| CHARS[self.range(0, CHARS.len() as u64) as usize] as char)
+            .collect();
+        HumanAddr(addr)
+    }
+}
+
+// like mock_env, but with random block height, time and entropy
+pub fn mock_env_with_rng<T: Api, U: Into<HumanAddr>>(
+    api: &T,
+    rng: &mut TestRng,
+    signer: U,
+    sent: &[Coin],
+    balance: &[Coin],
+) -> Env {
+    let mut env = mock_env(api, signer, sent, balance);
+    env.block.height = rng.range(1, 100_000_000) as i64;
+    env.block.time = rng.range(1_500_000_000, 2_000_000_000) as i64;
+    env.block.entropy = Some(Binary(rng.bytes(BLOCK_ENTROPY_LENGTH)));
+    env
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -153,6 +463,41 @@ mod test {
         assert_eq!(a, c);
     }
 
+    #[test]
+    fn test_rng_is_reproducible() {
+        let mut rng = TestRng::from_env();
+        let mut replay = TestRng::new(rng.seed());
+        assert_eq!(rng.bytes(20), replay.bytes(20));
+        assert_eq!(rng.next_u64(), replay.next_u64());
+
+        for _ in 0..100 {
+            let value = rng.range(10, 20);
+            assert!((10..20).contains(&value));
+        }
+
+        // random addresses and envs work with the mocks
+        let api = MockApi::new(20);
+        let addr = rng.human_address(12);
+        assert_eq!(addr.len(), 12);
+        let canonical = api.canonical_address(&addr).unwrap();
+        assert_eq!(api.human_address(&canonical).unwrap(), addr);
+
+        let env = mock_env_with_rng(&api, &mut rng, addr, &[], &[]);
+        assert_eq!(env.block.entropy.unwrap().len(), BLOCK_ENTROPY_LENGTH);
+
+        // different seeds give different values
+        assert_ne!(TestRng::new(1).next_u64(), TestRng::new(2).next_u64());
+    }
+
+    #[test]
+    fn mock_entropy_is_deterministic() {
+        let a = mock_entropy(b"seed");
+        assert_eq!(a.len(), BLOCK_ENTROPY_LENGTH);
+        assert_eq!(a, mock_entropy(b"seed"));
+        assert_ne!(a, mock_entropy(b"other seed"));
+        assert_eq!(mock_entropy(b"").len(), BLOCK_ENTROPY_LENGTH);
+    }
+
     #[test]
     fn get_and_set() {
         let mut store = MockStorage::new();
@@ -162,6 +507,92 @@ mod test {
         assert_eq!(None, store.get(b"food"));
     }
 
+    #[test]
+    fn storage_counts_gas() {
+        let costs = MockStorageCosts {
+            read_base: 10,
+            read_per_byte: 1,
+            write_base: 20,
+            write_per_byte: 2,
+        };
+        let mut store = MockStorage::new().with_costs(costs);
+        store.set(b"foo", b"bar");
+        assert_eq!(store.gas_used(), 32);
+        assert_eq!(store.get(b"foo"), Some(b"bar".to_vec()));
+        assert_eq!(store.get(b"food"), None);
+        assert_eq!(store.gas_used(), 32 + 16 + 14);
+        assert_eq!(store.reads(), 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "injected failure on read 2")]
+    fn storage_panics_on_nth_read() {
+        let mut store = MockStorage::new().panic_on_read(2);
+        store.set(b"foo", b"bar");
+        assert_eq!(store.get(b"foo"), Some(b"bar".to_vec()));
+        store.get(b"foo");
+    }
+
+    #[test]
+    #[should_panic(expected = "injected failure accessing key")]
+    fn storage_panics_on_key() {
+        let mut store = MockStorage::new().panic_on_key(b"bad");
+        store.set(b"good", b"bar");
+        store.set(b"bad", b"bar");
+    }
+
+    #[test]
+    fn storage_range_is_ordered() {
+        let mut store = MockStorage::new();
+        store.set(b"b", b"2");
+        store.set(b"c", b"3");
+        store.set(b"a", b"1");
+
+        let all = store.range(None, None, Order::Ascending);
+        let keys: Vec<&[u8]> = all.iter().map(|(k, _)| k.as_slice()).collect();
+        assert_eq!(keys, vec![b"a", b"b", b"c"]);
+
+        // end is exclusive
+        let part = store.range(Some(b"b"), Some(b"c"), Order::Ascending);
+        assert_eq!(part, vec![(b"b".to_vec(), b"2".to_vec())]);
+
+        let desc = store.range(Some(b"b"), None, Order::Descending);
+        assert_eq!(
+            desc,
+            vec![
+                (b"c".to_vec(), b"3".to_vec()),
+                (b"b".to_vec(), b"2".to_vec())
+            ]
+        );
+    }
+
+    #[test]
+    fn storage_diff_lists_changes() {
+        let mut store = MockStorage::new();
+        store.set(b"same", b"1");
+        store.set(b"changed", b"1");
+        let snapshot = store.clone();
+        assert_eq!(store.diff(&snapshot), vec![]);
+
+        store.set(b"changed", b"2");
+        store.set(b"added", b"3");
+        assert_eq!(
+            store.diff(&snapshot),
+            vec![
+                StorageChange {
+                    key: b"added".to_vec(),
+                    old: None,
+                    new: Some(b"3".to_vec()),
+                },
+                StorageChange {
+                    key: b"changed".to_vec(),
+                    old: Some(b"1".to_vec()),
+                    new: Some(b"2".to_vec()),
+                },
+            ]
+        );
+    }
+
     #[test]
     fn flip_addresses() {
         let api = MockApi::new(20);
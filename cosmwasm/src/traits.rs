@@ -1,7 +1,7 @@
 use std::vec::Vec;
 
-use crate::errors::Result;
-use crate::types::{CanonicalAddr, HumanAddr};
+use crate::errors::{unsupported, Result};
+use crate::types::{CanonicalAddr, GasInfo, HumanAddr};
 
 // Extern holds all external dependencies of the contract,
 // designed to allow easy dependency injection at runtime
@@ -15,6 +15,12 @@ pub struct Extern<S: Storage, A: Api> {
 //pub trait ReadonlyStorage: Clone {
 pub trait ReadonlyStorage {
     fn get(&self, key: &[u8]) -> Option<Vec<u8>>;
+
+    // get_many returns the values of all keys, in order. Storages behind a slow
+    // connection can override it to fetch all keys in one round trip.
+    fn get_many(&self, keys: &[Vec<u8>]) -> Vec<Option<Vec<u8>>> {
+        keys.iter().map(|key| self.get(key)).collect()
+    }
 }
 
 // Storage extends ReadonlyStorage to give mutable access
@@ -25,7 +31,7 @@ pub trait Storage: ReadonlyStorage {
 // Api are callbacks to system functions defined outside of the wasm modules.
 // This is a trait to allow Mocks in the test code.
 //
-// Currently it just supports address conversion, we could add eg. crypto functions here.
+// It supports address conversion and signature verification.
 // These should all be pure (stateless) functions. If you need state, you probably want
 // to use the Querier (TODO)
 //
@@ -34,4 +40,58 @@ pub trait Storage: ReadonlyStorage {
 pub trait Api: Copy + Clone + Send {
     fn canonical_address(&self, human: &HumanAddr) -> Result<CanonicalAddr>;
     fn human_address(&self, canonical: &CanonicalAddr) -> Result<HumanAddr>;
+
+    // The methods below were added later. Their defaults fail with Unsupported (debug does
+    // nothing), so implementations written against older versions keep compiling.
+
+    // Signature verification, see the crypto module for the supported formats.
+    // The verify functions return an error for malformed input and Ok(false) for wrong signatures.
+    fn secp256k1_verify(
+        &self,
+        _message_hash: &[u8],
+        _signature: &[u8],
+        _public_key: &[u8],
+    ) -> Result<bool> {
+        unsupported("secp256k1_verify")
+    }
+    fn secp256k1_recover_pubkey(
+        &self,
+        _message_hash: &[u8],
+        _signature: &[u8],
+        _recovery_param: u8,
+    ) -> Result<Vec<u8>> {
+        unsupported("secp256k1_recover_pubkey")
+    }
+    fn ed25519_verify(
+        &self,
+        _message: &[u8],
+        _signature: &[u8],
+        _public_key: &[u8],
+    ) -> Result<bool> {
+        unsupported("ed25519_verify")
+    }
+    fn ed25519_batch_verify(
+        &self,
+        _messages: &[&[u8]],
+        _signatures: &[&[u8]],
+        _public_keys: &[&[u8]],
+    ) -> Result<bool> {
+        unsupported("ed25519_batch_verify")
+    }
+
+    // Emits a message for debugging. Nodes may drop it (validators usually do),
+    // so nothing may depend on it being delivered.
+    fn debug(&self, _message: &str) {}
+
+    // Returns len bytes derived from the block entropy. They are the same on every node and only
+    // as unpredictable as the entropy of the block. Fails if the chain provides no entropy (eg. in queries).
+    fn random(&self, _len: usize) -> Result<Vec<u8>> {
+        unsupported("random")
+    }
+
+    // Returns the gas left and the progress of the current call. Use it instead of a clock
+    // to stop long running work in time, it is deterministic.
+    fn check_gas(&self) -> Result<GasInfo> {
+        unsupported("check_gas")
+    }
 }
@@ -72,8 +72,15 @@ pub struct BlockInfo {
     // time is seconds since epoch begin (Jan. 1, 1970)
     pub time: i64,
     pub chain_id: String,
+    // entropy is an optional, chain-provided random beacon value for this block (eg. from vote extensions).
+    // It is omitted from the json when unset, so contracts built before it was added can still parse the Env.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub entropy: Option<Binary>,
 }
 
+// the exact length the vm accepts for BlockInfo.entropy
+pub static BLOCK_ENTROPY_LENGTH: usize = 32;
+
 #[derive(Serialize, Deserialize, Clone, Default, Debug, PartialEq, JsonSchema)]
 pub struct MessageInfo {
     pub signer: CanonicalAddr,
@@ -88,6 +95,14 @@ pub struct ContractInfo {
     pub balance: Option<Vec<Coin>>,
 }
 
+/// GasInfo is returned by Api::check_gas
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+pub struct GasInfo {
+    pub gas_left: u64,
+    /// all gas used by the current call so far. It only grows and is the same on every node.
+    pub progress: u64,
+}
+
 #[derive(Serialize, Deserialize, Clone, Default, Debug, PartialEq, JsonSchema)]
 pub struct Coin {
     pub denom: String,
@@ -199,6 +214,31 @@ mod test {
     use super::*;
     use crate::serde::{from_slice, to_vec};
 
+    #[test]
+    fn block_entropy_is_optional() {
+        let block = BlockInfo {
+            height: 123,
+            time: 1_571_797_419,
+            chain_id: "foo".to_string(),
+            entropy: None,
+        };
+        let bin = to_vec(&block).expect("encode block info");
+        assert_eq!(
+            std::str::from_utf8(&bin).unwrap(),
+            r#"{"height":123,"time":1571797419,"chain_id":"foo"}"#
+        );
+        let back: BlockInfo = from_slice(&bin).expect("decode block info");
+        assert_eq!(block, back);
+
+        let block = BlockInfo {
+            entropy: Some(Binary(vec![7u8; BLOCK_ENTROPY_LENGTH])),
+            ..block
+        };
+        let bin = to_vec(&block).expect("encode block info");
+        let back: BlockInfo = from_slice(&bin).expect("decode block info");
+        assert_eq!(block, back);
+    }
+
     #[test]
     fn can_deser_error_result() {
         let fail = ContractResult::Err("foobar".to_string());
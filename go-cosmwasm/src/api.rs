@@ -5,7 +5,7 @@ use cosmwasm::errors::{ContractErr, Result, Utf8Err};
 use cosmwasm::traits::Api;
 use cosmwasm::types::{CanonicalAddr, HumanAddr};
 
-use crate::memory::Buffer;
+use crate::memory::{Buffer, OwnedBuffer};
 
 // this represents something passed in from the caller side of FFI
 // in this case a struct with go function pointers
@@ -39,32 +39,30 @@ impl Api for GoApi {
     fn canonical_address(&self, human: &HumanAddr) -> Result<CanonicalAddr> {
         let human = human.as_str().as_bytes();
         let input = Buffer::from_vec(human.to_vec());
-        let mut output = Buffer::from_vec(vec![0u8; MAX_ADDRESS_BYTES]);
-        let read = (self.vtable.canonicalize_address)(self.state, input, output);
+        let output = OwnedBuffer::new(vec![0u8; MAX_ADDRESS_BYTES]);
+        let read = (self.vtable.canonicalize_address)(self.state, input, output.raw());
         if read < 0 {
             return ContractErr {
                 msg: "human_address returned error",
             }
             .fail();
         }
-        output.len = read as usize;
-        let canon = unsafe { output.consume() };
+        let canon = output.into_vec(read as usize);
         Ok(CanonicalAddr(Binary(canon)))
     }
 
     fn human_address(&self, canonical: &CanonicalAddr) -> Result<HumanAddr> {
         let canonical = canonical.as_slice();
         let input = Buffer::from_vec(canonical.to_vec());
-        let mut output = Buffer::from_vec(vec![0u8; MAX_ADDRESS_BYTES]);
-        let read = (self.vtable.humanize_address)(self.state, input, output);
+        let output = OwnedBuffer::new(vec![0u8; MAX_ADDRESS_BYTES]);
+        let read = (self.vtable.humanize_address)(self.state, input, output.raw());
         if read < 0 {
             return ContractErr {
                 msg: "canonical_address returned error",
             }
             .fail();
         }
-        output.len = read as usize;
-        let result = unsafe { output.consume() };
+        let result = output.into_vec(read as usize);
         // TODO: let's change the Utf8Err definition in cosmwasm to avoid a copy
         //        let human = String::from_utf8(result).context(Utf8Err{})?;
         let human = std::str::from_utf8(&result)
@@ -1,6 +1,6 @@
 use cosmwasm::traits::{ReadonlyStorage, Storage};
 
-use crate::memory::Buffer;
+use crate::memory::{Buffer, OwnedBuffer};
 
 // this represents something passed in from the caller side of FFI
 #[repr(C)]
@@ -22,8 +22,8 @@ impl ReadonlyStorage for DB {
     fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
         let buf = Buffer::from_vec(key.to_vec());
         // TODO: dynamic size
-        let mut buf2 = Buffer::from_vec(vec![0u8; 2000]);
-        let res = (self.vtable.read_db)(self.state, buf, buf2);
+        let buf2 = OwnedBuffer::new(vec![0u8; 2000]);
+        let res = (self.vtable.read_db)(self.state, buf, buf2.raw());
 
         // read in the number of bytes returned
         if res < 0 {
@@ -33,8 +33,7 @@ impl ReadonlyStorage for DB {
         if res == 0 {
             return None;
         }
-        buf2.len = res as usize;
-        unsafe { Some(buf2.consume()) }
+        Some(buf2.into_vec(res as usize))
     }
 }
 
@@ -1,3 +1,14 @@
+//! The C API of the vm, as declared in api/bindings.h (generated by cbindgen on build).
+//!
+//! Ownership rules for every Buffer crossing the boundary:
+//! * Buffers returned by a function of this library belong to the caller, who must pass
+//!   them to free_rust exactly once (an empty buffer may be dropped).
+//! * Buffers passed into a function of this library stay owned by the caller and are only
+//!   read during the call.
+//! * Input buffers passed to the DB and GoApi callbacks belong to the callee, which frees
+//!   them with free_rust. Output buffers of the callbacks stay owned by this library, the
+//!   callee only writes into them.
+//! * A cache_t from init_cache is released with release_cache.
 mod api;
 mod db;
 mod error;
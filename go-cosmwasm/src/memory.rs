@@ -9,6 +9,7 @@ pub extern "C" fn free_rust(buf: Buffer) {
     }
 }
 
+/// Buffer is a byte slice passed over FFI. See the crate documentation for who frees it.
 #[derive(Copy, Clone)]
 #[repr(C)]
 pub struct Buffer {
@@ -71,3 +72,65 @@ impl Buffer {
         self.ptr.is_null() || self.len == 0 || self.cap == 0
     }
 }
+
+/// OwnedBuffer holds memory we lend to the caller for the duration of a call only,
+/// like the output buffers of the callbacks. It is freed when dropped, so early returns
+/// cannot leak it. Buffers that hand ownership to the caller still use Buffer::from_vec.
+pub struct OwnedBuffer(Buffer);
+
+impl OwnedBuffer {
+    pub fn new(v: Vec<u8>) -> Self {
+        OwnedBuffer(Buffer::from_vec(v))
+    }
+
+    /// raw returns the buffer to pass over FFI. The memory stays owned by self,
+    /// the caller must not free it.
+    pub fn raw(&self) -> Buffer {
+        self.0
+    }
+
+    /// into_vec returns the first len bytes (at most the original length) as a Vec
+    pub fn into_vec(self, len: usize) -> Vec<u8> {
+        let len = len.min(self.0.len);
+        if len == 0 {
+            // consume would leak the buffer, dropping self frees it
+            return Vec::new();
+        }
+        let mut buf = self.0;
+        mem::forget(self);
+        buf.len = len;
+        unsafe { buf.consume() }
+    }
+}
+
+impl Drop for OwnedBuffer {
+    fn drop(&mut self) {
+        // consume leaks buffers with len 0, so rebuild the Vec from the capacity alone
+        if !self.0.ptr.is_null() && self.0.cap > 0 {
+            unsafe {
+                let _ = Vec::from_raw_parts(self.0.ptr, 0, self.0.cap);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn into_vec_truncates() {
+        let buf = OwnedBuffer::new(b"canonical".to_vec());
+        assert_eq!(buf.into_vec(5), b"canon".to_vec());
+        let buf = OwnedBuffer::new(b"canonical".to_vec());
+        assert_eq!(buf.into_vec(100), b"canonical".to_vec());
+    }
+
+    #[test]
+    fn into_vec_of_nothing_read_is_empty() {
+        // eg. the output of a callback that failed, the buffer is freed on drop
+        let buf = OwnedBuffer::new(vec![0u8; 32]);
+        assert!(!buf.raw().is_empty());
+        assert_eq!(buf.into_vec(0), Vec::<u8>::new());
+    }
+}